@@ -0,0 +1,198 @@
+//! On-disk cache of `ask_files` answers, next to the conversion cache.
+//!
+//! Entries are keyed by the file's content hash, the normalized query, the
+//! map call settings (model, prompt version and template, temperature and
+//! provider order) and the page selection, so an edited file, a different
+//! model or a new prompt never hits a stale answer.
+
+use crate::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::task::spawn_blocking;
+
+/// What an answer was cached for, stored with it so the cache can be inspected.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AskCacheEntry {
+    pub file: String,
+    pub query: String,
+    pub model: String,
+    pub prompt_version: u32,
+    pub pages: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    #[serde(flatten)]
+    entry: AskCacheEntry,
+    result: Value,
+}
+
+/// Lowercased with whitespace collapsed, so trivial rewordings still hit.
+fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+pub fn file_hash(path: &Path) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+/// The settings of a map call that change its answer.
+#[derive(Serialize)]
+pub struct MapKey<'a> {
+    pub model: &'a str,
+    pub prompt_version: u32,
+    pub prompt: &'a str,
+    pub temperature: Option<f64>,
+    pub provider_order: &'a str,
+}
+
+pub fn key(file_hash: &str, query: &str, map: &MapKey, pages: Option<&str>) -> String {
+    let material = json!([file_hash, normalize_query(query), map, pages]);
+    format!("{:x}", Sha256::digest(material.to_string()))
+}
+
+pub struct AskCache {
+    dir: PathBuf,
+}
+
+impl AskCache {
+    pub fn open() -> Result<Self> {
+        let dir = crate::cache_root()?.join("ask_cache").into_std_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        serde_json::from_slice::<Stored>(&bytes)
+            .ok()
+            .map(|stored| stored.result)
+    }
+
+    pub fn put(&self, key: &str, entry: AskCacheEntry, result: Value) -> Result<()> {
+        let path = self.path(key);
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&Stored { entry, result })?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Cached entries, newest first.
+    pub fn list(&self) -> Result<Vec<AskCacheEntry>> {
+        let mut entries: Vec<AskCacheEntry> = fs::read_dir(&self.dir)?
+            .flatten()
+            .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|e| {
+                let bytes = fs::read(e.path()).ok()?;
+                Some(serde_json::from_slice::<Stored>(&bytes).ok()?.entry)
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+        Ok(entries)
+    }
+
+    /// Removes every entry, returning how many there were.
+    pub fn clear(&self) -> Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[tauri::command]
+pub async fn ask_cache_list() -> Result<Vec<AskCacheEntry>> {
+    spawn_blocking(|| AskCache::open()?.list()).await?
+}
+
+#[tauri::command]
+pub async fn ask_cache_clear() -> Result<usize> {
+    spawn_blocking(|| AskCache::open()?.clear()).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AskCache, AskCacheEntry, MapKey, key};
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn cache_round_trip_inspect_and_clear() {
+        let dir = TempDir::new().unwrap();
+        let cache = AskCache {
+            dir: dir.path().to_path_buf(),
+        };
+        let entry = |query: &str, day| AskCacheEntry {
+            file: "a.pdf".to_string(),
+            query: query.to_string(),
+            model: "m".to_string(),
+            prompt_version: 1,
+            pages: None,
+            created_at: Utc.with_ymd_and_hms(2025, 1, day, 0, 0, 0).unwrap(),
+        };
+
+        let map = MapKey {
+            model: "m",
+            prompt_version: 1,
+            prompt: "p",
+            temperature: None,
+            provider_order: "",
+        };
+        let first = key("hash", "What is  X?", &map, None);
+        assert_eq!(first, key("hash", " what is x? ", &map, None));
+        for other in [
+            MapKey { model: "n", ..map },
+            MapKey {
+                prompt_version: 2,
+                ..map
+            },
+            MapKey { prompt: "q", ..map },
+            MapKey {
+                temperature: Some(0.0),
+                ..map
+            },
+            MapKey {
+                provider_order: "groq",
+                ..map
+            },
+        ] {
+            assert_ne!(first, key("hash", "What is X?", &other, None));
+        }
+        assert_ne!(first, key("hash", "What is X?", &map, Some("1-2")));
+        assert_ne!(first, key("other", "What is X?", &map, None));
+
+        assert!(cache.get(&first).is_none());
+        cache
+            .put(&first, entry("What is X?", 1), json!({"answer": "x"}))
+            .unwrap();
+        let second = key("hash", "What is Y?", &map, None);
+        cache
+            .put(&second, entry("What is Y?", 2), json!({"answer": "y"}))
+            .unwrap();
+        assert_eq!(cache.get(&first), Some(json!({"answer": "x"})));
+
+        let queries: Vec<_> = cache.list().unwrap().into_iter().map(|e| e.query).collect();
+        assert_eq!(queries, ["What is Y?", "What is X?"]);
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert!(cache.list().unwrap().is_empty());
+        assert!(cache.get(&first).is_none());
+    }
+}
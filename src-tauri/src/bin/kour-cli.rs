@@ -0,0 +1,3 @@
+fn main() -> std::process::ExitCode {
+    kour_ai_rs_lib::run_cli()
+}
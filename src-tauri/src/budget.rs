@@ -0,0 +1,185 @@
+//! Hard stops on LLM spend.
+//!
+//! `ChatProcessor` runs each request inside `limited`, which keeps track of
+//! every completion the request makes, including those made by its tools.
+//! `reserve` runs before each completion and fails with `Error::Limit` once
+//! the request or the day has used up one of its budgets. The reserved call
+//! counts until it settles with its real usage, so concurrent calls cannot
+//! all slip under the same limit.
+
+use crate::Result;
+use crate::error::Error;
+use crate::settings::{Budget, Settings};
+use crate::usage::{self, Usage};
+use std::cell::Cell;
+
+struct Tracker {
+    request: Budget,
+    daily: Budget,
+    /// Recorded today before this request started, or why it could not be read.
+    earlier_today: std::result::Result<Usage, String>,
+    spent: Cell<Usage>,
+    /// Calls reserved and not settled yet.
+    in_flight: Cell<u64>,
+}
+
+tokio::task_local! {
+    static REQUEST: Tracker;
+}
+
+/// Runs `fut` as one request, under the budgets in `settings`.
+pub async fn limited<F: Future>(settings: &Settings, fut: F) -> F::Output {
+    let tracker = Tracker {
+        request: settings.request_budget,
+        daily: settings.daily_budget,
+        earlier_today: usage::spent_today().map_err(|e| e.to_string()),
+        spent: Cell::default(),
+        in_flight: Cell::default(),
+    };
+    REQUEST.scope(tracker, fut).await
+}
+
+fn within(budget: &Budget, spent: Usage, calls: u64, period: &str) -> Result<()> {
+    let exceeded = |item: &str, requested: f64, limit: f64| {
+        Err(Error::Limit {
+            item: format!("{item} per {period}"),
+            requested,
+            limit,
+        })
+    };
+    if let Some(max) = budget.max_calls
+        && spent.calls + calls > max
+    {
+        return exceeded("LLM calls", (spent.calls + calls) as f64, max as f64);
+    }
+    if let Some(max) = budget.max_tokens
+        && spent.total_tokens >= max
+    {
+        return exceeded("tokens", spent.total_tokens as f64, max as f64);
+    }
+    if let Some(max) = budget.max_cost
+        && spent.cost >= max
+    {
+        return exceeded("USD", (spent.cost * 100.0).round() / 100.0, max);
+    }
+    Ok(())
+}
+
+fn fits(tracker: &Tracker, calls: u64) -> Result<()> {
+    let spent = tracker.spent.get();
+    let calls = calls + tracker.in_flight.get();
+    within(&tracker.request, spent, calls, "request")?;
+    let mut today = match &tracker.earlier_today {
+        Ok(earlier) => *earlier,
+        Err(_) if tracker.daily == Budget::default() => return Ok(()),
+        // A daily budget that cannot be checked is not a reason to spend.
+        Err(e) => {
+            return Err(Error::Conflict(format!(
+                "cannot read today's usage to check the daily budget: {e}"
+            )));
+        }
+    };
+    today += spent;
+    within(&tracker.daily, today, calls, "day")
+}
+
+/// Fails if making `calls` more completions, on top of those in flight, would
+/// break the budget of the current request or of the day. Always passes
+/// outside of `limited`.
+pub fn check(calls: u64) -> Result<()> {
+    REQUEST
+        .try_with(|tracker| fits(tracker, calls))
+        .unwrap_or(Ok(()))
+}
+
+/// One completion counted against the budgets while it runs. Dropping it
+/// without `settle`, e.g. when the call fails, releases it uncharged.
+pub struct Reservation {
+    active: bool,
+}
+
+/// Reserves one completion, failing like `check(1)` would.
+pub fn reserve() -> Result<Reservation> {
+    REQUEST
+        .try_with(|tracker| {
+            fits(tracker, 1)?;
+            tracker.in_flight.set(tracker.in_flight.get() + 1);
+            Ok(Reservation { active: true })
+        })
+        .unwrap_or(Ok(Reservation { active: false }))
+}
+
+impl Reservation {
+    /// Replaces the reservation with the usage the call reported.
+    pub fn settle(self, usage: Usage) {
+        let _ = REQUEST.try_with(|tracker| {
+            let mut spent = tracker.spent.get();
+            spent += usage;
+            tracker.spent.set(spent);
+        });
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.active {
+            let _ = REQUEST.try_with(|tracker| tracker.in_flight.set(tracker.in_flight.get() - 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, limited, reserve};
+    use crate::error::Error;
+    use crate::settings::{Budget, Settings};
+    use crate::test_support::Harness;
+    use crate::usage::Usage;
+
+    #[tokio::test]
+    async fn reservations_count_until_settled() {
+        let _harness = Harness::new().await;
+        let mut settings = Settings::default();
+        settings.request_budget.max_calls = Some(2);
+        limited(&settings, async {
+            let first = reserve().unwrap();
+            let second = reserve().unwrap();
+            // Both calls are in flight, so a third cannot start.
+            assert!(matches!(reserve(), Err(Error::Limit { .. })));
+            assert!(check(1).is_err());
+            drop(second);
+            assert!(check(1).is_ok());
+            first.settle(Usage {
+                calls: 1,
+                ..Usage::default()
+            });
+            let third = reserve().unwrap();
+            assert!(reserve().is_err());
+            drop(third);
+            assert!(check(1).is_ok() && check(2).is_err());
+        })
+        .await;
+        assert!(reserve().is_ok());
+    }
+
+    #[tokio::test]
+    async fn unreadable_daily_usage_blocks_daily_budgets() {
+        let _harness = Harness::new().await;
+        let mut settings = Settings::default();
+        settings.daily_budget.max_calls = Some(10);
+        // Nothing recorded yet counts as zero.
+        limited(&settings, async { assert!(check(1).is_ok()) }).await;
+
+        let usage_dir = crate::get_data_dir().unwrap().join("usage");
+        std::fs::write(usage_dir.join("daily.json"), "{not json").unwrap();
+        limited(&settings, async {
+            assert!(matches!(check(1), Err(Error::Conflict(_))));
+            assert!(reserve().is_err());
+        })
+        .await;
+
+        // Without a daily budget there is nothing to check.
+        settings.daily_budget = Budget::default();
+        limited(&settings, async { assert!(check(1).is_ok()) }).await;
+    }
+}
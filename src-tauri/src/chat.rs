@@ -1,50 +1,91 @@
 use std::sync::{Arc, Mutex};
 
 use crate::{
-    Result,
-    interaction::{History, Source, Target},
+    Result, budget, context,
+    error::Error,
+    interaction::{History, Interaction, Source, Target},
+    llm::{self, LlmBackend},
     openrouter::{ChatOptions, Openrouter, ToolCall},
+    settings::{Settings, get_settings},
     tools,
-    ui_events::UIEvents,
+    ui_events::{EventPayload, EventSink},
+    usage,
+    utils::debug_log,
 };
 use futures::future::join_all;
 
 pub static SYSTEM_PROMPT: &str = include_str!("DEFAULT_PROMPT.md");
 
 pub struct ChatProcessor {
-    ui: UIEvents,
+    ui: Arc<dyn EventSink>,
     options: ChatOptions,
     history: Arc<Mutex<History>>,
+    backend: Arc<dyn LlmBackend>,
 }
 
 impl ChatProcessor {
-    pub fn new(window: tauri::Window, options: ChatOptions, history: Arc<Mutex<History>>) -> Self {
+    pub fn new(
+        ui: Arc<dyn EventSink>,
+        options: ChatOptions,
+        history: Arc<Mutex<History>>,
+        backend: Arc<dyn LlmBackend>,
+    ) -> Self {
         Self {
-            ui: UIEvents::new(window),
+            ui,
             options,
             history,
+            backend,
         }
     }
 
+    /// Runs the agent loop until the model answers without tool calls, within
+    /// the request and daily budgets.
     pub async fn run(&self) -> Result<()> {
+        let settings = get_settings()?;
+        budget::limited(&settings, self.run_loop(&settings)).await
+    }
+
+    async fn run_loop(&self, settings: &Settings) -> Result<()> {
         let _ = self.ui.emit_start();
 
-        loop {
-            let to_llm: Vec<_> = Openrouter::render(&self.history.lock().unwrap());
-            let res = Openrouter::call(
+        for iteration in 1.. {
+            if let Some(max) = settings.max_iterations
+                && iteration > max
+            {
+                return Err(Error::Limit {
+                    item: "agent loop iterations per request".to_string(),
+                    requested: iteration as f64,
+                    limit: max as f64,
+                });
+            }
+            let to_llm: Vec<_> = {
+                let history = self.history.lock().unwrap();
+                match settings.context_budget {
+                    Some(budget) => Openrouter::render(&context::compact(&history, budget)),
+                    None => Openrouter::render(&history),
+                }
+            };
+            // Deltas and the final interaction share an id so the UI can swap the draft out
+            let id = Interaction::create_id();
+            let res = llm::call_streaming(
+                self.backend.as_ref(),
                 &to_llm,
                 &self.options.model_name,
                 SYSTEM_PROMPT,
                 &tools::get_tools(),
-                None,
+                &mut |delta| {
+                    let _ = self.ui.emit_delta(id, &delta);
+                },
             )
             .await?;
 
             let choice = &res.choices[0];
             let incoming_message = choice.message.clone();
-            let interaction = Openrouter::sends(incoming_message.clone());
+            let interaction = Openrouter::sends(incoming_message.clone())
+                .with_id(id)
+                .with_usage(res.usage);
             self.ui.emit_interaction(&interaction)?;
-            self.history.lock().unwrap().push(interaction);
+            self.push(interaction);
 
             if let Some(tool_calls) = incoming_message.tool_calls.clone() {
                 self.handle_tool_calls(tool_calls).await?;
@@ -60,15 +101,291 @@ impl ChatProcessor {
 
     pub async fn handle_tool_calls(&self, tool_calls: Vec<ToolCall>) -> Result<()> {
         let tool_futs = tool_calls.into_iter().map(async |tool_call| {
-            let tool_payload =
-                tools::tool_dispatcher(&tool_call.function.name, &tool_call.function.arguments)
-                    .await;
+            let (tool_payload, tool_usage) = usage::scoped(tools::tool_dispatcher(
+                &tool_call.function.name,
+                &tool_call.function.arguments,
+            ))
+            .await;
 
-            let interaction = tool_payload.finalize(tool_call.id.to_string());
+            let mut interaction = tool_payload.finalize(tool_call.id.to_string());
+            if tool_usage.calls > 0 {
+                interaction = interaction.with_usage(tool_usage);
+            }
             let _ = self.ui.emit_interaction(&interaction);
-            self.history.lock().unwrap().push(interaction);
+            self.push(interaction);
         });
         let _ = join_all(tool_futs).await;
         Ok(())
     }
+
+    /// Appends to the history and reports the interaction's usage, if any.
+    fn push(&self, interaction: Interaction) {
+        let id = interaction.id();
+        let cost = interaction.usage();
+        let conversation = {
+            let mut history = self.history.lock().unwrap();
+            history.push(interaction);
+            history.usage()
+        };
+        if let Some(cost) = cost {
+            let _ = self.ui.emit(EventPayload::Usage {
+                id,
+                usage: cost,
+                conversation,
+            });
+            if let Err(e) = usage::record(cost) {
+                debug_log!("Failed to record usage: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChatProcessor;
+    use crate::error::Error;
+    use crate::interaction::{Content, History, Interaction};
+    use crate::openrouter::ChatOptions;
+    use crate::test_support::{Harness, ScriptedBackend, text, tool_calls};
+    use crate::usage::{self, Usage};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    fn user_says(history: &Arc<Mutex<History>>, msg: &str) {
+        history
+            .lock()
+            .unwrap()
+            .push(Interaction::user_message(vec![Content::Text {
+                text: msg.to_string(),
+            }]));
+    }
+
+    #[tokio::test]
+    async fn run_multi_turn_tool_calls() {
+        let harness = Harness::new().await;
+        harness.write("reports/q1.txt", "Revenue was 42");
+        let usage = Usage {
+            calls: 0,
+            prompt_tokens: 100,
+            completion_tokens: 20,
+            total_tokens: 120,
+            cost: 0.5,
+        };
+        let backend = ScriptedBackend::with_usage(
+            vec![
+                tool_calls(&[
+                    ("call_ls", "ls", json!({"relative_path": "reports"})),
+                    (
+                        "call_load",
+                        "load_file",
+                        json!({"filename": "reports/q1.txt"}),
+                    ),
+                ]),
+                text("Revenue was 42 in Q1."),
+            ],
+            usage,
+        );
+        let history = Arc::new(Mutex::new(History::default()));
+        user_says(&history, "What was Q1 revenue?");
+
+        let proc = ChatProcessor::new(
+            harness.sink.clone(),
+            ChatOptions {
+                model_name: "test/model".to_string(),
+            },
+            Arc::clone(&history),
+            backend.clone(),
+        );
+        proc.run().await.unwrap();
+
+        let history = history.lock().unwrap();
+        assert_eq!(history.inner.len(), 5);
+        assert!(matches!(
+            &history.inner[1],
+            Interaction::LlmResponse { tool_calls: Some(calls), .. } if calls.len() == 2
+        ));
+        let mut results: Vec<_> = history.inner[2..4]
+            .iter()
+            .map(|i| match i {
+                Interaction::ToolResult {
+                    tool_call_id,
+                    response,
+                    for_llm,
+                    ..
+                } => (tool_call_id.as_str(), response.as_str(), for_llm.len()),
+                other => panic!("expected tool result, got {other:?}"),
+            })
+            .collect();
+        results.sort();
+        assert_eq!(
+            results,
+            vec![
+                ("call_load", r#"{"Ok":"file_loaded"}"#, 2),
+                ("call_ls", r#"{"Ok":["q1.txt"]}"#, 0),
+            ]
+        );
+        assert!(matches!(
+            &history.inner[4],
+            Interaction::LlmResponse { content, tool_calls: None, .. }
+                if content == &vec![Content::Text { text: "Revenue was 42 in Q1.".to_string() }]
+        ));
+        // Each completion is counted; tools that made none carry no usage.
+        assert_eq!(history.inner[1].usage(), Some(Usage { calls: 1, ..usage }));
+        assert!(history.inner[2].usage().is_none());
+        assert_eq!(history.usage().calls, 2);
+        assert_eq!(history.usage().total_tokens, 240);
+
+        // The second request carries tool results before the deferred file content.
+        let requests = backend.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["model"], "test/model");
+        assert_eq!(requests[0]["stream"], true);
+        let roles: Vec<_> = requests[1]["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            roles,
+            ["system", "user", "assistant", "tool", "tool", "user"]
+        );
+
+        assert_eq!(
+            harness.event_types(),
+            [
+                "Start",
+                "ToolCallDelta",
+                "ToolCall",
+                "ToolCall",
+                "Usage",
+                "ToolDone",
+                "ToolDone",
+                "MessageDelta",
+                "Message",
+                "Usage",
+                "End"
+            ]
+        );
+        let last = harness.sink.events();
+        let last = last.iter().rfind(|e| e["type"] == "Usage").unwrap();
+        assert_eq!(last["conversation"]["cost"], 1.0);
+        let streamed: String = harness
+            .sink
+            .events()
+            .iter()
+            .filter(|e| e["type"] == "MessageDelta")
+            .map(|e| e["delta"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(streamed, "Revenue was 42 in Q1.");
+    }
+
+    #[tokio::test]
+    async fn run_stops_at_budgets() {
+        let harness = Harness::new().await;
+        let ls = || tool_calls(&[("call_ls", "ls", json!({"relative_path": "."}))]);
+        let run = async |responses, usage| {
+            let history = Arc::new(Mutex::new(History::default()));
+            user_says(&history, "Keep looking");
+            let backend = ScriptedBackend::with_usage(responses, usage);
+            let result = ChatProcessor::new(
+                harness.sink.clone(),
+                ChatOptions {
+                    model_name: "test/model".to_string(),
+                },
+                Arc::clone(&history),
+                backend.clone(),
+            )
+            .run()
+            .await;
+            let calls = backend.requests.lock().unwrap().len();
+            (result, calls)
+        };
+
+        let limited_to = |result: &Result<_, Error>, expected: &str| matches!(result, Err(Error::Limit { item, .. }) if item == expected);
+        let spend = Usage {
+            total_tokens: 100,
+            cost: 0.5,
+            ..Usage::default()
+        };
+
+        harness.settings(|s| s.request_budget.max_calls = Some(2));
+        let (result, calls) = run(vec![ls(), ls(), ls()], Usage::default()).await;
+        assert!(limited_to(&result, "LLM calls per request"), "{result:?}");
+        assert_eq!(calls, 2);
+
+        harness.settings(|s| {
+            s.request_budget.max_calls = None;
+            s.request_budget.max_tokens = Some(150);
+        });
+        let (result, calls) = run(vec![ls(), ls(), ls()], spend).await;
+        assert!(limited_to(&result, "tokens per request"), "{result:?}");
+        assert_eq!(calls, 2);
+
+        harness.settings(|s| {
+            s.request_budget.max_tokens = None;
+            s.request_budget.max_cost = Some(0.75);
+        });
+        let (result, calls) = run(vec![ls(), ls(), ls()], spend).await;
+        assert!(limited_to(&result, "USD per request"), "{result:?}");
+        assert_eq!(calls, 2);
+
+        // Earlier requests today count against the daily budget.
+        let earlier = usage::spent_today().unwrap().calls;
+        assert!(earlier >= 6);
+        harness.settings(|s| {
+            s.request_budget.max_cost = None;
+            s.daily_budget.max_calls = Some(earlier + 1);
+        });
+        let (result, calls) = run(vec![ls(), ls()], Usage::default()).await;
+        assert!(limited_to(&result, "LLM calls per day"), "{result:?}");
+        assert_eq!(calls, 1);
+
+        harness.settings(|s| {
+            s.daily_budget.max_calls = None;
+            s.max_iterations = Some(1);
+        });
+        let (result, calls) = run(vec![ls(), ls()], Usage::default()).await;
+        assert!(
+            limited_to(&result, "agent loop iterations per request"),
+            "{result:?}"
+        );
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn handle_tool_calls_reports_errors_as_results() {
+        let harness = Harness::new().await;
+        let history = Arc::new(Mutex::new(History::default()));
+        let proc = ChatProcessor::new(
+            harness.sink.clone(),
+            ChatOptions {
+                model_name: "test/model".to_string(),
+            },
+            Arc::clone(&history),
+            ScriptedBackend::new(vec![]),
+        );
+        let calls = match tool_calls(&[
+            ("call_1", "no_such_tool", json!({})),
+            ("call_2", "ls", json!({"relative_path": "../.."})),
+        ])
+        .tool_calls
+        {
+            Some(calls) => calls,
+            None => unreachable!(),
+        };
+        proc.handle_tool_calls(calls).await.unwrap();
+
+        let history = history.lock().unwrap();
+        assert_eq!(history.inner.len(), 2);
+        for interaction in &history.inner {
+            match interaction {
+                Interaction::ToolResult { response, .. } => {
+                    assert!(response.starts_with(r#"{"Err":"#), "{response}")
+                }
+                other => panic!("expected tool result, got {other:?}"),
+            }
+        }
+        assert_eq!(harness.event_types(), ["ToolDone", "ToolDone"]);
+    }
 }
@@ -0,0 +1,354 @@
+//! Headless entry point running the agent over a directory without the GUI.
+
+use crate::chat::ChatProcessor;
+use crate::interaction::{Content, History, Interaction};
+use crate::openrouter::ChatOptions;
+use crate::settings::{Backend, SETTINGS_OVERRIDE, Settings};
+use crate::sinks::{JsonLinesSink, NullSink};
+use crate::ui_events::EventSink;
+use crate::utils::VERBOSE;
+use crate::{CACHE_DIR, DATA_DIR, Result, llm};
+use camino::Utf8PathBuf;
+use serde_json::json;
+use std::fs::File;
+use std::io::Read;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "Usage: kour-cli --root <dir> [options] [prompt]
+
+Runs a single Kour-AI query over <dir> and prints the final answer.
+
+Options:
+  -r, --root <dir>            Root directory the agent may read (required)
+  -m, --model <name>          Model name (default: google/gemini-2.5-pro)
+  -p, --prompt-file <path>    Read the prompt from a file, '-' for stdin
+  -f, --format <text|json>    Output format of the answer (default: text)
+  -t, --transcript <path>     Write the full transcript as JSON to <path>
+  -e, --events <path>         Stream chat events as JSON lines to <path>, '-' for stdout
+  -v, --verbose               Log requests, responses and conversions to stderr
+      --backend <name>        openrouter, openai_compatible or ollama
+      --base-url <url>        Base URL for the openai_compatible and ollama backends
+      --api-key <key>         API key (default: $KOUR_API_KEY or $OPENROUTER_API_KEY)
+      --provider-order <list> OpenRouter provider order, comma separated
+      --soffice <path>        LibreOffice fallback for DOCX/PPTX files
+      --pdf-as-text           Send PDFs as extracted text, for models without PDF input
+      --map-model <name>      Model ask_files queries each file with
+      --map-temperature <t>   Sampling temperature for ask_files map calls
+      --cache-dir <dir>       Conversion cache directory (default: system temp dir)
+      --data-dir <dir>        Usage ledger directory (default: data under the cache dir)
+  -h, --help                  Show this help";
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Debug)]
+struct CliArgs {
+    settings: Settings,
+    prompt: String,
+    format: Format,
+    transcript: Option<Utf8PathBuf>,
+    events: Option<Utf8PathBuf>,
+    verbose: bool,
+    cache_dir: Utf8PathBuf,
+    data_dir: Utf8PathBuf,
+}
+
+/// `None` if only the help was asked for.
+fn parse_args(
+    args: impl IntoIterator<Item = String>,
+) -> std::result::Result<Option<CliArgs>, String> {
+    let mut settings = Settings {
+        api_key: std::env::var("KOUR_API_KEY")
+            .or_else(|_| std::env::var("OPENROUTER_API_KEY"))
+            .unwrap_or_default(),
+        ..Settings::default()
+    };
+    let mut prompt = None;
+    let mut prompt_file = None;
+    let mut format = Format::Text;
+    let mut transcript = None;
+    let mut events = None;
+    let mut verbose = false;
+    let mut cache_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir().join("kour-ai-rs"))
+        .map_err(|p| format!("temp dir is not UTF-8: {p:?}"))?;
+    let mut data_dir = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {name}"))
+        };
+        match arg.as_str() {
+            "-r" | "--root" => settings.root_dir = value(&arg)?,
+            "-m" | "--model" => settings.model_name = value(&arg)?,
+            "-p" | "--prompt-file" => prompt_file = Some(value(&arg)?),
+            "-f" | "--format" => {
+                format = match value(&arg)?.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    other => return Err(format!("unknown format: {other}")),
+                }
+            }
+            "-t" | "--transcript" => transcript = Some(Utf8PathBuf::from(value(&arg)?)),
+            "-e" | "--events" => events = Some(Utf8PathBuf::from(value(&arg)?)),
+            "-v" | "--verbose" => verbose = true,
+            "--backend" => {
+                settings.backend = serde_json::from_value(json!(value(&arg)?)).map_err(|_| {
+                    "unknown backend, expected openrouter, openai_compatible or ollama".to_string()
+                })?
+            }
+            "--base-url" => settings.base_url = value(&arg)?,
+            "--api-key" => settings.api_key = value(&arg)?,
+            "--provider-order" => settings.provider_order = value(&arg)?,
+            "--soffice" => settings.soffice_path = value(&arg)?,
+            "--pdf-as-text" => settings.pdf_as_text = true,
+            "--map-model" => settings.map_model_name = value(&arg)?,
+            "--map-temperature" => {
+                settings.map_temperature = Some(
+                    value(&arg)?
+                        .parse()
+                        .map_err(|_| "--map-temperature must be a number".to_string())?,
+                )
+            }
+            "--cache-dir" => cache_dir = Utf8PathBuf::from(value(&arg)?),
+            "--data-dir" => data_dir = Some(Utf8PathBuf::from(value(&arg)?)),
+            "-h" | "--help" => return Ok(None),
+            flag if flag.starts_with('-') && flag != "-" => {
+                return Err(format!("unknown option: {flag}"));
+            }
+            _ if prompt.is_none() => prompt = Some(arg),
+            _ => return Err("only one prompt may be given".to_string()),
+        }
+    }
+
+    if settings.root_dir.is_empty() {
+        return Err("--root is required".to_string());
+    }
+    let prompt = match (prompt, prompt_file) {
+        (Some(_), Some(_)) => return Err("give either a prompt or --prompt-file".to_string()),
+        (Some(prompt), None) if prompt != "-" => prompt,
+        (Some(_), None) => read_prompt("-")?,
+        (None, Some(path)) => read_prompt(&path)?,
+        (None, None) => return Err("no prompt given".to_string()),
+    };
+    if prompt.trim().is_empty() {
+        return Err("prompt is empty".to_string());
+    }
+    if settings.backend == Backend::Openrouter && settings.api_key.is_empty() {
+        return Err("no API key, pass --api-key or set KOUR_API_KEY".to_string());
+    }
+
+    Ok(Some(CliArgs {
+        settings,
+        prompt,
+        format,
+        transcript,
+        events,
+        verbose,
+        data_dir: data_dir.unwrap_or_else(|| cache_dir.join("data")),
+        cache_dir,
+    }))
+}
+
+fn read_prompt(path: &str) -> std::result::Result<String, String> {
+    if path == "-" {
+        let mut prompt = String::new();
+        std::io::stdin()
+            .read_to_string(&mut prompt)
+            .map_err(|e| format!("cannot read prompt from stdin: {e}"))?;
+        Ok(prompt)
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read {path}: {e}"))
+    }
+}
+
+/// Text of the last assistant answer in the history.
+fn final_answer(history: &History) -> String {
+    history
+        .inner
+        .iter()
+        .rev()
+        .find_map(|i| match i {
+            Interaction::LlmResponse {
+                content,
+                tool_calls: None,
+                ..
+            } => Some(
+                content
+                    .iter()
+                    .filter_map(|c| match c {
+                        Content::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+fn tools_used(history: &History) -> Vec<String> {
+    history
+        .inner
+        .iter()
+        .flat_map(|i| match i {
+            Interaction::LlmResponse {
+                tool_calls: Some(calls),
+                ..
+            } => calls.iter().map(|c| c.function.name.clone()).collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+async fn run_query(args: &CliArgs) -> Result<History> {
+    let sink: Arc<dyn EventSink> = match &args.events {
+        Some(path) if path == "-" => Arc::new(JsonLinesSink::new(Box::new(std::io::stdout()))),
+        Some(path) => Arc::new(JsonLinesSink::new(Box::new(File::create(path)?))),
+        None => Arc::new(NullSink),
+    };
+    let history = Arc::new(Mutex::new(History::default()));
+    history
+        .lock()
+        .unwrap() // unwrap: won't try to recover from poisoned lock
+        .push(Interaction::user_message(vec![Content::Text {
+            text: args.prompt.clone(),
+        }]));
+
+    let proc = ChatProcessor::new(
+        sink,
+        ChatOptions {
+            model_name: args.settings.model_name.clone(),
+        },
+        Arc::clone(&history),
+        llm::backend_from_settings(&args.settings),
+    );
+    proc.run().await?;
+    let history = history.lock().unwrap().clone(); // unwrap: won't try to recover from poisoned lock
+    Ok(history)
+}
+
+fn report(args: &CliArgs, history: &History) -> Result<()> {
+    if let Some(path) = &args.transcript {
+        let transcript = json!({
+            "model": args.settings.model_name,
+            "root_dir": args.settings.root_dir,
+            "prompt": args.prompt,
+            "history": history,
+        });
+        std::fs::write(path, serde_json::to_vec_pretty(&transcript)?)?;
+    }
+    let answer = final_answer(history);
+    match args.format {
+        Format::Text => println!("{answer}"),
+        Format::Json => println!(
+            "{}",
+            json!({
+                "answer": answer,
+                "model": args.settings.model_name,
+                "tools_used": tools_used(history),
+                "usage": history.usage(),
+            })
+        ),
+    }
+    Ok(())
+}
+
+/// Parses the command line, runs the query to completion and prints the answer.
+pub fn run_cli() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    VERBOSE.store(args.verbose, Ordering::Relaxed);
+    CACHE_DIR.get_or_init(|| args.cache_dir.clone());
+    DATA_DIR.get_or_init(|| args.data_dir.clone());
+    *SETTINGS_OVERRIDE.write().unwrap() = Some(args.settings.clone()); // unwrap: won't try to recover from poisoned lock
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("cannot start runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime
+        .block_on(run_query(&args))
+        .and_then(|history| report(&args, &history))
+    {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, parse_args};
+    use crate::settings::Backend;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_full_command_line() {
+        let parsed = parse_args(args(&[
+            "--root",
+            "/data",
+            "-m",
+            "llama3",
+            "--backend",
+            "ollama",
+            "-f",
+            "json",
+            "--transcript",
+            "out.json",
+            "--events",
+            "-",
+            "what is in here?",
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(parsed.settings.root_dir, "/data");
+        assert_eq!(parsed.settings.model_name, "llama3");
+        assert_eq!(parsed.settings.backend, Backend::Ollama);
+        assert_eq!(parsed.format, Format::Json);
+        assert_eq!(parsed.transcript.unwrap().as_str(), "out.json");
+        assert_eq!(parsed.events.unwrap().as_str(), "-");
+        assert!(!parsed.verbose);
+        assert_eq!(parsed.data_dir, parsed.cache_dir.join("data"));
+        assert_eq!(parsed.prompt, "what is in here?");
+        assert!(
+            parse_args(args(&["--root", "/data", "--help"]))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        assert!(parse_args(args(&["prompt"])).is_err());
+        assert!(parse_args(args(&["--root", "/data", "--backend", "ollama"])).is_err());
+        assert!(parse_args(args(&["--root", "/data", "--backend", "x", "p"])).is_err());
+        assert!(parse_args(args(&["--root", "/data", "--bogus", "p"])).is_err());
+        assert!(parse_args(args(&["--root", "/data", "--backend", "ollama", "a", "b"])).is_err());
+        assert!(parse_args(args(&["--root"])).is_err());
+    }
+}
@@ -0,0 +1,535 @@
+//! Full-text index over the extracted contents of the files under the root.
+//!
+//! The index is built on the first search for a root, persisted next to the
+//! conversion cache and refreshed from the paths the file watcher reports.
+
+use crate::Result;
+use crate::conversations::root_hash;
+use crate::error::Error;
+use crate::file_handler::{self, FileType, determine_file_type};
+use crate::settings::get_root;
+use camino::{Utf8Path, Utf8PathBuf};
+use ignore::Walk;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::UNIX_EPOCH;
+use tokio::task::spawn_blocking;
+
+/// Bumped whenever tokenization or the stored layout changes.
+const INDEX_VERSION: u32 = 1;
+const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+const SNIPPET_WORDS_BEFORE: usize = 8;
+const SNIPPET_WORDS_AFTER: usize = 24;
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Size and modification time, used to tell whether a file needs reindexing.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stamp {
+    size: u64,
+    modified: u128,
+}
+
+impl Stamp {
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        if !meta.is_file() || meta.len() > MAX_FILE_SIZE {
+            return None;
+        }
+        let modified = meta
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_nanos();
+        Some(Self {
+            size: meta.len(),
+            modified,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Doc {
+    path: String,
+    stamp: Stamp,
+    len: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContentMatch {
+    pub file: String,
+    pub score: f64,
+    pub snippet: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ContentIndex {
+    version: u32,
+    root: String,
+    /// Indexed files by doc id. Removed files leave a hole until compaction.
+    docs: Vec<Option<Doc>>,
+    /// (doc id, term frequency) pairs by term. Entries of holes are skipped.
+    postings: HashMap<String, Vec<(u32, u32)>>,
+    #[serde(skip)]
+    ids: HashMap<String, u32>,
+}
+
+/// Alphanumeric runs of `text` with their byte offsets.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut out = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                out.push((s, &text[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        out.push((s, &text[s..]));
+    }
+    out
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    words(text)
+        .into_iter()
+        .map(|(_, w)| w.to_lowercase())
+        .collect()
+}
+
+fn is_indexable(path: &Path) -> bool {
+    !matches!(
+        determine_file_type(path),
+        FileType::Image(_) | FileType::Unsupported
+    )
+}
+
+fn index_path(root: &Utf8Path) -> Result<Utf8PathBuf> {
+    let dir = crate::cache_root()?.join("content_index");
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.json", root_hash(root))))
+}
+
+impl ContentIndex {
+    fn new(root: String) -> Self {
+        Self {
+            version: INDEX_VERSION,
+            root,
+            docs: vec![],
+            postings: HashMap::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Loads the saved index of `root`, dropping the holes left by removed files.
+    fn load(root: &Utf8Path) -> Option<Self> {
+        let bytes = fs::read(index_path(root).ok()?).ok()?;
+        let saved: Self = serde_json::from_slice(&bytes).ok()?;
+        if saved.version != INDEX_VERSION || saved.root != root.as_str() {
+            return None;
+        }
+        Some(saved.compact())
+    }
+
+    /// Renumbers the docs without holes and drops their postings.
+    fn compact(self) -> Self {
+        let mut index = Self::new(self.root);
+        let mut remap = HashMap::new();
+        for (old_id, doc) in self.docs.into_iter().enumerate() {
+            if let Some(doc) = doc {
+                let id = index.docs.len() as u32;
+                remap.insert(old_id as u32, id);
+                index.ids.insert(doc.path.clone(), id);
+                index.docs.push(Some(doc));
+            }
+        }
+        for (term, postings) in self.postings {
+            let postings: Vec<_> = postings
+                .into_iter()
+                .filter_map(|(id, tf)| Some((*remap.get(&id)?, tf)))
+                .collect();
+            if !postings.is_empty() {
+                index.postings.insert(term, postings);
+            }
+        }
+        index
+    }
+
+    /// Compacts once holes make up most of the docs.
+    fn compact_if_sparse(&mut self) {
+        if self.docs.len() > 2 * self.ids.len() {
+            let index = std::mem::replace(self, Self::new(String::new()));
+            *self = index.compact();
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = index_path(Utf8Path::new(&self.root))?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    fn stamp(&self, path: &str) -> Option<Stamp> {
+        let id = self.ids.get(path)?;
+        self.docs[*id as usize].as_ref().map(|d| d.stamp)
+    }
+
+    /// Leaves a hole; the postings of the doc stay until compaction.
+    fn remove(&mut self, path: &str) -> bool {
+        let Some(id) = self.ids.remove(path) else {
+            return false;
+        };
+        self.docs[id as usize] = None;
+        true
+    }
+
+    fn insert(&mut self, path: String, stamp: Stamp, text: &str) {
+        self.remove(&path);
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        let mut len = 0;
+        for term in tokenize(text) {
+            *counts.entry(term).or_default() += 1;
+            len += 1;
+        }
+        let id = self.docs.len() as u32;
+        for (term, tf) in counts {
+            self.postings.entry(term).or_default().push((id, tf));
+        }
+        self.ids.insert(path.clone(), id);
+        self.docs.push(Some(Doc { path, stamp, len }));
+    }
+
+    /// Extracts and indexes the given files in parallel, skipping unchanged ones.
+    /// Returns whether anything changed.
+    fn index_files(&mut self, files: Vec<(String, PathBuf, Stamp)>) -> bool {
+        let stale: Vec<_> = files
+            .into_iter()
+            .filter(|(rel, _, stamp)| self.stamp(rel) != Some(*stamp))
+            .collect();
+        let extracted: Vec<_> = stale
+            .into_par_iter()
+            .map(|(rel, abs, stamp)| {
+                // Unreadable files are indexed empty so they aren't retried until they change.
+                let text = file_handler::file_text(&abs)
+                    .inspect_err(|e| eprintln!("Cannot index {abs:?}: {e}"))
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default();
+                (rel, stamp, text)
+            })
+            .collect();
+        let changed = !extracted.is_empty();
+        for (rel, stamp, text) in extracted {
+            self.insert(rel, stamp, &text);
+        }
+        changed
+    }
+
+    fn indexable_files(&self, dir: &Path) -> Vec<(String, PathBuf, Stamp)> {
+        Walk::new(dir)
+            .flatten()
+            .filter_map(|e| {
+                let path = e.into_path();
+                if !is_indexable(&path) {
+                    return None;
+                }
+                let stamp = Stamp::of(&path)?;
+                let rel = path
+                    .strip_prefix(&self.root)
+                    .ok()?
+                    .to_string_lossy()
+                    .to_string();
+                Some((rel, path, stamp))
+            })
+            .collect()
+    }
+
+    /// Brings the whole index in line with the files on disk.
+    fn refresh(&mut self) -> bool {
+        let files = self.indexable_files(Path::new(&self.root));
+        let present: HashSet<&str> = files.iter().map(|(rel, _, _)| rel.as_str()).collect();
+        let gone: Vec<String> = self
+            .ids
+            .keys()
+            .filter(|path| !present.contains(path.as_str()))
+            .cloned()
+            .collect();
+        let mut changed = !gone.is_empty();
+        for path in gone {
+            self.remove(&path);
+        }
+        changed |= self.index_files(files);
+        self.compact_if_sparse();
+        changed
+    }
+
+    /// Reindexes paths reported by the file watcher, which may be files or
+    /// whole directories that appeared or disappeared.
+    fn update(&mut self, paths: Vec<PathBuf>) -> bool {
+        let mut changed = false;
+        let mut files = vec![];
+        for path in paths {
+            let Ok(rel) = path.strip_prefix(&self.root) else {
+                continue;
+            };
+            let rel = rel.to_string_lossy().to_string();
+            if path.is_dir() {
+                files.extend(self.indexable_files(&path));
+            } else if let Some(stamp) = Stamp::of(&path)
+                && is_indexable(&path)
+            {
+                files.push((rel, path, stamp));
+            } else {
+                let nested = format!("{rel}{}", std::path::MAIN_SEPARATOR);
+                let gone: Vec<String> = self
+                    .ids
+                    .keys()
+                    .filter(|p| **p == rel || p.starts_with(&nested))
+                    .cloned()
+                    .collect();
+                for path in gone {
+                    changed |= self.remove(&path);
+                }
+            }
+        }
+        changed |= self.index_files(files);
+        self.compact_if_sparse();
+        changed
+    }
+
+    /// Files containing every term of `query`, ranked by BM25.
+    fn search(&self, terms: &HashSet<String>, max_results: usize) -> Vec<(String, f64)> {
+        let doc_count = self.ids.len() as f64;
+        let avg_len = self
+            .docs
+            .iter()
+            .flatten()
+            .map(|d| d.len as f64)
+            .sum::<f64>()
+            / doc_count.max(1.0);
+        let mut scores: HashMap<u32, (usize, f64)> = HashMap::new();
+        for term in terms {
+            let Some(postings) = self.postings.get(term) else {
+                return vec![];
+            };
+            let live: Vec<_> = postings
+                .iter()
+                .filter_map(|(id, tf)| Some((*id, *tf, self.docs[*id as usize].as_ref()?.len)))
+                .collect();
+            let df = live.len() as f64;
+            let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+            for (id, tf, len) in live {
+                let len = len as f64;
+                let tf = tf as f64;
+                let score = idf * tf * (BM25_K1 + 1.0)
+                    / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * len / avg_len.max(1.0)));
+                let entry = scores.entry(id).or_default();
+                entry.0 += 1;
+                entry.1 += score;
+            }
+        }
+        let mut hits: Vec<_> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == terms.len())
+            .filter_map(|(id, (_, score))| {
+                Some((self.docs[id as usize].as_ref()?.path.clone(), score))
+            })
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits.truncate(max_results);
+        hits
+    }
+}
+
+/// The words around the first hit, with every query term in bold.
+fn snippet(text: &str, terms: &HashSet<String>) -> String {
+    let words = words(text);
+    let Some(first) = words
+        .iter()
+        .position(|(_, w)| terms.contains(&w.to_lowercase()))
+    else {
+        return String::new();
+    };
+    let from = first.saturating_sub(SNIPPET_WORDS_BEFORE);
+    let to = (first + SNIPPET_WORDS_AFTER).min(words.len());
+    let mut out = String::new();
+    let mut cursor = match from {
+        0 => 0,
+        _ => {
+            out.push('…');
+            words[from].0
+        }
+    };
+    for (offset, word) in &words[from..to] {
+        out.push_str(&text[cursor..*offset]);
+        if terms.contains(&word.to_lowercase()) {
+            out.push_str(&format!("**{word}**"));
+        } else {
+            out.push_str(word);
+        }
+        cursor = offset + word.len();
+    }
+    if to < words.len() {
+        out.push('…');
+    } else {
+        out.push_str(&text[cursor..]);
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[derive(Default)]
+pub struct ContentIndexState {
+    index: Mutex<Option<ContentIndex>>,
+    dirty: Mutex<HashSet<PathBuf>>,
+}
+
+impl ContentIndexState {
+    /// Queues paths touched on disk for reindexing before the next search.
+    pub fn mark_dirty(&self, paths: &[PathBuf]) {
+        self.dirty.lock().unwrap().extend(paths.iter().cloned()); // unwrap: won't try to recover from poisoned lock
+    }
+
+    pub fn search(&self, query: &str, max_results: usize) -> Result<Vec<ContentMatch>> {
+        let terms: HashSet<String> = tokenize(query).into_iter().collect();
+        if terms.is_empty() {
+            return Err(Error::Tool("query has no searchable words".to_string()));
+        }
+        let root = get_root()?;
+        let hits = {
+            let mut guard = self.index.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+            let changed = match guard.as_mut() {
+                Some(index) if index.root == root.as_str() => {
+                    let dirty: Vec<_> = self.dirty.lock().unwrap().drain().collect(); // unwrap: won't try to recover from poisoned lock
+                    index.update(dirty)
+                }
+                _ => {
+                    self.dirty.lock().unwrap().clear(); // unwrap: won't try to recover from poisoned lock
+                    let index = guard.insert(
+                        ContentIndex::load(&root)
+                            .unwrap_or_else(|| ContentIndex::new(root.to_string())),
+                    );
+                    index.refresh()
+                }
+            };
+            let index = guard.as_ref().ok_or(Error::Other)?;
+            if changed && let Err(e) = index.save() {
+                eprintln!("Cannot save content index: {e}");
+            }
+            index.search(&terms, max_results)
+        };
+        Ok(hits
+            .into_iter()
+            .map(|(file, score)| {
+                let snippet = file_handler::file_text(root.join(&file).as_std_path())
+                    .ok()
+                    .flatten()
+                    .map(|text| snippet(&text, &terms))
+                    .unwrap_or_default();
+                ContentMatch {
+                    file,
+                    score,
+                    snippet,
+                }
+            })
+            .collect())
+    }
+}
+
+pub static CONTENT_INDEX: LazyLock<ContentIndexState> = LazyLock::new(Default::default);
+
+#[tauri::command]
+pub async fn search_content(query: String, max_results: usize) -> Result<Vec<ContentMatch>> {
+    spawn_blocking(move || CONTENT_INDEX.search(&query, max_results)).await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CONTENT_INDEX, ContentIndex, search_content, tokenize};
+    use crate::test_support::Harness;
+    use camino::Utf8Path;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn search_ranks_and_follows_changes() {
+        let harness = Harness::new().await;
+        harness.write("plans/falcon.txt", "Project Falcon launches in May.");
+        harness.write(
+            "notes.md",
+            "Falcon, falcon, falcon! The project is on track.",
+        );
+        harness.write("other.txt", "Nothing to see here.");
+        harness.write("photo.png", "falcon");
+
+        let hits = search_content("project falcon".to_string(), 10)
+            .await
+            .unwrap();
+        let files: Vec<_> = hits.iter().map(|h| h.file.replace('\\', "/")).collect();
+        assert_eq!(files, ["notes.md", "plans/falcon.txt"]);
+        assert_eq!(hits[1].snippet, "**Project** **Falcon** launches in May.");
+
+        std::fs::remove_file(harness.root.path().join("notes.md")).unwrap();
+        harness.write("other.txt", "A falcon project after all.");
+        CONTENT_INDEX.mark_dirty(&[
+            harness.root.path().join("notes.md"),
+            harness.root.path().join("other.txt"),
+        ]);
+        let hits = search_content("falcon".to_string(), 1).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        let files: Vec<_> = search_content("falcon".to_string(), 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|h| h.file.replace('\\', "/"))
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert!(files.contains(&"other.txt".to_string()));
+        assert!(search_content("!!".to_string(), 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn save_load_and_remap_holes() {
+        let harness = Harness::new().await;
+        harness.write("a.txt", "alpha shared");
+        harness.write("b.txt", "beta shared");
+        harness.write("c.txt", "gamma shared");
+        let root = Utf8Path::from_path(harness.root.path()).unwrap();
+        let terms = |q: &str| tokenize(q).into_iter().collect::<HashSet<_>>();
+
+        let mut index = ContentIndex::new(root.to_string());
+        assert!(index.refresh());
+        assert!(!index.refresh());
+        index.save().unwrap();
+        assert!(ContentIndex::load(root).unwrap().stamp("a.txt").is_some());
+
+        // A changed stamp reindexes the file under a new id, leaving a hole.
+        harness.write("b.txt", "beta shared again");
+        std::fs::remove_file(harness.root.path().join("a.txt")).unwrap();
+        assert!(index.refresh());
+        assert_eq!(index.docs.len(), 4);
+        assert_eq!(index.search(&terms("alpha"), 10), []);
+        assert_eq!(index.search(&terms("shared"), 10).len(), 2);
+        index.save().unwrap();
+
+        let loaded = ContentIndex::load(root).unwrap();
+        assert_eq!(loaded.docs.len(), 2);
+        assert_eq!(loaded.stamp("b.txt"), index.stamp("b.txt"));
+        assert!(loaded.stamp("a.txt").is_none());
+        let hits = loaded.search(&terms("beta again"), 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0, "b.txt");
+        assert_eq!(loaded.search(&terms("gamma"), 10)[0].0, "c.txt");
+        assert!(!loaded.postings.contains_key("alpha"));
+        assert!(ContentIndex::load(Utf8Path::new("/elsewhere")).is_none());
+    }
+}
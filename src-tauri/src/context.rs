@@ -0,0 +1,219 @@
+//! Keeps what is sent to the model within a token budget.
+//!
+//! The history itself is left alone, so the UI transcript and saved
+//! conversations stay complete. `compact` works on a copy, replacing the
+//! bulkiest parts of earlier turns with short notes, oldest first, until the
+//! estimate fits: loaded files and attachments go first, then files loaded
+//! earlier in the current turn, then long tool results of earlier turns. The
+//! user message of the current turn and the tool results answering the
+//! latest model response are kept whole.
+
+use crate::file_handler::estimate_tokens;
+use crate::interaction::{Content, History, Interaction};
+use crate::utils::debug_log;
+use std::borrow::Cow;
+
+/// Parts this small are not worth evicting.
+const KEEP_TOKENS: usize = 200;
+/// How much of a shortened tool result is kept.
+const RESPONSE_HEAD_CHARS: usize = 400;
+
+fn note(content: &Content) -> Content {
+    let what = match content {
+        Content::File { file } => format!("The file {}", file.filename),
+        Content::ImageUrl { .. } => "An image".to_string(),
+        Content::Text { .. } => "Loaded content".to_string(),
+    };
+    Content::Text {
+        text: format!(
+            "[{what} was removed from the context to save space. Load it again if it is still needed.]"
+        ),
+    }
+}
+
+/// Replaces the large parts of `content` that `evict` selects, returning the tokens saved.
+fn evict_parts(content: &mut [Content], evict: impl Fn(&Content) -> bool) -> usize {
+    let mut saved = 0;
+    for part in content.iter_mut() {
+        let tokens = part.estimate_tokens();
+        if tokens > KEEP_TOKENS && evict(part) {
+            *part = note(part);
+            saved += tokens.saturating_sub(part.estimate_tokens());
+        }
+    }
+    saved
+}
+
+fn shorten_response(response: &mut String) -> usize {
+    let tokens = estimate_tokens(response);
+    if tokens <= KEEP_TOKENS {
+        return 0;
+    }
+    let head: String = response.chars().take(RESPONSE_HEAD_CHARS).collect();
+    *response = format!(
+        "{head}… [The rest of this result was removed from the context to save space. Run the tool again if it is still needed.]"
+    );
+    tokens.saturating_sub(estimate_tokens(response))
+}
+
+/// `history` as is if it fits in `budget` tokens, otherwise a copy
+/// compacted as far as evicting can get it there.
+pub fn compact(history: &History, budget: usize) -> Cow<'_, History> {
+    let before = history.estimate_tokens();
+    if before <= budget {
+        return Cow::Borrowed(history);
+    }
+    let mut out = history.clone();
+    let current_turn = out
+        .inner
+        .iter()
+        .rposition(|i| matches!(i, Interaction::UserMessage { .. }))
+        .unwrap_or(0);
+    // Tool results after the latest model response are what it asked for last.
+    let latest = out.inner[current_turn..]
+        .iter()
+        .rposition(|i| matches!(i, Interaction::LlmResponse { .. }))
+        .map_or(out.inner.len(), |i| current_turn + i);
+    let (earlier, current) = out.inner.split_at_mut(current_turn);
+    let older_in_turn = &mut current[..latest - current_turn];
+
+    let mut total = before;
+    for interaction in earlier.iter_mut() {
+        if total <= budget {
+            break;
+        }
+        total -= match interaction {
+            Interaction::ToolResult { for_llm, .. } => evict_parts(for_llm, |_| true),
+            // The user's own words stay, only attachments go
+            Interaction::UserMessage { content, .. } => {
+                evict_parts(content, |c| !matches!(c, Content::Text { .. }))
+            }
+            Interaction::LlmResponse { .. } => 0,
+        };
+    }
+    for interaction in older_in_turn.iter_mut() {
+        if total <= budget {
+            break;
+        }
+        if let Interaction::ToolResult { for_llm, .. } = interaction {
+            total -= evict_parts(for_llm, |_| true);
+        }
+    }
+    for interaction in earlier.iter_mut() {
+        if total <= budget {
+            break;
+        }
+        if let Interaction::ToolResult { response, .. } = interaction {
+            total -= shorten_response(response);
+        }
+    }
+    debug_log!("Compacted context from about {before} to {total} tokens");
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compact;
+    use crate::interaction::{Content, FileData, History, Interaction};
+    use std::borrow::Cow;
+
+    fn words(text: &str) -> Content {
+        Content::Text {
+            text: text.to_string(),
+        }
+    }
+
+    fn pdf(name: &str) -> Content {
+        Content::File {
+            file: FileData {
+                filename: name.to_string(),
+                file_data: "A".repeat(100_000),
+            },
+        }
+    }
+
+    #[test]
+    fn compacts_earlier_turns_oldest_first() {
+        let history = History {
+            inner: vec![
+                Interaction::user_message(vec![pdf("old.pdf"), words("Summarize")]),
+                Interaction::tool_result("a".to_string(), "x".repeat(8_000), vec![], vec![]),
+                Interaction::tool_result(
+                    "b".to_string(),
+                    "ok".to_string(),
+                    vec![pdf("loaded.pdf")],
+                    vec![],
+                ),
+                Interaction::llm_response(vec![words("Done")], None),
+                Interaction::user_message(vec![pdf("new.pdf")]),
+                Interaction::tool_result("c".to_string(), "y".repeat(8_000), vec![], vec![]),
+            ],
+            ..Default::default()
+        };
+        let size = history.estimate_tokens();
+
+        assert_eq!(compact(&history, size).estimate_tokens(), size);
+
+        // Evicting the first attachment is enough.
+        let compacted = compact(&history, size - 4_000);
+        assert!(matches!(
+            &compacted.inner[0],
+            Interaction::UserMessage { content, .. }
+                if matches!(&content[0], Content::Text { text } if text.contains("old.pdf"))
+                    && content[1] == words("Summarize")
+        ));
+        assert!(
+            matches!(&compacted.inner[2], Interaction::ToolResult { for_llm, .. } if for_llm == &[pdf("loaded.pdf")])
+        );
+
+        // Then files, then tool results; the current turn is never touched.
+        let compacted = compact(&history, 0);
+        assert!(
+            matches!(&compacted.inner[1], Interaction::ToolResult { response, .. } if response.len() < 1_000)
+        );
+        assert!(
+            matches!(&compacted.inner[2], Interaction::ToolResult { for_llm, .. } if for_llm != &[pdf("loaded.pdf")])
+        );
+        assert_eq!(
+            serde_json::to_value(&compacted.inner[4..]).unwrap(),
+            serde_json::to_value(&history.inner[4..]).unwrap()
+        );
+        assert!(
+            matches!(&history.inner[2], Interaction::ToolResult { for_llm, .. } if for_llm == &[pdf("loaded.pdf")])
+        );
+    }
+
+    #[test]
+    fn evicts_files_loaded_earlier_in_the_current_turn() {
+        let history = History {
+            inner: vec![
+                Interaction::user_message(vec![words("Compare them")]),
+                Interaction::llm_response(vec![], None),
+                Interaction::tool_result(
+                    "a".to_string(),
+                    "ok".to_string(),
+                    vec![pdf("a.pdf")],
+                    vec![],
+                ),
+                Interaction::llm_response(vec![], None),
+                Interaction::tool_result(
+                    "b".to_string(),
+                    "ok".to_string(),
+                    vec![pdf("b.pdf")],
+                    vec![],
+                ),
+            ],
+            ..Default::default()
+        };
+        let size = history.estimate_tokens();
+        assert!(matches!(compact(&history, size), Cow::Borrowed(_)));
+
+        let compacted = compact(&history, 0);
+        assert!(
+            matches!(&compacted.inner[2], Interaction::ToolResult { for_llm, .. } if for_llm != &[pdf("a.pdf")])
+        );
+        assert!(
+            matches!(&compacted.inner[4], Interaction::ToolResult { for_llm, .. } if for_llm == &[pdf("b.pdf")])
+        );
+    }
+}
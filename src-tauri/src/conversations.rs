@@ -0,0 +1,235 @@
+use crate::Result;
+use crate::error::Error;
+use crate::interaction::{Content, History, Interaction};
+use crate::settings::get_root;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+
+const TITLE_MAX_CHARS: usize = 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    pub root_dir: String,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub history: History,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub message_count: usize,
+}
+
+/// The conversation currently loaded in the app, with the root it belongs to.
+#[derive(Debug, Clone)]
+pub struct OpenConversation {
+    pub id: String,
+    pub root_dir: Utf8PathBuf,
+}
+
+/// Short stable name for a root directory, used to key per-root data files.
+pub(crate) fn root_hash(root_dir: &Utf8Path) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(root_dir.as_str().as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+fn get_conversations_dir(root_dir: &Utf8Path) -> Result<Utf8PathBuf> {
+    let dir = crate::get_data_dir()?
+        .join("conversations")
+        .join(root_hash(root_dir));
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn get_conversation_path(root_dir: &Utf8Path, id: &str) -> Result<Utf8PathBuf> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(Error::Io(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "invalid conversation id",
+        )));
+    }
+    Ok(get_conversations_dir(root_dir)?.join(format!("{id}.json")))
+}
+
+pub(crate) fn title_from_history(history: &History) -> String {
+    let first_text = history.inner.iter().find_map(|i| match i {
+        Interaction::UserMessage { content, .. } => content.iter().find_map(|c| match c {
+            Content::Text { text } if !text.trim().is_empty() => Some(text.trim()),
+            _ => None,
+        }),
+        _ => None,
+    });
+    match first_text {
+        Some(text) => {
+            let line = text.lines().next().unwrap_or_default();
+            let mut title: String = line.chars().take(TITLE_MAX_CHARS).collect();
+            if line.chars().count() > TITLE_MAX_CHARS {
+                title.push('…');
+            }
+            title
+        }
+        None => "Untitled".to_string(),
+    }
+}
+
+fn write(root_dir: &Utf8Path, conversation: &Conversation) -> Result<()> {
+    let path = get_conversation_path(root_dir, &conversation.id)?;
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(conversation)?)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+pub fn new_id() -> String {
+    format!(
+        "{}-{:08x}",
+        Local::now().format("%Y%m%d%H%M%S"),
+        rand::random::<u32>()
+    )
+}
+
+pub fn load(root_dir: &Utf8Path, id: &str) -> Result<Conversation> {
+    let path = get_conversation_path(root_dir, id)?;
+    let conversation: Conversation = serde_json::from_slice(&fs::read(path)?)?;
+    conversation.history.reserve_ids();
+    Ok(conversation)
+}
+
+/// Writes `history` to the conversation `id`, creating it if needed.
+pub fn save(root_dir: &Utf8Path, id: &str, history: &History) -> Result<()> {
+    let now = Local::now();
+    let conversation = match load(root_dir, id) {
+        Ok(existing) => Conversation {
+            updated_at: now,
+            history: history.clone(),
+            ..existing
+        },
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Conversation {
+            id: id.to_string(),
+            title: title_from_history(history),
+            root_dir: root_dir.to_string(),
+            created_at: now,
+            updated_at: now,
+            history: history.clone(),
+        },
+        // Don't clobber a file that is there but can't be read right now
+        Err(e) => return Err(e),
+    };
+    write(root_dir, &conversation)
+}
+
+pub fn delete(root_dir: &Utf8Path, id: &str) -> Result<()> {
+    fs::remove_file(get_conversation_path(root_dir, id)?)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn list_conversations() -> Result<Vec<ConversationSummary>> {
+    let root_dir = get_root()?;
+    let mut out = vec![];
+    for entry in fs::read_dir(get_conversations_dir(&root_dir)?)?.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let Ok(conversation) = fs::read(&path)
+            .map_err(Error::from)
+            .and_then(|b| serde_json::from_slice::<Conversation>(&b).map_err(Error::from))
+        else {
+            eprintln!("Skipping unreadable conversation {path:?}");
+            continue;
+        };
+        out.push(ConversationSummary {
+            message_count: conversation
+                .history
+                .inner
+                .iter()
+                .filter(|i| !matches!(i, Interaction::ToolResult { .. }))
+                .count(),
+            id: conversation.id,
+            title: conversation.title,
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+        });
+    }
+    out.sort_by_key(|c| std::cmp::Reverse(c.updated_at));
+    Ok(out)
+}
+
+pub fn rename(root_dir: &Utf8Path, id: &str, title: &str) -> Result<()> {
+    let mut conversation = load(root_dir, id)?;
+    conversation.title = title.to_string();
+    write(root_dir, &conversation)
+}
+
+#[tauri::command]
+pub fn rename_conversation(id: String, title: String) -> Result<()> {
+    rename(&get_root()?, &id, &title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        delete, get_conversation_path, list_conversations, load, new_id, rename_conversation, save,
+    };
+    use crate::interaction::{Content, History, Interaction};
+    use crate::settings::get_root;
+    use crate::test_support::Harness;
+
+    fn history(texts: &[&str]) -> History {
+        let mut history = History::default();
+        for text in texts {
+            history.push(Interaction::user_message(vec![Content::Text {
+                text: text.to_string(),
+            }]));
+        }
+        history
+    }
+
+    #[tokio::test]
+    async fn save_load_list_rename_and_delete() {
+        let _harness = Harness::new().await;
+        let root = get_root().unwrap();
+        let id = new_id();
+
+        save(
+            &root,
+            &id,
+            &history(&["What changed in Q2?\nDetails follow"]),
+        )
+        .unwrap();
+        let first = load(&root, &id).unwrap();
+        assert_eq!(first.title, "What changed in Q2?");
+        save(&root, &id, &history(&["What changed in Q2?", "And Q3?"])).unwrap();
+        let second = load(&root, &id).unwrap();
+        assert_eq!(second.created_at, first.created_at);
+        assert_eq!(second.history.inner.len(), 2);
+
+        rename_conversation(id.clone(), "Quarterly review".to_string()).unwrap();
+        let listed = list_conversations().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].title, "Quarterly review");
+        assert_eq!(listed[0].message_count, 2);
+
+        // An unreadable file is reported, not overwritten.
+        let path = get_conversation_path(&root, &id).unwrap();
+        std::fs::write(&path, "{").unwrap();
+        assert!(save(&root, &id, &history(&["Other"])).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{");
+        assert!(list_conversations().unwrap().is_empty());
+
+        delete(&root, &id).unwrap();
+        assert!(load(&root, &id).is_err());
+        assert!(load(&root, "../escape").is_err());
+    }
+}
@@ -24,13 +24,17 @@ pub enum Error {
     Conflict(String),
     #[error("Anyhow Error: {0}")]
     Anyhow(#[from] anyhow::Error),
+    #[error("API error {status}: {message}")]
+    Api { status: u16, message: String },
+    #[error("Invalid transcript: {0}")]
+    Transcript(String),
     #[error("Unkonwn Error")]
     Other,
     #[error("Limit exceeded. Requested {requested} of {item}. Limited to {limit}")]
     Limit {
         item: String,
-        requested: usize,
-        limit: usize,
+        requested: f64,
+        limit: f64,
     },
 }
 
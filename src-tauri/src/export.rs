@@ -0,0 +1,525 @@
+//! Conversation exports: Markdown and HTML reports to share findings, and a
+//! lossless JSON transcript that can be imported again.
+
+use crate::Result;
+use crate::conversations::Conversation;
+use crate::error::Error;
+use crate::interaction::{Branch, Content, History, Interaction, Target};
+use crate::openrouter::ToolCall;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// Marks a JSON file as a transcript, so other JSON is rejected on import.
+pub const TRANSCRIPT_FORMAT: &str = "kour-ai-transcript";
+pub const TRANSCRIPT_VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+/// Files a tool call names in its `filename` or `filenames` argument.
+fn cited_files(tool_call: &ToolCall) -> Vec<String> {
+    let Ok(args) = serde_json::from_str::<Value>(&tool_call.function.arguments) else {
+        return vec![];
+    };
+    let mut files: Vec<String> = args["filename"]
+        .as_str()
+        .map(String::from)
+        .into_iter()
+        .collect();
+    if let Some(names) = args["filenames"].as_array() {
+        files.extend(names.iter().filter_map(|n| n.as_str().map(String::from)));
+    }
+    files
+}
+
+/// Every file attached to or cited in the active branch, sorted.
+fn sources(history: &History) -> BTreeSet<String> {
+    let mut out = BTreeSet::new();
+    for interaction in &history.inner {
+        match interaction {
+            Interaction::UserMessage { content, .. } => {
+                out.extend(content.iter().filter_map(|c| match c {
+                    Content::File { file } => Some(file.filename.clone()),
+                    _ => None,
+                }));
+            }
+            Interaction::LlmResponse { tool_calls, .. } => {
+                out.extend(tool_calls.iter().flatten().flat_map(cited_files));
+            }
+            Interaction::ToolResult { .. } => {}
+        }
+    }
+    out
+}
+
+fn pretty_args(arguments: &str) -> String {
+    serde_json::from_str::<Value>(arguments)
+        .and_then(|v| serde_json::to_string_pretty(&v))
+        .unwrap_or_else(|_| arguments.to_string())
+}
+
+/// A fenced code block whose fence is longer than any backtick run in `text`.
+fn fenced(text: &str, lang: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{lang}\n{text}\n{fence}")
+}
+
+pub struct Markdown;
+
+impl Markdown {
+    pub fn report(title: &str, history: &History) -> String {
+        let mut out = vec![format!("# {title}")];
+        out.extend(Markdown::render(history));
+        let sources = sources(history);
+        if !sources.is_empty() {
+            let list: Vec<String> = sources.iter().map(|f| format!("- {f}")).collect();
+            out.push(format!("## Files\n\n{}", list.join("\n")));
+        }
+        out.join("\n\n") + "\n"
+    }
+
+    fn content(content: &[Content]) -> String {
+        content
+            .iter()
+            .map(|c| match c {
+                Content::Text { text } => text.clone(),
+                Content::ImageUrl { image_url } => format!("![image]({})", image_url.url),
+                Content::File { file } => format!("*Attached: {}*", file.filename),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn details(summary: &str, body: &str) -> String {
+        format!("<details>\n<summary>{summary}</summary>\n\n{body}\n\n</details>")
+    }
+}
+
+impl<'a> Target<'a> for Markdown {
+    type RenderType = String;
+    fn convert(interaction: &Interaction) -> Vec<String> {
+        match interaction {
+            Interaction::UserMessage { content, .. } => {
+                vec![format!("## User\n\n{}", Markdown::content(content))]
+            }
+            Interaction::LlmResponse {
+                content,
+                tool_calls,
+                ..
+            } => {
+                let mut out = vec![];
+                if content.iter().any(|c| !c.is_empty()) {
+                    out.push(format!("## Assistant\n\n{}", Markdown::content(content)));
+                }
+                out.extend(tool_calls.iter().flatten().map(|t| {
+                    let files = cited_files(t);
+                    let mut summary = format!("Tool call: {}", t.function.name);
+                    if !files.is_empty() {
+                        summary.push_str(&format!(" ({})", files.join(", ")));
+                    }
+                    Markdown::details(
+                        &summary,
+                        &fenced(&pretty_args(&t.function.arguments), "json"),
+                    )
+                }));
+                out
+            }
+            Interaction::ToolResult {
+                response, for_user, ..
+            } => {
+                let mut out = vec![Markdown::details("Tool result", &fenced(response, ""))];
+                if !for_user.is_empty() {
+                    out.push(Markdown::content(for_user));
+                }
+                out
+            }
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;max-width:50em;margin:2em auto;padding:0 1em;line-height:1.5}\
+.turn{margin:1em 0;padding:.5em 1em;border-radius:6px;white-space:pre-wrap}\
+.user{background:#e8f0fe}.assistant{background:#f4f4f4}\
+details{margin:.5em 0;padding:.25em 1em;border-left:3px solid #ccc}\
+summary{cursor:pointer;color:#555}pre{white-space:pre-wrap;overflow-wrap:anywhere}\
+img{max-width:100%}";
+
+pub struct Html;
+
+impl Html {
+    pub fn report(title: &str, history: &History) -> String {
+        let mut body = vec![format!("<h1>{}</h1>", escape(title))];
+        body.extend(Html::render(history));
+        let sources = sources(history);
+        if !sources.is_empty() {
+            let items: String = sources
+                .iter()
+                .map(|f| format!("<li>{}</li>", escape(f)))
+                .collect();
+            body.push(format!("<h2>Files</h2><ul>{items}</ul>"));
+        }
+        format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title><style>{HTML_STYLE}</style></head>\n<body>\n{}\n</body></html>\n",
+            escape(title),
+            body.join("\n")
+        )
+    }
+
+    fn content(content: &[Content]) -> String {
+        content
+            .iter()
+            .map(|c| match c {
+                Content::Text { text } => escape(text),
+                Content::ImageUrl { image_url } => {
+                    format!("<img src=\"{}\" alt=\"image\">", escape(&image_url.url))
+                }
+                Content::File { file } => format!("<em>Attached: {}</em>", escape(&file.filename)),
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn details(summary: &str, body: &str) -> String {
+        format!(
+            "<details><summary>{}</summary><pre>{}</pre></details>",
+            escape(summary),
+            escape(body)
+        )
+    }
+}
+
+impl<'a> Target<'a> for Html {
+    type RenderType = String;
+    fn convert(interaction: &Interaction) -> Vec<String> {
+        match interaction {
+            Interaction::UserMessage { content, .. } => vec![format!(
+                "<div class=\"turn user\">{}</div>",
+                Html::content(content)
+            )],
+            Interaction::LlmResponse {
+                content,
+                tool_calls,
+                ..
+            } => {
+                let mut out = vec![];
+                if content.iter().any(|c| !c.is_empty()) {
+                    out.push(format!(
+                        "<div class=\"turn assistant\">{}</div>",
+                        Html::content(content)
+                    ));
+                }
+                out.extend(tool_calls.iter().flatten().map(|t| {
+                    let files = cited_files(t);
+                    let mut summary = format!("Tool call: {}", t.function.name);
+                    if !files.is_empty() {
+                        summary.push_str(&format!(" ({})", files.join(", ")));
+                    }
+                    Html::details(&summary, &pretty_args(&t.function.arguments))
+                }));
+                out
+            }
+            Interaction::ToolResult {
+                response, for_user, ..
+            } => {
+                let mut out = vec![Html::details("Tool result", response)];
+                if !for_user.is_empty() {
+                    out.push(format!(
+                        "<div class=\"turn assistant\">{}</div>",
+                        Html::content(for_user)
+                    ));
+                }
+                out
+            }
+        }
+    }
+}
+
+/// Lossless copy of a conversation, branches included.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transcript {
+    pub format: String,
+    pub version: u32,
+    pub title: String,
+    pub exported_at: DateTime<Local>,
+    pub interactions: Vec<Interaction>,
+    #[serde(default)]
+    pub branches: Vec<Branch>,
+}
+
+impl<'a> Target<'a> for Transcript {
+    type RenderType = Interaction;
+    fn convert(interaction: &Interaction) -> Vec<Interaction> {
+        vec![interaction.clone()]
+    }
+}
+
+impl Transcript {
+    pub fn new(title: &str, history: &History) -> Self {
+        Transcript {
+            format: TRANSCRIPT_FORMAT.to_string(),
+            version: TRANSCRIPT_VERSION,
+            title: title.to_string(),
+            exported_at: Local::now(),
+            interactions: Transcript::render(history),
+            branches: history.branches.clone(),
+        }
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::Transcript(reason)
+}
+
+/// Fails unless every tool result directly follows the call it answers and
+/// every call is answered before the conversation goes on. Calls left
+/// unanswered at the end, e.g. by a cancelled request, are dropped.
+fn check_pairing(interactions: Vec<Interaction>) -> Result<Vec<Interaction>> {
+    let mut pending: Vec<&str> = vec![];
+    for interaction in &interactions {
+        match interaction {
+            Interaction::ToolResult { tool_call_id, .. } => {
+                let Some(index) = pending.iter().position(|id| id == tool_call_id) else {
+                    return Err(invalid(format!(
+                        "tool result {tool_call_id} does not follow its tool call"
+                    )));
+                };
+                pending.swap_remove(index);
+            }
+            _ if !pending.is_empty() => {
+                return Err(invalid(format!(
+                    "tool calls {} have no result",
+                    pending.join(", ")
+                )));
+            }
+            Interaction::LlmResponse {
+                tool_calls: Some(calls),
+                ..
+            } => pending = calls.iter().map(|c| c.id.as_str()).collect(),
+            _ => {}
+        }
+    }
+    let mut history = History {
+        inner: interactions,
+        ..Default::default()
+    };
+    history.clean_unfinished_tool_calls();
+    Ok(history.inner)
+}
+
+/// Gives every interaction a fresh id, in the order of the old ones, so
+/// they don't clash with this session's and still increase along each branch.
+fn remap_ids(history: History) -> Result<History> {
+    let mut old: Vec<usize> = history
+        .inner
+        .iter()
+        .chain(history.branches.iter().flat_map(|b| &b.interactions))
+        .map(Interaction::id)
+        .collect();
+    old.sort();
+    if let Some(pair) = old.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(invalid(format!("interaction id {} is used twice", pair[0])));
+    }
+    let ids: HashMap<usize, usize> = old
+        .into_iter()
+        .map(|id| (id, Interaction::create_id()))
+        .collect();
+    let remap = |interactions: Vec<Interaction>| -> Vec<Interaction> {
+        interactions
+            .into_iter()
+            .map(|i| {
+                let id = ids[&i.id()];
+                i.with_id(id)
+            })
+            .collect()
+    };
+    let mut branches = vec![];
+    for branch in history.branches {
+        let parent = match branch.parent {
+            Some(parent) => Some(*ids.get(&parent).ok_or_else(|| {
+                invalid(format!("a branch follows unknown interaction {parent}"))
+            })?),
+            None => None,
+        };
+        branches.push(Branch {
+            parent,
+            interactions: remap(branch.interactions),
+        });
+    }
+    Ok(History {
+        inner: remap(history.inner),
+        branches,
+    })
+}
+
+/// Reads a transcript, or a conversation file saved by the app, into a title
+/// and a history that can be chatted on in this session.
+pub fn import(json: &[u8]) -> Result<(String, History)> {
+    let value: Value = serde_json::from_slice(json)?;
+    let (title, history) = if value["format"] == TRANSCRIPT_FORMAT {
+        let transcript: Transcript = serde_json::from_value(value)?;
+        if transcript.version > TRANSCRIPT_VERSION {
+            return Err(invalid(format!(
+                "version {} is newer than this app supports",
+                transcript.version
+            )));
+        }
+        let history = History {
+            inner: transcript.interactions,
+            branches: transcript.branches,
+        };
+        (transcript.title, history)
+    } else if value.get("history").is_some() {
+        let conversation: Conversation = serde_json::from_value(value)?;
+        (conversation.title, conversation.history)
+    } else {
+        return Err(invalid(
+            "not a transcript or saved conversation".to_string(),
+        ));
+    };
+    if history.inner.is_empty() {
+        return Err(invalid("there are no messages".to_string()));
+    }
+    let mut branches = vec![];
+    for branch in history.branches {
+        branches.push(Branch {
+            parent: branch.parent,
+            interactions: check_pairing(branch.interactions)?,
+        });
+    }
+    let history = History {
+        inner: check_pairing(history.inner)?,
+        branches,
+    };
+    Ok((title, remap_ids(history)?))
+}
+
+pub fn export(format: ExportFormat, title: &str, history: &History) -> Result<String> {
+    Ok(match format {
+        ExportFormat::Markdown => Markdown::report(title, history),
+        ExportFormat::Html => Html::report(title, history),
+        ExportFormat::Json => serde_json::to_string_pretty(&Transcript::new(title, history))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExportFormat, Transcript, export, import};
+    use crate::interaction::{Content, FileData, History, Interaction};
+    use crate::openrouter::{FunctionCall, ToolCall};
+
+    #[test]
+    fn exports_reports_and_lossless_transcripts() {
+        let text = |t: &str| Content::Text {
+            text: t.to_string(),
+        };
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            r#type: "function".to_string(),
+            function: FunctionCall {
+                name: "ask_files".to_string(),
+                arguments: r#"{"query":"q","filenames":["a.pdf","b.txt"]}"#.to_string(),
+            },
+        };
+        let mut history = History::default();
+        history.push(Interaction::user_message(vec![
+            text("What <changed>?"),
+            Content::File {
+                file: FileData {
+                    filename: "c.pdf".to_string(),
+                    file_data: "data".to_string(),
+                },
+            },
+        ]));
+        history.push(Interaction::llm_response(vec![], Some(vec![call])));
+        history.push(Interaction::tool_result(
+            "call_1".to_string(),
+            "```x```".to_string(),
+            vec![],
+            vec![],
+        ));
+        history.push(Interaction::llm_response(
+            vec![text("Prices went up.")],
+            None,
+        ));
+
+        let markdown = export(ExportFormat::Markdown, "Report", &history).unwrap();
+        assert!(
+            markdown.starts_with("# Report\n\n## User\n\nWhat <changed>?\n\n*Attached: c.pdf*")
+        );
+        assert!(markdown.contains("<summary>Tool call: ask_files (a.pdf, b.txt)</summary>"));
+        assert!(markdown.contains("````\n```x```\n````"));
+        assert!(markdown.contains("## Assistant\n\nPrices went up."));
+        assert!(markdown.ends_with("## Files\n\n- a.pdf\n- b.txt\n- c.pdf\n"));
+
+        let html = export(ExportFormat::Html, "Report", &history).unwrap();
+        assert!(html.contains("<div class=\"turn user\">What &lt;changed&gt;?"));
+        assert!(
+            html.contains("<details><summary>Tool result</summary><pre>```x```</pre></details>")
+        );
+
+        history.fork(history.inner[3].id());
+        history.push(Interaction::llm_response(vec![text("Retry")], None));
+        let json = export(ExportFormat::Json, "Report", &history).unwrap();
+        let transcript: Transcript = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            serde_json::to_value(&transcript.interactions).unwrap(),
+            serde_json::to_value(&history.inner).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&transcript.branches).unwrap(),
+            serde_json::to_value(&history.branches).unwrap()
+        );
+    }
+
+    #[test]
+    fn imports_transcripts_with_fresh_ids_and_checked_tool_calls() {
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: FunctionCall {
+                name: "load_file".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let result =
+            |id: &str| Interaction::tool_result(id.to_string(), "ok".to_string(), vec![], vec![]);
+        let mut history = History::default();
+        history.push(Interaction::user_message(vec![]));
+        history.push(Interaction::llm_response(vec![], Some(vec![call("a")])));
+        history.push(result("a"));
+        history.push(Interaction::llm_response(vec![], Some(vec![call("b")])));
+        let json = export(ExportFormat::Json, "Handover", &history).unwrap();
+
+        let (title, imported) = import(json.as_bytes()).unwrap();
+        assert_eq!(title, "Handover");
+        let ids: Vec<usize> = imported.inner.iter().map(Interaction::id).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids[0] > history.inner[3].id());
+        // The call that never got a result is dropped.
+        assert!(matches!(
+            &imported.inner[3],
+            Interaction::LlmResponse { tool_calls: Some(calls), .. } if calls.is_empty()
+        ));
+
+        history.inner.swap(1, 2);
+        let json = export(ExportFormat::Json, "Handover", &history).unwrap();
+        let error = import(json.as_bytes()).unwrap_err().to_string();
+        assert!(error.contains("tool result a does not follow its tool call"));
+        assert!(import(b"{}").is_err());
+    }
+}
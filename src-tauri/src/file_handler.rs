@@ -1,6 +1,8 @@
 use crate::Result;
 use crate::error::Error;
 use crate::interaction::{Content, FileData, ImageUrl};
+use crate::office;
+use crate::utils::debug_log;
 use base64::{Engine as _, engine::general_purpose};
 use calamine::{Reader, open_workbook_auto_from_rs};
 use camino::Utf8PathBuf;
@@ -11,6 +13,9 @@ use std::io::Cursor;
 use std::iter::once;
 use std::path::Path;
 
+/// Most PDF pages sent to a model in one call.
+const PDF_PAGES_PER_CHUNK: usize = 100;
+
 fn get_cache_path(file_buffer: &[u8], target_extension: &str) -> Result<Utf8PathBuf> {
     let mut hasher = Sha256::new();
     hasher.update(file_buffer);
@@ -121,19 +126,19 @@ pub fn convert_to_pdf(path: &Path) -> Result<Vec<u8>> {
 }
 
 fn convert_xlsx_to_csv(file_buffer: &[u8]) -> Result<String> {
-    println!("starting to convert csv");
+    debug_log!("starting to convert csv");
     if let Ok(Some(cached_csv_bytes)) = read_conversion_cache(file_buffer, "csv") {
         return Ok(String::from_utf8(cached_csv_bytes).unwrap_or_default());
     }
-    println!("no cache");
+    debug_log!("no cache");
     let reader = Cursor::new(file_buffer);
     let mut workbook = open_workbook_auto_from_rs(reader)
         .map_err(|_| Error::Tool("cannot open xlsx workbook".to_string()))?;
-    println!("wb opened");
+    debug_log!("wb opened");
     let mut csv_data = vec![];
     for (sheet_name, range) in workbook.worksheets() {
         let mut writer = Writer::from_writer(vec![]);
-        println!("enter a sheet");
+        debug_log!("enter a sheet");
         for row in range.rows() {
             writer
                 .write_record(once(sheet_name.clone()).chain(row.iter().map(|c| match c {
@@ -150,14 +155,471 @@ fn convert_xlsx_to_csv(file_buffer: &[u8]) -> Result<String> {
                 .map_err(|_| Error::Tool("CSV conversion error".to_string()))?,
         );
     }
-    println!("done all sheets");
+    debug_log!("done all sheets");
 
     write_conversion_cache(file_buffer, &csv_data, "csv");
-    println!("written to cache");
+    debug_log!("written to cache");
     String::from_utf8(csv_data).map_err(|_| Error::Tool("error converting CSV to UTF".to_string()))
 }
 
-pub fn process_file_for_llm(path: &Path) -> Result<Vec<Content>> {
+fn convert_office_to_markdown(
+    file_buffer: &[u8],
+    to_markdown: fn(&[u8]) -> Result<String>,
+) -> Result<String> {
+    if let Ok(Some(cached_md_bytes)) = read_conversion_cache(file_buffer, "md") {
+        return Ok(String::from_utf8(cached_md_bytes).unwrap_or_default());
+    }
+    let markdown = to_markdown(file_buffer)?;
+    write_conversion_cache(file_buffer, markdown.as_bytes(), "md");
+    Ok(markdown)
+}
+
+/// Text of every page of a PDF, cached by content hash.
+fn pdf_page_texts(file_buffer: &[u8]) -> Result<Vec<String>> {
+    if let Ok(Some(cached)) = read_conversion_cache(file_buffer, "pages.json")
+        && let Ok(pages) = serde_json::from_slice(&cached)
+    {
+        return Ok(pages);
+    }
+    // The extractor panics on some malformed fonts rather than returning an error.
+    let pages =
+        std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(file_buffer))
+            .map_err(|_| Error::Tool("PDF text extraction failed".to_string()))?
+            .map_err(|e| Error::Tool(format!("PDF text extraction failed: {e}")))?;
+    write_conversion_cache(file_buffer, &serde_json::to_vec(&pages)?, "pages.json");
+    Ok(pages)
+}
+
+/// Parses a 1-based page selection such as `40-55` or `1,3,10-12`.
+pub fn parse_pages(spec: &str, page_count: usize) -> Result<Vec<usize>> {
+    let invalid = || {
+        Error::Tool(format!(
+            "invalid page range '{spec}', expected e.g. 40-55 or 1,3,10-12"
+        ))
+    };
+    let mut pages = vec![];
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.trim(), end.trim()),
+            None => (part, part),
+        };
+        let start: usize = start.parse().map_err(|_| invalid())?;
+        let end: usize = end.parse().map_err(|_| invalid())?;
+        if start == 0 || start > end {
+            return Err(invalid());
+        }
+        if end > page_count {
+            return Err(Error::Tool(format!(
+                "page {end} is out of range, the PDF has {page_count} pages"
+            )));
+        }
+        pages.extend(start..=end);
+    }
+    if pages.is_empty() {
+        return Err(invalid());
+    }
+    pages.sort_unstable();
+    pages.dedup();
+    Ok(pages)
+}
+
+/// The text layer of the selected pages, each under a page heading.
+fn pdf_text(file_buffer: &[u8], pages: Option<&str>) -> Result<String> {
+    let texts = pdf_page_texts(file_buffer)?;
+    let selected = match pages {
+        Some(spec) => parse_pages(spec, texts.len())?,
+        None => (1..=texts.len()).collect(),
+    };
+    Ok(selected
+        .iter()
+        .map(|n| match texts[n - 1].trim() {
+            "" => format!("## Page {n}\n\n(no text layer)"),
+            text => format!("## Page {n}\n\n{text}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n"))
+}
+
+/// A copy of the PDF with only the selected pages.
+fn pdf_subset(file_buffer: &[u8], pages: &str) -> Result<Vec<u8>> {
+    let mut doc = pdf_extract::Document::load_mem(file_buffer)
+        .map_err(|e| Error::Tool(format!("cannot read PDF: {e}")))?;
+    let page_numbers: Vec<u32> = doc.get_pages().keys().copied().collect();
+    let keep = parse_pages(pages, page_numbers.len())?;
+    let delete: Vec<u32> = page_numbers
+        .into_iter()
+        .filter(|n| keep.binary_search(&(*n as usize)).is_err())
+        .collect();
+    doc.delete_pages(&delete);
+    doc.prune_objects();
+    let mut out = vec![];
+    doc.save_to(&mut out)?;
+    Ok(out)
+}
+
+fn pdf_content(path: &Path, pdf_bytes: &[u8]) -> Content {
+    let encoded = general_purpose::STANDARD.encode(pdf_bytes);
+    let data_url = format!("data:application/pdf;base64,{encoded}");
+    Content::File {
+        file: FileData {
+            filename: path
+                .file_name()
+                .unwrap_or_default()
+                .to_str()
+                .unwrap_or_default()
+                .to_string(),
+            file_data: data_url,
+        },
+    }
+}
+
+/// Plain text rendition of a file for indexing and searching, using the same
+/// cached conversions as `process_file_for_llm`. `None` for images and
+/// unsupported types.
+pub fn file_text(path: &Path) -> Result<Option<String>> {
+    let file_type = determine_file_type(path);
+    if matches!(file_type, FileType::Image(_) | FileType::Unsupported) {
+        return Ok(None);
+    }
+    let file_buffer = fs::read(path)?;
+    let text = match file_type {
+        FileType::Pdf => pdf_text(&file_buffer, None)?,
+        FileType::Docx => convert_office_to_markdown(&file_buffer, office::docx::to_markdown)?,
+        FileType::Pptx => convert_office_to_markdown(&file_buffer, office::pptx::to_markdown)?,
+        FileType::Xlsx => convert_xlsx_to_csv(&file_buffer)?,
+        _ => String::from_utf8_lossy(&file_buffer).into_owned(),
+    };
+    Ok(Some(text))
+}
+
+/// A run of lines sharing a location prefix, e.g. one PDF page or one sheet.
+pub struct Section {
+    pub label: Option<String>,
+    /// What a line is called in locations, "line" or "row".
+    pub unit: &'static str,
+    pub lines: Vec<String>,
+}
+
+impl Section {
+    fn new(label: Option<String>, unit: &'static str) -> Self {
+        Self {
+            label,
+            unit,
+            lines: vec![],
+        }
+    }
+}
+
+/// Splits a `file_text` rendition at the headings its converter inserted,
+/// so matches can be located by page, slide or sheet.
+pub fn text_sections(file_type: &FileType, text: &str) -> Vec<Section> {
+    match file_type {
+        FileType::Xlsx => {
+            // Each CSV record starts with its sheet name; the rest stays CSV.
+            let mut out: Vec<Section> = vec![];
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(text.as_bytes());
+            for record in reader.records().flatten() {
+                let sheet = record.get(0).unwrap_or_default();
+                let mut writer = Writer::from_writer(vec![]);
+                writer.write_record(record.iter().skip(1)).ok();
+                let line = writer
+                    .into_inner()
+                    .map(|bytes| String::from_utf8_lossy(&bytes).trim_end().to_string())
+                    .unwrap_or_default();
+                match out.last_mut() {
+                    Some(section) if section.label.as_deref() == Some(sheet) => {
+                        section.lines.push(line)
+                    }
+                    _ => {
+                        let mut section = Section::new(Some(sheet.to_string()), "row");
+                        section.lines.push(line);
+                        out.push(section);
+                    }
+                }
+            }
+            out.iter_mut()
+                .for_each(|s| s.label = s.label.take().map(|l| format!("sheet {l}")));
+            out
+        }
+        FileType::Pdf | FileType::Pptx => {
+            let heading = match file_type {
+                FileType::Pdf => "## Page ",
+                _ => "## Slide ",
+            };
+            let is_pdf = matches!(file_type, FileType::Pdf);
+            let mut out = vec![];
+            let mut current = Section::new(None, "line");
+            let mut after_heading = false;
+            for line in text.lines() {
+                if let Some(rest) = line.strip_prefix(heading) {
+                    let number: String = rest.chars().take_while(char::is_ascii_digit).collect();
+                    out.push(current);
+                    current = Section::new(
+                        Some(format!("{}{number}", heading[3..].to_lowercase())),
+                        "line",
+                    );
+                    // Slide headings carry the title, page headings are ours.
+                    if is_pdf {
+                        after_heading = true;
+                        continue;
+                    }
+                }
+                // Lines count from the page text, not the blank line under our heading.
+                if !(after_heading && line.is_empty()) {
+                    current.lines.push(line.to_string());
+                }
+                after_heading = false;
+            }
+            out.push(current);
+            if is_pdf {
+                // Pages are trimmed, so trailing blank lines only separate them.
+                for section in &mut out {
+                    while section.lines.last().is_some_and(|l| l.is_empty()) {
+                        section.lines.pop();
+                    }
+                }
+            }
+            out.retain(|s| !s.lines.is_empty());
+            out
+        }
+        _ => {
+            let mut section = Section::new(None, "line");
+            section.lines = text.lines().map(str::to_string).collect();
+            vec![section]
+        }
+    }
+}
+
+/// Rough token count of `text`, at about four bytes per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Part of a file sized for one model call.
+pub struct FileChunk {
+    /// Where the part sits in the file, `None` when it is the whole file.
+    pub location: Option<String>,
+    pub content: Vec<Content>,
+}
+
+/// Compact page list such as `1-3,7`, as accepted by `parse_pages`.
+fn page_spec(pages: &[usize]) -> String {
+    let mut runs: Vec<(usize, usize)> = vec![];
+    for &page in pages {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == page => *end = page,
+            _ => runs.push((page, page)),
+        }
+    }
+    runs.iter()
+        .map(|&(start, end)| match start == end {
+            true => start.to_string(),
+            false => format!("{start}-{end}"),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Cuts `line` at char boundaries into pieces of at most `max_bytes`.
+pub fn split_line(line: &str, max_bytes: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        if i + c.len_utf8() - start > max_bytes && i > start {
+            pieces.push(&line[start..i]);
+            start = i;
+        }
+    }
+    pieces.push(&line[start..]);
+    pieces
+}
+
+/// Splits text over `token_budget` at its page, slide or sheet boundaries,
+/// then by lines, and lines still too long by characters. Consecutive small
+/// sections are packed back together. Sheet parts repeat the sheet's first
+/// row so columns keep their headers.
+fn split_text(file_type: &FileType, text: &str, token_budget: usize) -> Vec<(String, String)> {
+    // (location, text, whole section)
+    let mut pieces: Vec<(String, String, bool)> = vec![];
+    for section in text_sections(file_type, text) {
+        let header = match section.unit {
+            "row" => section.lines.first().cloned(),
+            _ => None,
+        };
+        let location = |start: usize, end: usize| {
+            let position = match (start + 1, end) {
+                (a, b) if a == b => format!("{} {a}", section.unit),
+                (a, b) => format!("{}s {a}-{b}", section.unit),
+            };
+            let whole = start == 0 && end == section.lines.len();
+            match (&section.label, whole) {
+                (Some(label), true) => label.clone(),
+                (Some(label), false) => format!("{label}, {position}"),
+                (None, _) => position,
+            }
+        };
+        let part = |start: usize, lines: &[&str]| {
+            let mut text = vec![];
+            if start > 0
+                && let Some(header) = &header
+            {
+                text.push(header.as_str());
+            }
+            text.extend(lines);
+            text.join("\n")
+        };
+        let lines = |start: usize, end: usize| {
+            let lines: Vec<_> = section.lines[start..end]
+                .iter()
+                .map(String::as_str)
+                .collect();
+            let whole = start == 0 && end == section.lines.len();
+            (location(start, end), part(start, &lines), whole)
+        };
+        let header_tokens = header.as_deref().map_or(0, estimate_tokens);
+        let max_line_bytes = token_budget.saturating_sub(header_tokens + 1).max(1) * 4;
+        let mut start = 0;
+        let mut size = 0;
+        for (i, line) in section.lines.iter().enumerate() {
+            let tokens = estimate_tokens(line) + 1;
+            if tokens > token_budget {
+                if i > start {
+                    pieces.push(lines(start, i));
+                }
+                for (n, piece) in split_line(line, max_line_bytes).into_iter().enumerate() {
+                    let location = format!("{}, part {}", location(i, i + 1), n + 1);
+                    pieces.push((location, part(i, &[piece]), false));
+                }
+                start = i + 1;
+                size = header_tokens;
+                continue;
+            }
+            if size + tokens > token_budget && i > start {
+                pieces.push(lines(start, i));
+                start = i;
+                size = header_tokens;
+            }
+            size += tokens;
+        }
+        if start < section.lines.len() {
+            pieces.push(lines(start, section.lines.len()));
+        }
+    }
+
+    let mut packed: Vec<(Vec<String>, String, bool)> = vec![];
+    for (location, text, whole) in pieces {
+        // Labelled parts are marked, as their headings don't survive the split.
+        let text = match file_type {
+            FileType::Pdf | FileType::Xlsx => format!("[{location}]\n{text}"),
+            _ => text,
+        };
+        match packed.last_mut() {
+            Some((locations, packed_text, true))
+                if whole
+                    && estimate_tokens(packed_text) + estimate_tokens(&text) <= token_budget =>
+            {
+                locations.push(location);
+                packed_text.push_str("\n\n");
+                packed_text.push_str(&text);
+            }
+            _ => packed.push((vec![location], text, whole)),
+        }
+    }
+    packed
+        .into_iter()
+        .map(|(locations, text, _)| {
+            let location = match locations.as_slice() {
+                [one] => one.clone(),
+                [first, .., last] => format!("{first} to {last}"),
+                [] => String::new(),
+            };
+            (location, text)
+        })
+        .collect()
+}
+
+/// Like `process_file_for_llm`, but splits content larger than
+/// `token_budget` into parts: PDFs into runs of `PDF_PAGES_PER_CHUNK` pages,
+/// text at its page, slide or sheet boundaries, by lines or within long lines.
+pub fn chunk_file_for_llm(
+    path: &Path,
+    pages: Option<&str>,
+    token_budget: usize,
+) -> Result<Vec<FileChunk>> {
+    let file_type = determine_file_type(path);
+    if let FileType::Pdf = file_type {
+        let selected = selected_pages(path, pages)?;
+        if selected.len() > PDF_PAGES_PER_CHUNK {
+            return selected
+                .chunks(PDF_PAGES_PER_CHUNK)
+                .map(|run| {
+                    let spec = page_spec(run);
+                    Ok(FileChunk {
+                        content: process_file_for_llm(path, Some(&spec))?,
+                        location: Some(format!("pages {spec}")),
+                    })
+                })
+                .collect();
+        }
+    }
+    let content = process_file_for_llm(path, pages)?;
+    match content.as_slice() {
+        [Content::Text { text }] if estimate_tokens(text) > token_budget => {
+            Ok(split_text(&file_type, text, token_budget)
+                .into_iter()
+                .map(|(location, text)| FileChunk {
+                    location: Some(location),
+                    content: vec![Content::Text { text }],
+                })
+                .collect())
+        }
+        _ => Ok(vec![FileChunk {
+            location: None,
+            content,
+        }]),
+    }
+}
+
+/// How many parts `chunk_file_for_llm` splits a file into, without rendering
+/// the PDFs sent as files.
+pub fn count_chunks(path: &Path, pages: Option<&str>, token_budget: usize) -> Result<usize> {
+    let file_type = determine_file_type(path);
+    if let FileType::Pdf = file_type {
+        let selected = selected_pages(path, pages)?;
+        if selected.len() > PDF_PAGES_PER_CHUNK {
+            return Ok(selected.len().div_ceil(PDF_PAGES_PER_CHUNK));
+        }
+        if !crate::settings::get_settings()?.pdf_as_text {
+            return Ok(1);
+        }
+    }
+    Ok(match process_file_for_llm(path, pages)?.as_slice() {
+        [Content::Text { text }] if estimate_tokens(text) > token_budget => {
+            split_text(&file_type, text, token_budget).len()
+        }
+        _ => 1,
+    })
+}
+
+/// The PDF pages `pages` selects, all of them when `None`.
+fn selected_pages(path: &Path, pages: Option<&str>) -> Result<Vec<usize>> {
+    let file_buffer = fs::read(path)?;
+    let page_count = pdf_extract::Document::load_mem(&file_buffer)
+        .map_err(|e| Error::Tool(format!("cannot read PDF: {e}")))?
+        .get_pages()
+        .len();
+    match pages {
+        Some(spec) => parse_pages(spec, page_count),
+        None => Ok((1..=page_count).collect()),
+    }
+}
+
+/// Renders a file as message content. `pages` selects PDF pages and is
+/// ignored for other file types.
+pub fn process_file_for_llm(path: &Path, pages: Option<&str>) -> Result<Vec<Content>> {
     let file_buffer = fs::read(path)?;
     let file_type = determine_file_type(path);
 
@@ -169,36 +631,27 @@ pub fn process_file_for_llm(path: &Path) -> Result<Vec<Content>> {
                 image_url: ImageUrl { url: data_url },
             }])
         }
-        FileType::Pdf => {
-            let encoded = general_purpose::STANDARD.encode(&file_buffer);
-            let data_url = format!("data:application/pdf;base64,{encoded}");
-            Ok(vec![Content::File {
-                file: FileData {
-                    filename: path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default()
-                        .to_string(),
-                    file_data: data_url,
-                },
-            }])
-        }
+        FileType::Pdf if crate::settings::get_settings()?.pdf_as_text => Ok(vec![Content::Text {
+            text: pdf_text(&file_buffer, pages)?,
+        }]),
+        FileType::Pdf => match pages {
+            Some(pages) => Ok(vec![pdf_content(path, &pdf_subset(&file_buffer, pages)?)]),
+            None => Ok(vec![pdf_content(path, &file_buffer)]),
+        },
         FileType::Docx | FileType::Pptx => {
-            let pdf_bytes = convert_to_pdf(path)?;
-            let encoded = general_purpose::STANDARD.encode(&pdf_bytes);
-            let data_url = format!("data:application/pdf;base64,{encoded}");
-            Ok(vec![Content::File {
-                file: FileData {
-                    filename: path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or_default()
-                        .to_string(),
-                    file_data: data_url,
-                },
-            }])
+            let to_markdown = match file_type {
+                FileType::Docx => office::docx::to_markdown,
+                _ => office::pptx::to_markdown,
+            };
+            match convert_office_to_markdown(&file_buffer, to_markdown) {
+                Ok(markdown) => Ok(vec![Content::Text { text: markdown }]),
+                // Fall back to LibreOffice for documents the native reader can't handle.
+                Err(e) if !crate::settings::get_settings()?.soffice_path.is_empty() => {
+                    eprintln!("native conversion failed, trying LibreOffice: {e}");
+                    Ok(vec![pdf_content(path, &convert_to_pdf(path)?)])
+                }
+                Err(e) => Err(e),
+            }
         }
         FileType::Xlsx => {
             let csv_data = convert_xlsx_to_csv(&file_buffer)?;
@@ -211,3 +664,174 @@ pub fn process_file_for_llm(path: &Path) -> Result<Vec<Content>> {
         _ => Err(Error::Tool("Unsupported file type".to_string())),
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::{
+        FileType, page_spec, parse_pages, pdf_subset, pdf_text, split_text, text_sections,
+    };
+    use pdf_extract::content::{Content, Operation};
+    use pdf_extract::{Document, Object, Stream, dictionary};
+
+    /// A PDF with one line of Courier text per page.
+    pub fn sample_pdf(pages: &[&str]) -> Vec<u8> {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+        });
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+        let mut kids: Vec<Object> = vec![];
+        for text in pages {
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![100.into(), 600.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*text)]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            kids.push(
+                doc.add_object(dictionary! {
+                    "Type" => "Page",
+                    "Parent" => pages_id,
+                    "Contents" => content_id,
+                })
+                .into(),
+            );
+        }
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages.len() as i64,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        let mut out = vec![];
+        doc.save_to(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn parse_page_ranges() {
+        assert_eq!(parse_pages("2", 5).unwrap(), vec![2]);
+        assert_eq!(parse_pages("4-5, 1,2-3", 5).unwrap(), vec![1, 2, 3, 4, 5]);
+        assert!(parse_pages("0-2", 5).is_err());
+        assert!(parse_pages("3-1", 5).is_err());
+        assert!(parse_pages("abc", 5).is_err());
+        assert!(parse_pages("4-6", 5).is_err());
+    }
+
+    #[test]
+    fn pdf_page_selection() {
+        let pdf = sample_pdf(&["alpha", "beta", "gamma"]);
+
+        let text = pdf_text(&pdf, Some("2-3")).unwrap();
+        assert!(text.starts_with("## Page 2\n\nbeta"));
+        assert!(text.contains("## Page 3\n\ngamma"));
+        assert!(!text.contains("alpha"));
+
+        let subset = pdf_subset(&pdf, "3").unwrap();
+        assert_eq!(pdf_text(&subset, None).unwrap(), "## Page 1\n\ngamma");
+    }
+
+    fn locations(file_type: FileType, text: &str) -> Vec<(Option<String>, Vec<String>)> {
+        text_sections(&file_type, text)
+            .into_iter()
+            .map(|s| (s.label, s.lines))
+            .collect()
+    }
+
+    #[test]
+    fn sections_follow_rendition_headings() {
+        assert_eq!(
+            locations(FileType::Pdf, "## Page 1\n\nalpha\n\n## Page 2\n\nbeta"),
+            [
+                (Some("page 1".to_string()), vec!["alpha".into()]),
+                (Some("page 2".to_string()), vec!["beta".into()]),
+            ]
+        );
+        assert_eq!(
+            locations(
+                FileType::Xlsx,
+                "Revenue,Q1,10\nRevenue,Q2,12\nCosts,Q1,\"4,5\"\n"
+            ),
+            [
+                (
+                    Some("sheet Revenue".to_string()),
+                    vec!["Q1,10".into(), "Q2,12".into()]
+                ),
+                (Some("sheet Costs".to_string()), vec!["Q1,\"4,5\"".into()]),
+            ]
+        );
+        assert_eq!(
+            locations(FileType::Pptx, "## Slide 3: Outlook\n\n- Growth"),
+            [(
+                Some("slide 3".to_string()),
+                vec!["## Slide 3: Outlook".into(), "".into(), "- Growth".into()]
+            )]
+        );
+    }
+
+    #[test]
+    fn split_oversized_text() {
+        assert_eq!(page_spec(&[1, 2, 3, 7, 9, 10]), "1-3,7,9-10");
+
+        let csv = "Big,Name,Amount\nBig,a,1\nBig,b,2\nBig,c,3\nSmall,x\n";
+        let parts = split_text(&FileType::Xlsx, csv, 7);
+        let locations: Vec<_> = parts.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(
+            locations,
+            ["sheet Big, rows 1-2", "sheet Big, rows 3-4", "sheet Small"]
+        );
+        assert_eq!(parts[1].1, "[sheet Big, rows 3-4]\nName,Amount\nb,2\nc,3");
+
+        let pdf = format!(
+            "## Page 1\nalpha\n## Page 2\nbeta\n## Page 3\n{}",
+            "x".repeat(100)
+        );
+        let parts = split_text(&FileType::Pdf, &pdf, 20);
+        assert_eq!(parts[0].0, "page 1 to page 2");
+        assert_eq!(parts[0].1, "[page 1]\nalpha\n\n[page 2]\nbeta");
+        assert_eq!(parts[1].0, "page 3, part 1");
+        assert_eq!(parts[2].1, format!("[page 3, part 2]\n{}", "x".repeat(24)));
+        assert_eq!(parts.len(), 3);
+
+        let parts = split_text(&FileType::Text, &format!("short\n{}", "é".repeat(30)), 10);
+        let locations: Vec<_> = parts.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(locations, ["line 1", "line 2, part 1", "line 2, part 2"]);
+        assert!(parts.iter().all(|(_, text)| text.len() <= 36));
+        assert_eq!(
+            parts[1..]
+                .iter()
+                .map(|(_, t)| t.as_str())
+                .collect::<String>(),
+            "é".repeat(30)
+        );
+
+        let text = "one two\nthree four\nfive six";
+        let parts = split_text(&FileType::Text, text, 5);
+        assert_eq!(
+            parts,
+            [
+                ("line 1".to_string(), "one two".to_string()),
+                ("line 2".to_string(), "three four".to_string()),
+                ("line 3".to_string(), "five six".to_string()),
+            ]
+        );
+    }
+}
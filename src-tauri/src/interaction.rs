@@ -1,10 +1,18 @@
+use crate::file_handler::estimate_tokens;
 use crate::openrouter::ToolCall;
+use crate::usage::Usage;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+/// Flat guess for an image, whatever its size.
+const IMAGE_TOKENS: usize = 1_000;
+/// Base64 PDFs carry fonts and images, so their length overstates their tokens.
+const FILE_CHARS_PER_TOKEN: usize = 20;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, PartialOrd, Ord, Eq)]
 #[serde(tag = "type")]
 pub enum Content {
@@ -17,7 +25,16 @@ pub enum Content {
 }
 
 impl Content {
-    fn is_empty(&self) -> bool {
+    /// Rough number of tokens this part takes up in a prompt.
+    pub fn estimate_tokens(&self) -> usize {
+        match self {
+            Content::Text { text } => estimate_tokens(text),
+            Content::ImageUrl { .. } => IMAGE_TOKENS,
+            Content::File { file } => file.file_data.len().div_ceil(FILE_CHARS_PER_TOKEN),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
         match self {
             Content::Text { text } => text.is_empty(),
             Content::ImageUrl { .. } => false,
@@ -37,12 +54,15 @@ pub struct ImageUrl {
     pub url: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum Interaction {
     LlmResponse {
         interaction_id: usize,
         content: Vec<Content>,
         tool_calls: Option<Vec<ToolCall>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
     },
     ToolResult {
         interaction_id: usize,
@@ -50,6 +70,9 @@ pub enum Interaction {
         response: String,
         for_llm: Vec<Content>,
         for_user: Vec<Content>,
+        /// Completions the tool made itself, e.g. `ask_files` or `check_online`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        usage: Option<Usage>,
     },
     UserMessage {
         interaction_id: usize,
@@ -58,9 +81,38 @@ pub enum Interaction {
 }
 
 impl Interaction {
-    fn create_id() -> usize {
-        static COUNTER: AtomicUsize = AtomicUsize::new(1);
-        COUNTER.fetch_add(1, Ordering::Relaxed)
+    pub fn create_id() -> usize {
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Replaces the interaction id, e.g. with one reserved before streaming started.
+    pub fn with_id(mut self, id: usize) -> Interaction {
+        match &mut self {
+            Interaction::LlmResponse { interaction_id, .. }
+            | Interaction::ToolResult { interaction_id, .. }
+            | Interaction::UserMessage { interaction_id, .. } => *interaction_id = id,
+        }
+        self
+    }
+
+    /// Attaches what producing this interaction cost. User messages cost nothing.
+    pub fn with_usage(mut self, cost: Usage) -> Interaction {
+        match &mut self {
+            Interaction::LlmResponse { usage, .. } | Interaction::ToolResult { usage, .. } => {
+                *usage = Some(cost)
+            }
+            Interaction::UserMessage { .. } => {}
+        }
+        self
+    }
+
+    pub fn usage(&self) -> Option<Usage> {
+        match self {
+            Interaction::LlmResponse { usage, .. } | Interaction::ToolResult { usage, .. } => {
+                *usage
+            }
+            Interaction::UserMessage { .. } => None,
+        }
     }
 
     pub fn llm_response(content: Vec<Content>, tool_calls: Option<Vec<ToolCall>>) -> Interaction {
@@ -68,6 +120,7 @@ impl Interaction {
             content,
             tool_calls,
             interaction_id: Self::create_id(),
+            usage: None,
         }
     }
     pub fn tool_result(
@@ -82,6 +135,7 @@ impl Interaction {
             response,
             for_llm,
             for_user,
+            usage: None,
         }
     }
     pub fn user_message(content: Vec<Content>) -> Interaction {
@@ -90,7 +144,34 @@ impl Interaction {
             content,
         }
     }
-    fn id(&self) -> usize {
+    /// Rough number of tokens this interaction takes up in a prompt.
+    pub fn estimate_tokens(&self) -> usize {
+        let parts =
+            |content: &[Content]| -> usize { content.iter().map(Content::estimate_tokens).sum() };
+        match self {
+            Interaction::LlmResponse {
+                content,
+                tool_calls,
+                ..
+            } => {
+                parts(content)
+                    + tool_calls
+                        .iter()
+                        .flatten()
+                        .map(|t| {
+                            estimate_tokens(&t.function.name)
+                                + estimate_tokens(&t.function.arguments)
+                        })
+                        .sum::<usize>()
+            }
+            Interaction::ToolResult {
+                response, for_llm, ..
+            } => estimate_tokens(response) + parts(for_llm),
+            Interaction::UserMessage { content, .. } => parts(content),
+        }
+    }
+
+    pub fn id(&self) -> usize {
         match self {
             Interaction::LlmResponse { interaction_id, .. } => *interaction_id,
             Interaction::ToolResult { interaction_id, .. } => *interaction_id,
@@ -114,17 +195,170 @@ impl Interaction {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// A continuation of the conversation other than the active one, kept when
+/// an earlier message is edited so the user can switch back to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Branch {
+    /// Interaction the branch follows, `None` if it starts the conversation.
+    /// It may itself be part of another branch.
+    pub parent: Option<usize>,
+    pub interactions: Vec<Interaction>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct History {
+    /// The active branch, from the start of the conversation.
     pub inner: Vec<Interaction>,
+    /// Every other branch; with `inner` they form a tree of interactions.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<Branch>,
 }
 
 impl History {
+    /// Ensures ids handed out from now on do not collide with the ones
+    /// stored in this history, e.g. after loading it back from disk.
+    pub fn reserve_ids(&self) {
+        let stored = self.branches.iter().flat_map(|b| &b.interactions);
+        if let Some(max) = self.inner.iter().chain(stored).map(|i| i.id()).max() {
+            NEXT_ID.fetch_max(max + 1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn estimate_tokens(&self) -> usize {
+        self.inner.iter().map(Interaction::estimate_tokens).sum()
+    }
+
+    /// Total usage of the conversation so far.
+    pub fn usage(&self) -> Usage {
+        let mut total = Usage::default();
+        for usage in self.inner.iter().filter_map(Interaction::usage) {
+            total += usage;
+        }
+        total
+    }
+
     pub fn push(&mut self, new: Interaction) {
         self.inner.push(new);
     }
     pub fn clear(&mut self) {
         self.inner.clear();
+        self.branches.clear();
+    }
+
+    fn parent_of(&self, index: usize) -> Option<usize> {
+        index.checked_sub(1).map(|p| self.inner[p].id())
+    }
+
+    /// Where the children of `parent` go in the active branch, if it is on it.
+    fn children_index(&self, parent: Option<usize>) -> Option<usize> {
+        match parent {
+            None => Some(0),
+            Some(id) => self.inner.iter().position(|i| i.id() == id).map(|p| p + 1),
+        }
+    }
+
+    /// Moves interaction `id` and everything after it to a branch of their
+    /// own, so the conversation can go on differently from there. Returns
+    /// false if `id` is not on the active branch.
+    pub fn fork(&mut self, id: usize) -> bool {
+        let Some(index) = self.inner.iter().position(|i| i.id() == id) else {
+            return false;
+        };
+        let parent = self.parent_of(index);
+        let interactions = self.inner.split_off(index);
+        self.branches.push(Branch {
+            parent,
+            interactions,
+        });
+        true
+    }
+
+    /// Makes the branch starting with interaction `id` the active one,
+    /// keeping the continuation it replaces as a branch. Returns false if no
+    /// branch starts with `id`.
+    pub fn switch_branch(&mut self, id: usize) -> bool {
+        let starts_with = |b: &Branch| b.interactions.first().is_some_and(|i| i.id() == id);
+        let Some(parent) = self
+            .branches
+            .iter()
+            .find(|b| starts_with(b))
+            .map(|b| b.parent)
+        else {
+            return false;
+        };
+        // A branch of a branch: bring the outer one in first.
+        if self.children_index(parent).is_none() {
+            let outer = self
+                .branches
+                .iter()
+                .find(|b| b.interactions.iter().any(|i| Some(i.id()) == parent))
+                .and_then(|b| b.interactions.first())
+                .map(|i| i.id());
+            if !outer.is_some_and(|outer| self.switch_branch(outer)) {
+                return false;
+            }
+        }
+        let (Some(index), Some(position)) = (
+            self.children_index(parent),
+            self.branches.iter().position(starts_with),
+        ) else {
+            return false;
+        };
+        let branch = self.branches.remove(position);
+        let replaced = self.inner.split_off(index);
+        if !replaced.is_empty() {
+            self.branches.push(Branch {
+                parent,
+                interactions: replaced,
+            });
+        }
+        self.inner.extend(branch.interactions);
+        true
+    }
+
+    /// Interactions of the active branch that have alternatives, each with
+    /// the first interaction of every alternative, itself included, oldest first.
+    pub fn forks(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut out = vec![];
+        for (index, interaction) in self.inner.iter().enumerate() {
+            let parent = self.parent_of(index);
+            let mut alternatives: Vec<usize> = self
+                .branches
+                .iter()
+                .filter(|b| b.parent == parent)
+                .filter_map(|b| b.interactions.first().map(Interaction::id))
+                .collect();
+            if !alternatives.is_empty() {
+                alternatives.push(interaction.id());
+                alternatives.sort();
+                out.push((interaction.id(), alternatives));
+            }
+        }
+        out
+    }
+
+    /// Gives branches whose parent was deleted the closest earlier
+    /// interaction of `old_path` that still exists.
+    fn reattach_branches(&mut self, old_path: &[usize]) {
+        let existing: HashSet<usize> = self
+            .inner
+            .iter()
+            .chain(self.branches.iter().flat_map(|b| &b.interactions))
+            .map(Interaction::id)
+            .collect();
+        for branch in &mut self.branches {
+            if let Some(parent) = branch.parent
+                && !existing.contains(&parent)
+                && let Some(index) = old_path.iter().position(|id| *id == parent)
+            {
+                branch.parent = old_path[..index]
+                    .iter()
+                    .rev()
+                    .find(|id| existing.contains(id))
+                    .copied();
+            }
+        }
+        self.branches.retain(|b| !b.interactions.is_empty());
     }
     pub fn clean_unfinished_tool_calls(&mut self) {
         let tool_response_ids: HashSet<_> = self
@@ -150,7 +384,22 @@ impl History {
         }
     }
 
+    /// Moves the answer to the last user message to a branch of its own, so
+    /// it can be generated again while the old one stays reachable. Returns
+    /// the id of that message, if there is one.
+    pub fn fork_answer_to_last_user_message(&mut self) -> Option<usize> {
+        let index = self
+            .inner
+            .iter()
+            .rposition(|i| matches!(i, Interaction::UserMessage { .. }))?;
+        if let Some(answer) = self.inner.get(index + 1).map(Interaction::id) {
+            self.fork(answer);
+        }
+        Some(self.inner[index].id())
+    }
+
     pub fn delete_by_tool_id(&mut self, llm_interaction_id: usize, tool_call_id_to_delete: &str) {
+        let old_path: Vec<usize> = self.inner.iter().map(Interaction::id).collect();
         let llm_interaction_index = self.inner.iter().position(|i| i.id() == llm_interaction_id);
 
         if let Some(index) = llm_interaction_index {
@@ -176,9 +425,11 @@ impl History {
 
         // Finally, prune any LLM messages that have become empty
         self.inner.retain(|interaction| !interaction.is_empty());
+        self.reattach_branches(&old_path);
     }
 
     pub fn delete_by_id(&mut self, id: usize) {
+        let old_path: Vec<usize> = self.inner.iter().map(Interaction::id).collect();
         let interaction_to_delete = self.inner.iter().find(|i| i.id() == id).cloned();
 
         if let Some(interaction) = interaction_to_delete {
@@ -212,6 +463,7 @@ impl History {
             }
         }
         self.inner.retain(|i| i.id() != id);
+        self.reattach_branches(&old_path);
     }
 }
 
@@ -231,3 +483,95 @@ pub trait Source {
     type SendType;
     fn sends(data: Self::SendType) -> Interaction;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Content, History, Interaction};
+
+    fn say(history: &mut History, text: &str) -> usize {
+        let interaction = Interaction::user_message(vec![Content::Text {
+            text: text.to_string(),
+        }]);
+        let id = interaction.id();
+        history.push(interaction);
+        id
+    }
+
+    fn texts(history: &History) -> Vec<String> {
+        history
+            .inner
+            .iter()
+            .map(|i| match i {
+                Interaction::UserMessage { content, .. } => match &content[0] {
+                    Content::Text { text } => text.clone(),
+                    _ => String::new(),
+                },
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edits_fork_branches_that_can_be_switched() {
+        let mut history = History::default();
+        let first = say(&mut history, "q1");
+        let second = say(&mut history, "q2");
+        say(&mut history, "q3");
+
+        assert!(history.fork(second));
+        let edited = say(&mut history, "q2 edited");
+        assert_eq!(texts(&history), ["q1", "q2 edited"]);
+        assert_eq!(history.forks(), [(edited, vec![second, edited])]);
+
+        // Edit again within the edited branch, then go back to the original.
+        let deeper = say(&mut history, "q3 on edited");
+        assert!(history.fork(deeper));
+        say(&mut history, "q3 edited on edited");
+        assert!(history.switch_branch(second));
+        assert_eq!(texts(&history), ["q1", "q2", "q3"]);
+
+        // Reaching a branch of an inactive branch switches to both.
+        assert!(history.switch_branch(deeper));
+        assert_eq!(texts(&history), ["q1", "q2 edited", "q3 on edited"]);
+        assert_eq!(history.forks().len(), 2);
+        assert!(!history.switch_branch(first));
+
+        // Deleting a fork point keeps its branches reachable.
+        history.delete_by_id(edited);
+        assert_eq!(texts(&history), ["q1", "q3 on edited"]);
+        assert!(history.switch_branch(second));
+        assert_eq!(texts(&history), ["q1", "q2", "q3"]);
+    }
+
+    #[test]
+    fn regenerate_keeps_the_old_answer_as_a_branch() {
+        let mut history = History::default();
+        assert_eq!(history.fork_answer_to_last_user_message(), None);
+        say(&mut history, "q1");
+        history.push(Interaction::llm_response(vec![], None));
+        let last = say(&mut history, "q2");
+        let old_answer = Interaction::llm_response(vec![], None);
+        let old_id = old_answer.id();
+        history.push(old_answer);
+        history.push(Interaction::tool_result(
+            "call".to_string(),
+            "ok".to_string(),
+            vec![],
+            vec![],
+        ));
+
+        assert_eq!(history.fork_answer_to_last_user_message(), Some(last));
+        assert_eq!(texts(&history), ["q1", "", "q2"]);
+        // Nothing more to move when there is no answer yet.
+        assert_eq!(history.fork_answer_to_last_user_message(), Some(last));
+        assert_eq!(history.branches.len(), 1);
+
+        let new_answer = Interaction::llm_response(vec![], None);
+        let new_id = new_answer.id();
+        history.push(new_answer);
+        assert_eq!(history.forks(), [(new_id, vec![old_id, new_id])]);
+        assert!(history.switch_branch(old_id));
+        assert_eq!(history.inner.len(), 5);
+        assert_eq!(history.inner[3].id(), old_id);
+    }
+}
@@ -1,18 +1,35 @@
+mod ask_cache;
+mod budget;
 mod chat;
+mod cli;
+mod content_index;
+mod context;
+mod conversations;
 mod error;
+mod export;
 mod file_handler;
 mod interaction;
+mod llm;
+mod office;
 mod openrouter;
 mod search;
+mod semantic;
+mod server;
 mod settings;
+mod sinks;
+#[cfg(test)]
+mod test_support;
 mod tools;
 mod ui_events;
+mod usage;
 mod utils;
 use crate::chat::ChatProcessor;
+use crate::conversations::OpenConversation;
 use crate::error::Error;
-use crate::interaction::{Content, History, Source};
+use crate::export::ExportFormat;
+use crate::interaction::{Content, History, Interaction, Source};
 use crate::openrouter::ChatOptions;
-use crate::settings::get_settings;
+use crate::settings::{get_root, get_settings};
 use crate::ui_events::UIEvents;
 use camino::{Utf8Path, Utf8PathBuf};
 use std::sync::{Arc, Mutex, OnceLock};
@@ -22,89 +39,281 @@ use tauri_plugin_store::{Store, StoreBuilder};
 use tokio::select;
 use tokio_util::sync::CancellationToken;
 
+pub use crate::cli::run_cli;
+pub use crate::sinks::{JsonLinesSink, NullSink};
+pub use crate::ui_events::{EventPayload, EventSink};
+
 type Result<T> = std::result::Result<T, Error>;
 
 pub static STORE: OnceLock<Arc<Store<Wry>>> = OnceLock::new();
 pub static CACHE_DIR: OnceLock<Utf8PathBuf> = OnceLock::new();
+pub static DATA_DIR: OnceLock<Utf8PathBuf> = OnceLock::new();
 
 #[derive(Default)]
 struct AppStateInner {
     cancel: Mutex<Option<CancellationToken>>,
     history: Arc<Mutex<History>>,
+    conversation: Mutex<Option<OpenConversation>>,
+}
+type AppState<'a> = State<'a, Arc<AppStateInner>>;
+
+impl AppStateInner {
+    /// Saves the current history to its conversation, starting a new one under
+    /// the current root if none is open yet. Empty histories are not saved.
+    fn persist_conversation(&self) -> Result<()> {
+        let history = self.history.lock().unwrap().clone(); // unwrap: won't try to recover from poisoned lock
+        if history.inner.is_empty() {
+            return Ok(());
+        }
+        let open = {
+            let mut guard = self.conversation.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+            match guard.as_ref() {
+                Some(open) => open.clone(),
+                None => {
+                    let open = OpenConversation {
+                        id: conversations::new_id(),
+                        root_dir: get_root()?,
+                    };
+                    *guard = Some(open.clone());
+                    open
+                }
+            }
+        };
+        conversations::save(&open.root_dir, &open.id, &history)
+    }
+
+    /// Appends `content` as a user message, if any, and runs the agent until
+    /// it answers or the request is cancelled. `model_name` overrides the
+    /// model from the settings.
+    async fn chat(
+        &self,
+        ui: Arc<dyn EventSink>,
+        content: Vec<Content>,
+        model_name: Option<String>,
+    ) -> Result<()> {
+        self.run(ui, model_name, |history, ui| {
+            if !content.is_empty() {
+                let new_interaction = UIEvents::sends(content);
+                let _ = ui.emit_interaction(&new_interaction);
+                history.push(new_interaction);
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Claims the request, lets `prepare` change the history and runs the
+    /// agent until it answers or the request is cancelled. The history is
+    /// left alone if another request is running or `prepare` fails.
+    async fn run(
+        &self,
+        ui: Arc<dyn EventSink>,
+        model_name: Option<String>,
+        prepare: impl FnOnce(&mut History, &dyn EventSink) -> Result<()>,
+    ) -> Result<()> {
+        let settings = get_settings()?;
+        let backend = llm::backend_from_settings(&settings);
+        let options = ChatOptions {
+            model_name: model_name.unwrap_or(settings.model_name),
+        };
+        let cancel_token = CancellationToken::new();
+        {
+            let mut guard = self.cancel.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+            if guard.is_none() {
+                *guard = Some(cancel_token.clone());
+            } else {
+                return Err(Error::Conflict("more than 1 request".to_string()));
+            }
+        }
+
+        // unwrap: won't try to recover from poisoned lock
+        if let Err(e) = prepare(&mut self.history.lock().unwrap(), ui.as_ref()) {
+            *self.cancel.lock().unwrap() = None; // unwrap: won't try to recover from poisoned lock
+            return Err(e);
+        }
+        let _ = self.persist_conversation();
+        let proc = ChatProcessor::new(Arc::clone(&ui), options, Arc::clone(&self.history), backend);
+        let result = select! {
+            res = proc.run() => res,
+            _ = cancel_token.cancelled() => {
+                let _ = ui.emit_done();
+                self.history.lock().unwrap().clean_unfinished_tool_calls();
+                Ok(())
+            }
+        };
+        *self.cancel.lock().unwrap() = None; // unwrap: won't try to recover from poisoned lock
+        // The first answer to a regenerated message only now has siblings.
+        let _ = ui.emit_branches(&self.history.lock().unwrap()); // unwrap: won't try to recover from poisoned lock
+        self.persist_conversation()?;
+        result
+    }
+
+    /// Replaces the user message `id` with `content` and runs the agent from
+    /// there. The previous continuation is kept as a branch.
+    async fn edit_and_resend(
+        &self,
+        ui: Arc<dyn EventSink>,
+        id: usize,
+        content: Vec<Content>,
+    ) -> Result<()> {
+        let blank = |c: &Content| matches!(c, Content::Text { text } if text.trim().is_empty());
+        if content.iter().all(blank) {
+            return Err(Error::Conflict("the edited message is empty".to_string()));
+        }
+        self.run(ui, None, |history, ui| {
+            let is_user_message = history
+                .inner
+                .iter()
+                .any(|i| i.id() == id && matches!(i, Interaction::UserMessage { .. }));
+            if !is_user_message || !history.fork(id) {
+                return Err(Error::Conflict(format!("no user message {id} to edit")));
+            }
+            let new_interaction = UIEvents::sends(content);
+            let _ = ui.emit_interaction(&new_interaction);
+            history.push(new_interaction);
+            let _ = ui.emit_branches(history);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Runs the agent again on the last user message, with `model_name` if
+    /// given. The previous answer is kept as a branch.
+    async fn regenerate(&self, ui: Arc<dyn EventSink>, model_name: Option<String>) -> Result<()> {
+        self.run(ui, model_name, |history, _| {
+            history
+                .fork_answer_to_last_user_message()
+                .map(|_| ())
+                .ok_or_else(|| Error::Conflict("no user message to answer".to_string()))
+        })
+        .await
+    }
+
+    fn replay_history(&self, ui: &dyn EventSink) -> Result<()> {
+        let history = self.history.lock().unwrap().clone(); // unwrap: won't try to recover from poisoned lock
+        ui.replay_history(&history)
+    }
+
+    fn clear_history(&self) {
+        self.cancel_outstanding_request();
+        self.history.lock().unwrap().clear(); // unwrap: won't try to recover from poisoned lock
+        *self.conversation.lock().unwrap() = None; // unwrap: won't try to recover from poisoned lock
+    }
+
+    fn cancel_outstanding_request(&self) {
+        if let Some(cancel_token) = self.cancel.lock().unwrap().as_ref() {
+            // unwrap: won't try to recover from poisoned lock
+            cancel_token.cancel();
+        }
+    }
 }
-type AppState<'a> = State<'a, AppStateInner>;
 
 pub fn get_cache_dir() -> Result<Utf8PathBuf> {
-    let cache_dir = crate::CACHE_DIR
-        .get()
-        .ok_or(Error::Io(std::io::ErrorKind::NotFound.into()))?
-        .join("conversion_cache");
+    let cache_dir = crate::cache_root()?.join("conversion_cache");
     std::fs::create_dir_all(&cache_dir)?;
     Ok(cache_dir)
 }
 
+/// Stands in for both `CACHE_DIR` and `DATA_DIR` while a test harness is alive.
+#[cfg(test)]
+pub static DIR_OVERRIDE: std::sync::RwLock<Option<Utf8PathBuf>> = std::sync::RwLock::new(None);
+
+fn app_dir(dir: &OnceLock<Utf8PathBuf>) -> Result<Utf8PathBuf> {
+    #[cfg(test)]
+    if let Some(dir) = DIR_OVERRIDE.read().unwrap().clone() {
+        // unwrap: won't try to recover from poisoned lock
+        return Ok(dir);
+    }
+    dir.get()
+        .cloned()
+        .ok_or(Error::Io(std::io::ErrorKind::NotFound.into()))
+}
+
+pub fn cache_root() -> Result<Utf8PathBuf> {
+    app_dir(&CACHE_DIR)
+}
+
+pub fn get_data_dir() -> Result<Utf8PathBuf> {
+    let data_dir = app_dir(&DATA_DIR)?;
+    std::fs::create_dir_all(&data_dir)?;
+    Ok(data_dir)
+}
+
 #[tauri::command]
 async fn chat(window: tauri::Window, content: Vec<Content>, state: AppState<'_>) -> Result<()> {
-    let settings = get_settings()?;
-    let options = ChatOptions {
-        model_name: settings.model_name,
-    };
-    let cancel_token = CancellationToken::new();
-    {
-        let mut guard = state.cancel.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
-        if guard.is_none() {
-            *guard = Some(cancel_token.clone());
-        } else {
-            return Err(Error::Conflict("more than 1 request".to_string()));
-        }
-    }
+    state
+        .chat(Arc::new(UIEvents::new(window)), content, None)
+        .await
+}
 
-    let replayer = UIEvents::new(window.clone());
-    if !content.is_empty() {
-        let new_interaction = UIEvents::sends(content);
-        let _ = replayer.emit_interaction(&new_interaction);
-        state.history.lock().unwrap().push(new_interaction); // unwrap: won't try to recover from poisoned lock
-    }
-    let proc = ChatProcessor::new(window.clone(), options, Arc::clone(&state.history));
-    select! {
-        Ok(_) = {proc.run()} => {}
-        _ = cancel_token.cancelled() => {
-            let _ = replayer.emit_done();
-            state.history.lock().unwrap().clean_unfinished_tool_calls();
-        }
+#[tauri::command]
+async fn regenerate(
+    window: tauri::Window,
+    model_name: Option<String>,
+    state: AppState<'_>,
+) -> Result<()> {
+    state
+        .regenerate(Arc::new(UIEvents::new(window)), model_name)
+        .await
+}
+
+#[tauri::command]
+async fn edit_message(
+    window: tauri::Window,
+    id: usize,
+    content: Vec<Content>,
+    state: AppState<'_>,
+) -> Result<()> {
+    state
+        .edit_and_resend(Arc::new(UIEvents::new(window)), id, content)
+        .await
+}
+
+#[tauri::command]
+fn switch_branch(id: usize, state: AppState<'_>) -> Result<()> {
+    state.cancel_outstanding_request();
+    if !state.history.lock().unwrap().switch_branch(id) {
+        // unwrap: won't try to recover from poisoned lock
+        return Err(Error::Conflict(format!("no branch starts at {id}")));
     }
-    *state.cancel.lock().unwrap() = None; // unwrap: won't try to recover from poisoned lock
-    Ok(())
+    state.persist_conversation()
 }
+
+/// Writes the open conversation to `path` as a report or transcript.
 #[tauri::command]
-async fn replay_history(window: tauri::Window, state: AppState<'_>) -> Result<()> {
+fn export_conversation(format: ExportFormat, path: String, state: AppState<'_>) -> Result<()> {
     let history = state.history.lock().unwrap().clone(); // unwrap: won't try to recover from poisoned lock
-    UIEvents::new(window).replay_history(&history)?;
+    let open = state.conversation.lock().unwrap().clone(); // unwrap: won't try to recover from poisoned lock
+    let title = open
+        .and_then(|open| conversations::load(&open.root_dir, &open.id).ok())
+        .map(|conversation| conversation.title)
+        .unwrap_or_else(|| conversations::title_from_history(&history));
+    std::fs::write(path, export::export(format, &title, &history)?)?;
     Ok(())
 }
 
+#[tauri::command]
+async fn replay_history(window: tauri::Window, state: AppState<'_>) -> Result<()> {
+    state.replay_history(&UIEvents::new(window))
+}
+
 #[tauri::command]
 fn clear_history(state: AppState<'_>) -> Result<()> {
-    cancel_outstanding_request(state.clone())?;
-    let mut history = state.history.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
-    history.clear();
+    state.clear_history();
     Ok(())
 }
 
 #[tauri::command]
 fn cancel_outstanding_request(state: AppState<'_>) -> Result<()> {
-    if let Some(cancel_token) = state.cancel.lock().unwrap().as_ref() {
-        // unwrap: won't try to recover from poisoned lock
-        cancel_token.cancel();
-    }
+    state.cancel_outstanding_request();
     Ok(())
 }
 
 #[tauri::command]
 fn delete_message(id: usize, state: AppState<'_>) -> Result<()> {
-    cancel_outstanding_request(state.clone())?;
+    state.cancel_outstanding_request();
     state.history.lock().unwrap().delete_by_id(id);
-    Ok(())
+    state.persist_conversation()
 }
 
 #[tauri::command]
@@ -113,15 +322,66 @@ fn delete_tool_interaction(
     tool_call_id: String,
     state: AppState<'_>,
 ) -> Result<()> {
-    cancel_outstanding_request(state.clone())?;
+    state.cancel_outstanding_request();
     state
         .history
         .lock()
         .unwrap()
         .delete_by_tool_id(llm_interaction_id, &tool_call_id);
+    state.persist_conversation()
+}
+
+#[tauri::command]
+fn open_conversation(id: String, state: AppState<'_>) -> Result<()> {
+    state.cancel_outstanding_request();
+    let root_dir = get_root()?;
+    let conversation = conversations::load(&root_dir, &id)?;
+    *state.history.lock().unwrap() = conversation.history; // unwrap: won't try to recover from poisoned lock
+    *state.conversation.lock().unwrap() = Some(OpenConversation { id, root_dir }); // unwrap: won't try to recover from poisoned lock
     Ok(())
 }
 
+/// Opens a transcript, e.g. one exported by a teammate, as a new
+/// conversation under the current root. Returns its id.
+#[tauri::command]
+fn import_transcript(path: String, state: AppState<'_>) -> Result<String> {
+    let (title, history) = export::import(&std::fs::read(path)?)?;
+    state.clear_history();
+    let open = OpenConversation {
+        id: conversations::new_id(),
+        root_dir: get_root()?,
+    };
+    *state.history.lock().unwrap() = history; // unwrap: won't try to recover from poisoned lock
+    *state.conversation.lock().unwrap() = Some(open.clone()); // unwrap: won't try to recover from poisoned lock
+    state.persist_conversation()?;
+    conversations::rename(&open.root_dir, &open.id, &title)?;
+    Ok(open.id)
+}
+
+#[tauri::command]
+fn current_conversation(state: AppState<'_>) -> Option<String> {
+    state
+        .conversation
+        .lock()
+        .unwrap() // unwrap: won't try to recover from poisoned lock
+        .as_ref()
+        .map(|c| c.id.clone())
+}
+
+#[tauri::command]
+fn delete_conversation(id: String, state: AppState<'_>) -> Result<()> {
+    let is_open = state
+        .conversation
+        .lock()
+        .unwrap() // unwrap: won't try to recover from poisoned lock
+        .as_ref()
+        .is_some_and(|c| c.id == id);
+    if is_open {
+        state.clear_history();
+    }
+    conversations::delete(&get_root()?, &id)
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -132,12 +392,27 @@ pub fn run() {
             settings::get_settings,
             settings::set_settings,
             chat,
+            edit_message,
+            switch_branch,
+            regenerate,
+            export_conversation,
+            import_transcript,
             replay_history,
             clear_history,
             cancel_outstanding_request,
             delete_message,
             delete_tool_interaction,
+            open_conversation,
+            current_conversation,
+            delete_conversation,
+            conversations::list_conversations,
+            conversations::rename_conversation,
             search::search_files_by_name_interactive,
+            content_index::search_content,
+            semantic::semantic_search,
+            ask_cache::ask_cache_list,
+            ask_cache::ask_cache_clear,
+            usage::usage_report,
             search::selection_add,
             search::selection_remove,
             search::selection_clear,
@@ -151,10 +426,103 @@ pub fn run() {
             CACHE_DIR.get_or_init(
                 || Utf8Path::new(&app.path().app_cache_dir().unwrap().to_string_lossy()).to_owned(), // unwrap: crash if cannot find cache dir
             );
-            let inner_state = AppStateInner::default();
+            DATA_DIR.get_or_init(
+                || Utf8Path::new(&app.path().app_data_dir().unwrap().to_string_lossy()).to_owned(), // unwrap: crash if cannot find data dir
+            );
+            let inner_state = Arc::new(AppStateInner::default());
+            if let Ok(settings) = get_settings()
+                && settings.server_enabled
+            {
+                let state = Arc::clone(&inner_state);
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = server::start(state, &settings).await {
+                        eprintln!("API server stopped: {e}");
+                    }
+                });
+            }
             app.manage(inner_state);
             Ok(())
         })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AppStateInner;
+    use crate::error::Error;
+    use crate::interaction::{Content, Interaction};
+    use crate::test_support::Harness;
+    use tokio_util::sync::CancellationToken;
+
+    fn words(text: &str) -> Vec<Content> {
+        vec![Content::Text {
+            text: text.to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn edit_leaves_history_alone_when_refused() {
+        let harness = Harness::new().await;
+        let state = AppStateInner::default();
+        let question = Interaction::user_message(words("Hello"));
+        let id = question.id();
+        state.history.lock().unwrap().push(question);
+        let unchanged = |state: &AppStateInner| {
+            let history = state.history.lock().unwrap();
+            history.inner.len() == 1 && history.forks().is_empty()
+        };
+
+        let result = state
+            .edit_and_resend(harness.sink.clone(), id, words(" "))
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+        assert!(unchanged(&state));
+
+        // Another request holds the claim, so nothing is forked.
+        *state.cancel.lock().unwrap() = Some(CancellationToken::new());
+        let result = state
+            .edit_and_resend(harness.sink.clone(), id, words("Hi"))
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+        assert!(unchanged(&state));
+
+        // An unknown message releases the claim it took.
+        *state.cancel.lock().unwrap() = None;
+        let result = state
+            .edit_and_resend(harness.sink.clone(), id + 1, words("Hi"))
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+        assert!(unchanged(&state));
+        assert!(state.cancel.lock().unwrap().is_none());
+        assert!(harness.sink.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn regenerate_leaves_history_alone_when_refused() {
+        let harness = Harness::new().await;
+        let state = AppStateInner::default();
+        let result = state.regenerate(harness.sink.clone(), None).await;
+        assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+        assert!(state.cancel.lock().unwrap().is_none());
+
+        // Another request holds the claim, so the answer is not forked.
+        state
+            .history
+            .lock()
+            .unwrap()
+            .push(Interaction::user_message(words("Hello")));
+        state
+            .history
+            .lock()
+            .unwrap()
+            .push(Interaction::llm_response(words("Hi"), None));
+        *state.cancel.lock().unwrap() = Some(CancellationToken::new());
+        let result = state.regenerate(harness.sink.clone(), None).await;
+        assert!(matches!(result, Err(Error::Conflict(_))), "{result:?}");
+        let history = state.history.lock().unwrap();
+        assert_eq!(history.inner.len(), 2);
+        assert!(history.branches.is_empty());
+        assert!(harness.sink.events().is_empty());
+    }
+}
@@ -0,0 +1,534 @@
+use crate::Result;
+use crate::budget;
+use crate::error::Error;
+use crate::openrouter::{ChatResponse, Openrouter, StreamAccumulator, StreamDelta};
+use crate::settings::{Backend, Settings, get_settings};
+use crate::tools;
+use crate::usage;
+use crate::utils::debug_log;
+use futures::StreamExt;
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Value, json, to_string_pretty};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+const OLLAMA_BASE_URL: &str = "http://localhost:11434/v1";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Longest silence tolerated while waiting for, or streaming, a response.
+const READ_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT)
+        .build()
+        .unwrap() // unwrap: crash if the TLS backend cannot be initialized
+});
+
+/// HTTP client shared by every call, so connections are pooled.
+pub fn client() -> &'static reqwest::Client {
+    &CLIENT
+}
+
+/// A chat completion endpoint speaking the OpenAI wire format.
+///
+/// Implementors only need to describe where and how to send a request; the
+/// HTTP exchange itself is shared. Test doubles can override `complete` and
+/// `complete_streaming` to answer without a server.
+pub trait LlmBackend: Send + Sync {
+    /// Full URL of the chat completions endpoint.
+    fn endpoint(&self) -> String;
+
+    fn api_key(&self) -> Option<String>;
+
+    /// Adds backend specific fields to the request body.
+    fn prepare(&self, _body: &mut Value) {}
+
+    fn complete(&self, body: Value) -> BoxFuture<'_, Result<ChatResponse>> {
+        Box::pin(post(self.endpoint(), self.api_key(), body))
+    }
+
+    fn complete_streaming<'a>(
+        &'a self,
+        body: Value,
+        on_delta: &'a mut (dyn FnMut(StreamDelta) + Send),
+    ) -> BoxFuture<'a, Result<ChatResponse>> {
+        Box::pin(post_streaming(
+            self.endpoint(),
+            self.api_key(),
+            body,
+            on_delta,
+        ))
+    }
+}
+
+/// Any server exposing `/chat/completions`, e.g. vLLM, LiteLLM or an in-house gateway.
+pub struct OpenAiCompatible {
+    pub base_url: String,
+    pub api_key: String,
+}
+
+impl LlmBackend for OpenAiCompatible {
+    fn endpoint(&self) -> String {
+        format!("{}/chat/completions", self.base_url.trim_end_matches('/'))
+    }
+
+    fn api_key(&self) -> Option<String> {
+        (!self.api_key.is_empty()).then(|| self.api_key.clone())
+    }
+}
+
+/// A local Ollama or llama.cpp server through its OpenAI compatible API.
+pub struct Ollama {
+    pub base_url: String,
+}
+
+impl LlmBackend for Ollama {
+    fn endpoint(&self) -> String {
+        let base_url = match self.base_url.as_str() {
+            "" => OLLAMA_BASE_URL,
+            url => url,
+        };
+        format!("{}/chat/completions", base_url.trim_end_matches('/'))
+    }
+
+    fn api_key(&self) -> Option<String> {
+        None
+    }
+}
+
+pub fn backend_from_settings(settings: &Settings) -> Arc<dyn LlmBackend> {
+    match settings.backend {
+        Backend::Openrouter => Arc::new(Openrouter {
+            api_key: settings.api_key.clone(),
+            provider_order: settings.provider_order.clone(),
+        }),
+        Backend::OpenaiCompatible => Arc::new(OpenAiCompatible {
+            base_url: settings.base_url.clone(),
+            api_key: settings.api_key.clone(),
+        }),
+        Backend::Ollama => Arc::new(Ollama {
+            base_url: settings.base_url.clone(),
+        }),
+    }
+}
+
+pub fn backend() -> Result<Arc<dyn LlmBackend>> {
+    Ok(backend_from_settings(&get_settings()?))
+}
+
+fn request_body(
+    backend: &dyn LlmBackend,
+    messages: &[Value],
+    model_name: &str,
+    system_prompt: &str,
+    tools: &Vec<tools::Tool>,
+) -> Value {
+    debug_log!(
+        "Sending messages to {}: {}",
+        backend.endpoint(),
+        messages
+            .iter()
+            .map(|j| to_string_pretty(j).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    );
+    let mut final_messages = messages.to_vec();
+    if !system_prompt.is_empty() {
+        final_messages.insert(0, json!({"role":"system","content":system_prompt}));
+    }
+    let mut body = json!({
+        "model": model_name,
+        "messages": final_messages,
+        "tools": tools,
+    });
+    backend.prepare(&mut body);
+    body
+}
+
+/// Sends a completion request to the backend configured in settings.
+pub async fn call(
+    messages: &[Value],
+    model_name: &str,
+    system_prompt: &str,
+    tools: &Vec<tools::Tool>,
+    schema: Option<Value>,
+) -> Result<ChatResponse> {
+    call_with(
+        backend()?.as_ref(),
+        messages,
+        model_name,
+        system_prompt,
+        tools,
+        schema,
+        None,
+    )
+    .await
+}
+
+pub async fn call_with(
+    backend: &dyn LlmBackend,
+    messages: &[Value],
+    model_name: &str,
+    system_prompt: &str,
+    tools: &Vec<tools::Tool>,
+    schema: Option<Value>,
+    temperature: Option<f64>,
+) -> Result<ChatResponse> {
+    let mut body = request_body(backend, messages, model_name, system_prompt, tools);
+    if let Some(temperature) = temperature
+        && let Some(m) = body.as_object_mut()
+    {
+        m.insert("temperature".to_string(), json!(temperature));
+    }
+    if let Some(schema) = schema
+        && let Some(m) = body.as_object_mut()
+    {
+        m.insert(
+            "response_format".to_string(),
+            json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "output",
+                    "strict": true,
+                    "schema": schema
+                }
+            }),
+        );
+    }
+    let reservation = budget::reserve()?;
+    Ok(account(reservation, backend.complete(body).await?))
+}
+
+/// Same as `call_with`, but requests a streamed completion and hands every
+/// delta to `on_delta` as it arrives. The assembled message is returned
+/// once the stream ends.
+pub async fn call_streaming(
+    backend: &dyn LlmBackend,
+    messages: &[Value],
+    model_name: &str,
+    system_prompt: &str,
+    tools: &Vec<tools::Tool>,
+    on_delta: &mut (dyn FnMut(StreamDelta) + Send),
+) -> Result<ChatResponse> {
+    let mut body = request_body(backend, messages, model_name, system_prompt, tools);
+    if let Some(m) = body.as_object_mut() {
+        m.insert("stream".to_string(), json!(true));
+        m.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+    let reservation = budget::reserve()?;
+    Ok(account(
+        reservation,
+        backend.complete_streaming(body, on_delta).await?,
+    ))
+}
+
+/// Counts the completion, charges it to the enclosing usage scope and
+/// settles its budget reservation.
+fn account(reservation: budget::Reservation, mut res: ChatResponse) -> ChatResponse {
+    res.usage.calls = 1;
+    usage::charge(res.usage);
+    reservation.settle(res.usage);
+    res
+}
+
+#[derive(Deserialize)]
+struct ApiErrorBody {
+    error: ApiErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ApiErrorDetail {
+    message: String,
+}
+
+/// Reads the `{"error": {"message": ..}}` body OpenAI-style APIs send, falling
+/// back to the raw text.
+fn api_error(status: StatusCode, text: &str) -> Error {
+    let message = match serde_json::from_str::<ApiErrorBody>(text) {
+        Ok(body) => body.error.message,
+        Err(_) => text.trim().chars().take(500).collect(),
+    };
+    Error::Api {
+        status: status.as_u16(),
+        message,
+    }
+}
+
+fn parse_response(status: StatusCode, text: &str) -> Result<ChatResponse> {
+    match serde_json::from_str::<ChatResponse>(text) {
+        Ok(res) if !res.choices.is_empty() => Ok(res),
+        _ => Err(api_error(status, text)),
+    }
+}
+
+/// `Retry-After` as seconds or an HTTP date.
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    let value = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.to_utc() - chrono::Utc::now()).to_std().ok()
+}
+
+/// Exponential, with up to 50% jitter so parallel workers spread out.
+fn backoff(attempt: u32) -> Duration {
+    BASE_DELAY
+        .saturating_mul(1 << (attempt - 1))
+        .mul_f64(1.0 + rand::random::<f64>() / 2.0)
+}
+
+/// Sends the request, retrying network errors, 429 and 5xx responses with
+/// exponential backoff or as long as `Retry-After` asks. Responses that are
+/// still unsuccessful become `Error::Api`.
+pub async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let this_try = request.try_clone().ok_or(Error::Other)?;
+        let delay = match this_try.send().await {
+            Ok(res) if res.status().is_success() => return Ok(res),
+            Ok(res) => {
+                let status = res.status();
+                if !is_retryable(status) || attempt == MAX_ATTEMPTS {
+                    return Err(api_error(status, &res.text().await.unwrap_or_default()));
+                }
+                retry_after(&res).unwrap_or_else(|| backoff(attempt))
+            }
+            Err(e)
+                if attempt < MAX_ATTEMPTS
+                    && (e.is_connect() || e.is_timeout() || e.is_request()) =>
+            {
+                backoff(attempt)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let delay = delay.min(MAX_DELAY);
+        eprintln!("Request attempt {attempt} failed, retrying in {delay:?}");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+fn request(endpoint: &str, api_key: Option<String>, body: &Value) -> reqwest::RequestBuilder {
+    let builder = client().post(endpoint).json(body);
+    match api_key {
+        Some(key) => builder.bearer_auth(key),
+        None => builder,
+    }
+}
+
+async fn post(endpoint: String, api_key: Option<String>, body: Value) -> Result<ChatResponse> {
+    let res = send(request(&endpoint, api_key, &body)).await?;
+    let status = res.status();
+    let text = res.text().await?;
+    debug_log!("Got response from {endpoint}: {text}",);
+    parse_response(status, &text)
+}
+
+/// 429 and 5xx are worth trying again.
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Streams the completion. A stream the provider ends with a retryable
+/// error is sent again, as long as none of it was handed out yet.
+async fn post_streaming(
+    endpoint: String,
+    api_key: Option<String>,
+    body: Value,
+    on_delta: &mut (dyn FnMut(StreamDelta) + Send),
+) -> Result<ChatResponse> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let mut delivered = false;
+        let result = stream_once(&endpoint, api_key.clone(), &body, &mut |delta| {
+            delivered = true;
+            on_delta(delta);
+        })
+        .await;
+        match result {
+            Err(Error::Api { status, .. })
+                if !delivered
+                    && attempt < MAX_ATTEMPTS
+                    && StatusCode::from_u16(status).is_ok_and(is_retryable) =>
+            {
+                let delay = backoff(attempt).min(MAX_DELAY);
+                eprintln!("Stream attempt {attempt} failed, retrying in {delay:?}");
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn stream_once(
+    endpoint: &str,
+    api_key: Option<String>,
+    body: &Value,
+    on_delta: &mut (dyn FnMut(StreamDelta) + Send),
+) -> Result<ChatResponse> {
+    let res = send(request(endpoint, api_key, body)).await?;
+
+    let is_event_stream = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    if !is_event_stream {
+        let status = res.status();
+        let text = res.text().await?;
+        debug_log!("Got non-streamed response from {endpoint}: {text}",);
+        return parse_response(status, &text);
+    }
+
+    let mut accumulator = StreamAccumulator::default();
+    let mut pending = vec![];
+    let mut stream = res.bytes_stream();
+    while let Some(bytes) = stream.next().await {
+        // A multi-byte character may be split across network chunks.
+        pending.extend_from_slice(&bytes?);
+        let valid_up_to = match std::str::from_utf8(&pending) {
+            Ok(s) => s.len(),
+            Err(e) => e.valid_up_to(),
+        };
+        let text = String::from_utf8_lossy(&pending[..valid_up_to]).into_owned();
+        pending.drain(..valid_up_to);
+        for delta in accumulator.feed(&text) {
+            on_delta(delta);
+        }
+    }
+    let response = accumulator.into_response();
+    debug_log!("Got streamed response from {endpoint}: {response:?}");
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{post, post_streaming};
+    use crate::error::Error;
+    use crate::openrouter::IncomingContent;
+    use serde_json::json;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers each connection with the next canned response, counting requests.
+    async fn serve(responses: Vec<String>) -> (String, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/chat/completions", listener.local_addr().unwrap());
+        let served = Arc::new(Mutex::new(0));
+        let count = Arc::clone(&served);
+        let mut responses = VecDeque::from(responses);
+        tokio::spawn(async move {
+            while let Some(response) = responses.pop_front() {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                // Read the headers and the body announced by Content-Length.
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                *count.lock().unwrap() += 1;
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+        (endpoint, served)
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn post_retries_then_reports_api_errors() {
+        let ok = json!({"choices": [{"message": {"role": "assistant", "content": "hi"}}]});
+        let (endpoint, served) = serve(vec![
+            response("429 Too Many Requests", "Retry-After: 0\r\n", "{}"),
+            response("503 Service Unavailable", "Retry-After: 0\r\n", "busy"),
+            response("200 OK", "", &ok.to_string()),
+        ])
+        .await;
+        let res = post(endpoint, None, json!({})).await.unwrap();
+        assert!(matches!(&res.choices[0].message.content, IncomingContent::Text(t) if t == "hi"));
+        assert_eq!(*served.lock().unwrap(), 3);
+
+        let (endpoint, served) = serve(vec![response(
+            "400 Bad Request",
+            "",
+            r#"{"error": {"code": 400, "message": "no such model"}}"#,
+        )])
+        .await;
+        let err = post(endpoint, None, json!({})).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::Api { status: 400, message } if message == "no such model"),
+            "{err:?}"
+        );
+        assert_eq!(*served.lock().unwrap(), 1);
+    }
+
+    fn event_stream(events: &[&str]) -> String {
+        let body: String = events.iter().map(|e| format!("data: {e}\n\n")).collect();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn post_streaming_fails_on_error_chunks() {
+        let busy = r#"{"error": {"code": 503, "message": "busy"}}"#;
+        let hi = r#"{"choices": [{"delta": {"content": "hi"}}]}"#;
+
+        // Nothing was shown yet, so the request is sent again.
+        let (endpoint, served) =
+            serve(vec![event_stream(&[busy]), event_stream(&[hi, "[DONE]"])]).await;
+        let mut deltas = 0;
+        let res = post_streaming(endpoint, None, json!({}), &mut |_| deltas += 1)
+            .await
+            .unwrap();
+        assert!(matches!(&res.choices[0].message.content, IncomingContent::Text(t) if t == "hi"));
+        assert_eq!((deltas, *served.lock().unwrap()), (1, 2));
+
+        // Part of the answer is out, so the error is reported instead.
+        let (endpoint, served) = serve(vec![event_stream(&[hi, busy])]).await;
+        let err = post_streaming(endpoint, None, json!({}), &mut |_| {})
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, Error::Api { status: 503, message } if message == "busy"),
+            "{err:?}"
+        );
+        assert_eq!(*served.lock().unwrap(), 1);
+    }
+}
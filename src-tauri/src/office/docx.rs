@@ -0,0 +1,506 @@
+use super::{Package, Relationship, attr, child, emphasize, is, parse, rel_attr, table_cell};
+use crate::Result;
+use crate::error::Error;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+
+const DOCUMENT: &str = "word/document.xml";
+
+/// Renders a Word document as Markdown.
+///
+/// Footnotes, endnotes and comments become footnote references with their
+/// definitions at the end. Tracked changes are kept as CriticMarkup, i.e.
+/// `{++inserted++}` and `{--deleted--}`.
+pub fn to_markdown(file_buffer: &[u8]) -> Result<String> {
+    let mut package = Package::open(file_buffer)?;
+    let document = package
+        .part(DOCUMENT)?
+        .ok_or(Error::Tool("not a Word document".to_string()))?;
+    let styles = package.part("word/styles.xml")?;
+    let numbering = package.part("word/numbering.xml")?;
+    let footnotes = package.part("word/footnotes.xml")?;
+    let endnotes = package.part("word/endnotes.xml")?;
+    let comments = package.part("word/comments.xml")?;
+    let links = package.relationships(DOCUMENT)?;
+
+    let document = parse(&document)?;
+    let styles = styles.as_deref().map(parse).transpose()?;
+    let numbering = numbering.as_deref().map(parse).transpose()?;
+    let footnotes = footnotes.as_deref().map(parse).transpose()?;
+    let endnotes = endnotes.as_deref().map(parse).transpose()?;
+    let comments = comments.as_deref().map(parse).transpose()?;
+
+    let body = child(document.root_element(), "body")
+        .ok_or(Error::Tool("Word document has no body".to_string()))?;
+    let mut converter = Converter {
+        headings: heading_styles(styles.as_ref()),
+        list_formats: list_formats(numbering.as_ref()),
+        links,
+        footnotes: notes_by_id(footnotes.as_ref(), "footnote"),
+        endnotes: notes_by_id(endnotes.as_ref(), "endnote"),
+        comments: notes_by_id(comments.as_ref(), "comment"),
+        counters: HashMap::new(),
+        definitions: vec![],
+    };
+    let blocks = converter.blocks(body);
+    let mut out = join_blocks(&blocks);
+    if !converter.definitions.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&converter.definitions.join("\n"));
+    }
+    Ok(out)
+}
+
+/// Heading level by paragraph style id.
+fn heading_styles(styles: Option<&Document>) -> HashMap<String, usize> {
+    let Some(styles) = styles else {
+        return HashMap::new();
+    };
+    styles
+        .root_element()
+        .children()
+        .filter(|n| is(*n, "style") && attr(*n, "type") == Some("paragraph"))
+        .filter_map(|style| {
+            let id = attr(style, "styleId")?;
+            let name = child(style, "name")
+                .and_then(|n| attr(n, "val"))
+                .unwrap_or_default()
+                .to_lowercase();
+            let outline = child(style, "pPr")
+                .and_then(|p| child(p, "outlineLvl"))
+                .and_then(|o| attr(o, "val"))
+                .and_then(|v| v.parse::<usize>().ok());
+            let level = match name.strip_prefix("heading ") {
+                Some(n) => n.parse().ok(),
+                None if name == "title" => Some(1),
+                None => outline.filter(|l| *l < 9).map(|l| l + 1),
+            }?;
+            Some((id.to_string(), level.clamp(1, 6)))
+        })
+        .collect()
+}
+
+/// Whether each (numId, level) pair is a numbered rather than a bulleted list.
+fn list_formats(numbering: Option<&Document>) -> HashMap<(String, usize), bool> {
+    let Some(numbering) = numbering else {
+        return HashMap::new();
+    };
+    let root = numbering.root_element();
+    let abstract_formats: HashMap<&str, HashMap<usize, bool>> = root
+        .children()
+        .filter(|n| is(*n, "abstractNum"))
+        .filter_map(|abs| {
+            let levels = abs
+                .children()
+                .filter(|n| is(*n, "lvl"))
+                .filter_map(|lvl| {
+                    let level = attr(lvl, "ilvl")?.parse().ok()?;
+                    let format = child(lvl, "numFmt").and_then(|f| attr(f, "val"));
+                    Some((
+                        level,
+                        !matches!(format, Some("bullet") | Some("none") | None),
+                    ))
+                })
+                .collect();
+            Some((attr(abs, "abstractNumId")?, levels))
+        })
+        .collect();
+    let mut out = HashMap::new();
+    for num in root.children().filter(|n| is(*n, "num")) {
+        let (Some(num_id), Some(abstract_id)) = (
+            attr(num, "numId"),
+            child(num, "abstractNumId").and_then(|a| attr(a, "val")),
+        ) else {
+            continue;
+        };
+        for (level, ordered) in abstract_formats.get(abstract_id).into_iter().flatten() {
+            out.insert((num_id.to_string(), *level), *ordered);
+        }
+    }
+    out
+}
+
+/// Footnotes, endnotes or comments by id, leaving out separators.
+fn notes_by_id<'a, 'input>(
+    doc: Option<&'a Document<'input>>,
+    tag: &str,
+) -> HashMap<String, Node<'a, 'input>> {
+    let Some(doc) = doc else {
+        return HashMap::new();
+    };
+    doc.root_element()
+        .children()
+        .filter(|n| is(*n, tag) && attr(*n, "type").is_none_or(|t| t == "normal"))
+        .filter_map(|n| Some((attr(n, "id")?.to_string(), n)))
+        .collect()
+}
+
+fn toggled(props: Option<Node>, name: &str) -> bool {
+    props
+        .and_then(|p| child(p, name))
+        .is_some_and(|n| !matches!(attr(n, "val"), Some("0") | Some("false") | Some("off")))
+}
+
+struct Block {
+    text: String,
+    list_item: bool,
+}
+
+/// Paragraphs are separated by a blank line, consecutive list items by a newline.
+fn join_blocks(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    let mut prev_list_item = false;
+    for block in blocks {
+        if !out.is_empty() {
+            out.push_str(if prev_list_item && block.list_item {
+                "\n"
+            } else {
+                "\n\n"
+            });
+        }
+        out.push_str(&block.text);
+        prev_list_item = block.list_item;
+    }
+    out
+}
+
+struct Converter<'a, 'input> {
+    headings: HashMap<String, usize>,
+    list_formats: HashMap<(String, usize), bool>,
+    links: HashMap<String, Relationship>,
+    footnotes: HashMap<String, Node<'a, 'input>>,
+    endnotes: HashMap<String, Node<'a, 'input>>,
+    comments: HashMap<String, Node<'a, 'input>>,
+    /// Running item numbers of numbered lists, by (numId, level).
+    counters: HashMap<(String, usize), usize>,
+    /// Footnote style definitions, in order of first reference.
+    definitions: Vec<String>,
+}
+
+impl<'a, 'input> Converter<'a, 'input> {
+    fn blocks(&mut self, container: Node) -> Vec<Block> {
+        let mut out = vec![];
+        for node in container.children().filter(|n| n.is_element()) {
+            match node.tag_name().name() {
+                "p" => {
+                    if let Some(block) = self.paragraph(node) {
+                        out.push(block);
+                    }
+                }
+                "tbl" => out.push(Block {
+                    text: self.table(node),
+                    list_item: false,
+                }),
+                "sdt" => {
+                    if let Some(content) = child(node, "sdtContent") {
+                        out.extend(self.blocks(content));
+                    }
+                }
+                "customXml" | "ins" | "moveTo" => out.extend(self.blocks(node)),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn paragraph(&mut self, p: Node) -> Option<Block> {
+        let text = self.inline(p);
+        let text = text.trim_end();
+        if text.trim().is_empty() {
+            return None;
+        }
+        let props = child(p, "pPr");
+        let style = props
+            .and_then(|p| child(p, "pStyle"))
+            .and_then(|s| attr(s, "val"))
+            .unwrap_or_default();
+        let level = self.headings.get(style).copied().or_else(|| {
+            let n = style.to_lowercase().strip_prefix("heading")?.parse().ok()?;
+            Some(usize::clamp(n, 1, 6))
+        });
+        if let Some(level) = level {
+            return Some(Block {
+                text: format!("{} {}", "#".repeat(level), text.trim()),
+                list_item: false,
+            });
+        }
+        let numbering = props.and_then(|p| child(p, "numPr"));
+        let num_id = numbering
+            .and_then(|n| child(n, "numId"))
+            .and_then(|n| attr(n, "val"))
+            .filter(|id| *id != "0");
+        if let Some(num_id) = num_id {
+            let ilvl = numbering
+                .and_then(|n| child(n, "ilvl"))
+                .and_then(|n| attr(n, "val"))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            let key = (num_id.to_string(), ilvl);
+            let marker = if self.list_formats.get(&key).copied().unwrap_or(false) {
+                // A new item restarts the numbering of deeper levels.
+                self.counters
+                    .retain(|(id, level), _| id != num_id || *level <= ilvl);
+                let counter = self.counters.entry(key).or_default();
+                *counter += 1;
+                format!("{counter}.")
+            } else {
+                "-".to_string()
+            };
+            return Some(Block {
+                text: format!("{}{marker} {}", "  ".repeat(ilvl), text.trim()),
+                list_item: true,
+            });
+        }
+        Some(Block {
+            text: text.to_string(),
+            list_item: false,
+        })
+    }
+
+    fn table(&mut self, tbl: Node) -> String {
+        let mut rows: Vec<Vec<String>> = vec![];
+        for tr in tbl.children().filter(|n| is(*n, "tr")) {
+            let mut cells = vec![];
+            for tc in tr.children().filter(|n| is(*n, "tc")) {
+                let props = child(tc, "tcPr");
+                let span = props
+                    .and_then(|p| child(p, "gridSpan"))
+                    .and_then(|s| attr(s, "val"))
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1usize);
+                let merged = props
+                    .and_then(|p| child(p, "vMerge"))
+                    .is_some_and(|m| attr(m, "val") != Some("restart"));
+                if merged {
+                    cells.push(String::new());
+                } else {
+                    cells.push(table_cell(&join_blocks(&self.blocks(tc))));
+                }
+                cells.extend(std::iter::repeat_n(String::new(), span.saturating_sub(1)));
+            }
+            rows.push(cells);
+        }
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if width == 0 {
+            return String::new();
+        }
+        let mut lines = vec![];
+        for (i, row) in rows.iter_mut().enumerate() {
+            row.resize(width, String::new());
+            lines.push(format!("| {} |", row.join(" | ")));
+            if i == 0 {
+                lines.push(format!("|{}", " --- |".repeat(width)));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Text of runs and run-level containers, with adjacent runs of the same
+    /// emphasis merged so markers don't pile up.
+    fn inline(&mut self, node: Node) -> String {
+        let mut out = String::new();
+        let mut pending = String::new();
+        let mut pending_style = (false, false);
+        for n in node.children().filter(|n| n.is_element()) {
+            if is(n, "r") {
+                let props = child(n, "rPr");
+                let style = (toggled(props, "b"), toggled(props, "i"));
+                let text = self.run(n);
+                if style != pending_style {
+                    out.push_str(&emphasize(&pending, pending_style.0, pending_style.1));
+                    pending.clear();
+                    pending_style = style;
+                }
+                pending.push_str(&text);
+                continue;
+            }
+            out.push_str(&emphasize(&pending, pending_style.0, pending_style.1));
+            pending.clear();
+            match n.tag_name().name() {
+                "hyperlink" => {
+                    let text = self.inline(n);
+                    let target = rel_attr(n, "id")
+                        .and_then(|id| self.links.get(id))
+                        .filter(|rel| rel.rel_type == "hyperlink")
+                        .map(|rel| rel.target.clone())
+                        .or_else(|| attr(n, "anchor").map(|a| format!("#{a}")));
+                    match target {
+                        Some(target) if !text.trim().is_empty() => {
+                            out.push_str(&format!("[{text}]({target})"))
+                        }
+                        _ => out.push_str(&text),
+                    }
+                }
+                "ins" | "moveTo" => {
+                    let text = self.inline(n);
+                    if !text.is_empty() {
+                        out.push_str(&format!("{{++{text}++}}"));
+                    }
+                }
+                "del" | "moveFrom" => {
+                    let text = self.inline(n);
+                    if !text.is_empty() {
+                        out.push_str(&format!("{{--{text}--}}"));
+                    }
+                }
+                "smartTag" | "customXml" | "fldSimple" | "sdtContent" => {
+                    out.push_str(&self.inline(n))
+                }
+                "sdt" => {
+                    if let Some(content) = child(n, "sdtContent") {
+                        out.push_str(&self.inline(content));
+                    }
+                }
+                _ => {}
+            }
+        }
+        out.push_str(&emphasize(&pending, pending_style.0, pending_style.1));
+        out
+    }
+
+    fn run(&mut self, r: Node) -> String {
+        let mut out = String::new();
+        for n in r.children().filter(|n| n.is_element()) {
+            match n.tag_name().name() {
+                "t" | "delText" => out.push_str(n.text().unwrap_or_default()),
+                "tab" => out.push('\t'),
+                "br" | "cr" => out.push('\n'),
+                "noBreakHyphen" => out.push('-'),
+                "footnoteReference" => {
+                    out.push_str(&self.reference(attr(n, "id"), NoteKind::Footnote))
+                }
+                "endnoteReference" => {
+                    out.push_str(&self.reference(attr(n, "id"), NoteKind::Endnote))
+                }
+                "commentReference" => {
+                    out.push_str(&self.reference(attr(n, "id"), NoteKind::Comment))
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// Returns the footnote marker for a note and records its definition.
+    fn reference(&mut self, id: Option<&str>, kind: NoteKind) -> String {
+        let Some(id) = id else {
+            return String::new();
+        };
+        let (label, note) = match kind {
+            NoteKind::Footnote => (id.to_string(), self.footnotes.get(id).copied()),
+            NoteKind::Endnote => (format!("e{id}"), self.endnotes.get(id).copied()),
+            NoteKind::Comment => (format!("c{id}"), self.comments.get(id).copied()),
+        };
+        let Some(note) = note else {
+            return String::new();
+        };
+        let text = join_blocks(&self.blocks(note)).replace('\n', " ");
+        let definition = match (kind, attr(note, "author")) {
+            (NoteKind::Comment, Some(author)) => format!("[^{label}]: {author}: {}", text.trim()),
+            _ => format!("[^{label}]: {}", text.trim()),
+        };
+        if !self.definitions.contains(&definition) {
+            self.definitions.push(definition);
+        }
+        format!("[^{label}]")
+    }
+}
+
+#[derive(Clone, Copy)]
+enum NoteKind {
+    Footnote,
+    Endnote,
+    Comment,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_markdown;
+    use crate::office::tests::package;
+
+    const NS: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships""#;
+
+    fn para(inner: &str) -> String {
+        format!("<w:p>{inner}</w:p>")
+    }
+
+    fn run(text: &str) -> String {
+        format!(r#"<w:r><w:t xml:space="preserve">{text}</w:t></w:r>"#)
+    }
+
+    #[test]
+    fn docx_to_markdown() {
+        let body = [
+            para(&format!(
+                r#"<w:pPr><w:pStyle w:val="Heading1"/></w:pPr>{}"#,
+                run("Quarterly report")
+            )),
+            para(&format!(
+                r#"{}<w:r><w:rPr><w:b/></w:rPr><w:t>up</w:t></w:r><w:r><w:rPr><w:b/></w:rPr><w:t xml:space="preserve"> 5% </w:t></w:r>{}<w:r><w:footnoteReference w:id="1"/></w:r>"#,
+                run("Revenue was "),
+                run("overall.")
+            )),
+            para(&format!(
+                r#"<w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr>{}"#,
+                run("First")
+            )),
+            para(&format!(
+                r#"<w:pPr><w:numPr><w:ilvl w:val="1"/><w:numId w:val="1"/></w:numPr></w:pPr>{}"#,
+                run("Detail")
+            )),
+            para(&format!(
+                r#"<w:pPr><w:numPr><w:ilvl w:val="0"/><w:numId w:val="1"/></w:numPr></w:pPr>{}"#,
+                run("Second")
+            )),
+            r#"<w:tbl><w:tr><w:tc><w:p><w:r><w:t>Region</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Sales</w:t></w:r></w:p></w:tc></w:tr><w:tr><w:tc><w:p><w:r><w:t>EU|UK</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>10</w:t></w:r></w:p></w:tc></w:tr></w:tbl>"#.to_string(),
+            para(&format!(
+                r#"{}<w:del w:author="A"><w:r><w:delText>old</w:delText></w:r></w:del><w:ins w:author="A">{}</w:ins><w:r><w:commentReference w:id="0"/></w:r><w:hyperlink r:id="rId9">{}</w:hyperlink>"#,
+                run("Status: "),
+                run("new"),
+                run(" see site")
+            )),
+        ]
+        .concat();
+        let docx = package(&[
+            (
+                "word/document.xml",
+                &format!(r#"<w:document {NS}><w:body>{body}</w:body></w:document>"#),
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId9" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com" TargetMode="External"/></Relationships>"#,
+            ),
+            (
+                "word/numbering.xml",
+                &format!(
+                    r#"<w:numbering {NS}><w:abstractNum w:abstractNumId="7"><w:lvl w:ilvl="0"><w:numFmt w:val="decimal"/></w:lvl><w:lvl w:ilvl="1"><w:numFmt w:val="bullet"/></w:lvl></w:abstractNum><w:num w:numId="1"><w:abstractNumId w:val="7"/></w:num></w:numbering>"#
+                ),
+            ),
+            (
+                "word/footnotes.xml",
+                &format!(
+                    r#"<w:footnotes {NS}><w:footnote w:type="separator" w:id="-1"><w:p/></w:footnote><w:footnote w:id="1"><w:p>{}</w:p></w:footnote></w:footnotes>"#,
+                    run("Unaudited.")
+                ),
+            ),
+            (
+                "word/comments.xml",
+                &format!(
+                    r#"<w:comments {NS}><w:comment w:id="0" w:author="Dana"><w:p>{}</w:p></w:comment></w:comments>"#,
+                    run("Confirm with finance")
+                ),
+            ),
+        ]);
+
+        assert_eq!(
+            to_markdown(&docx).unwrap(),
+            "# Quarterly report\n\n\
+             Revenue was **up 5%** overall.[^1]\n\n\
+             1. First\n  - Detail\n2. Second\n\n\
+             | Region | Sales |\n| --- | --- |\n| EU\\|UK | 10 |\n\n\
+             Status: {--old--}{++new++}[^c0][ see site](https://example.com)\n\n\
+             [^1]: Unaudited.\n\
+             [^c0]: Dana: Confirm with finance"
+        );
+    }
+}
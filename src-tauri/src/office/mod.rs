@@ -0,0 +1,214 @@
+//! Readers for Office Open XML packages (DOCX, PPTX) that render their text
+//! as Markdown without going through LibreOffice.
+
+pub mod docx;
+pub mod pptx;
+
+use crate::Result;
+use crate::error::Error;
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+use zip::result::ZipError;
+
+/// A relationship from a `.rels` part, e.g. a hyperlink or a slide.
+pub struct Relationship {
+    pub rel_type: String,
+    pub target: String,
+}
+
+/// Most bytes all parts of a package may decompress to, so a zip bomb fails
+/// instead of exhausting memory.
+const MAX_UNCOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+
+/// The zip container of an OOXML document.
+pub struct Package<'a> {
+    archive: ZipArchive<Cursor<&'a [u8]>>,
+    /// Bytes left to decompress before reads are refused.
+    remaining: u64,
+}
+
+impl<'a> Package<'a> {
+    pub fn open(file_buffer: &'a [u8]) -> Result<Self> {
+        Self::with_limit(file_buffer, MAX_UNCOMPRESSED_SIZE)
+    }
+
+    fn with_limit(file_buffer: &'a [u8], limit: u64) -> Result<Self> {
+        let archive = ZipArchive::new(Cursor::new(file_buffer))
+            .map_err(|_| Error::Tool("not an Office Open XML package".to_string()))?;
+        Ok(Self {
+            archive,
+            remaining: limit,
+        })
+    }
+
+    /// Reads a part as text, `None` if the package doesn't have it.
+    pub fn part(&mut self, name: &str) -> Result<Option<String>> {
+        let file = match self.archive.by_name(name) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(_) => return Err(Error::Tool(format!("cannot read {name}"))),
+        };
+        // Don't trust the declared size; stop reading one byte past the limit.
+        let mut out = String::new();
+        file.take(self.remaining + 1).read_to_string(&mut out)?;
+        let read = out.len() as u64;
+        if read > self.remaining {
+            return Err(Error::Tool(format!(
+                "{name} is too large once decompressed"
+            )));
+        }
+        self.remaining -= read;
+        Ok(Some(out))
+    }
+
+    /// Relationships of `part` by id, with internal targets resolved to part names.
+    pub fn relationships(&mut self, part: &str) -> Result<HashMap<String, Relationship>> {
+        let (dir, file) = part.rsplit_once('/').unwrap_or(("", part));
+        let rels_name = match dir {
+            "" => format!("_rels/{file}.rels"),
+            dir => format!("{dir}/_rels/{file}.rels"),
+        };
+        let Some(xml) = self.part(&rels_name)? else {
+            return Ok(HashMap::new());
+        };
+        let doc = parse(&xml)?;
+        Ok(doc
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("Relationship"))
+            .filter_map(|n| {
+                let external = n.attribute("TargetMode") == Some("External");
+                let target = n.attribute("Target")?;
+                let rel = Relationship {
+                    rel_type: n.attribute("Type")?.rsplit('/').next()?.to_string(),
+                    target: if external {
+                        target.to_string()
+                    } else {
+                        resolve(dir, target)
+                    },
+                };
+                Some((n.attribute("Id")?.to_string(), rel))
+            })
+            .collect())
+    }
+}
+
+/// Resolves a relative part reference against the directory of its source part.
+fn resolve(dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut segments: Vec<&str> = dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                segments.pop();
+            }
+            "." | "" => {}
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+pub fn parse(xml: &str) -> Result<Document<'_>> {
+    Document::parse(xml).map_err(|e| Error::Tool(format!("malformed XML: {e}")))
+}
+
+/// Attribute by local name, whatever namespace prefix the producer used.
+pub fn attr<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|a| a.name() == name)
+        .map(|a| a.value())
+}
+
+/// A relationship reference such as `r:id` or `r:embed`, told apart from
+/// same-named attributes in other namespaces.
+pub fn rel_attr<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|a| {
+            a.name() == name
+                && a.namespace()
+                    .is_some_and(|ns| ns.ends_with("/relationships"))
+        })
+        .map(|a| a.value())
+}
+
+/// First child element with the given local name.
+pub fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+pub fn is(node: Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+/// Wraps `text` in emphasis markers, keeping surrounding whitespace outside.
+pub fn emphasize(text: &str, bold: bool, italic: bool) -> String {
+    let marker = match (bold, italic) {
+        (true, true) => "***",
+        (true, false) => "**",
+        (false, true) => "*",
+        (false, false) => "",
+    };
+    let trimmed = text.trim();
+    if marker.is_empty() || trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = start + trimmed.len();
+    format!(
+        "{}{marker}{trimmed}{marker}{}",
+        &text[..start],
+        &text[end..]
+    )
+}
+
+/// Escapes text for use inside a Markdown table cell.
+pub fn table_cell(text: &str) -> String {
+    text.trim()
+        .replace('|', "\\|")
+        .replace("\n\n", "<br>")
+        .replace('\n', "<br>")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Package, resolve};
+    use std::io::{Cursor, Write};
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    /// Zips `(name, content)` parts into an in-memory package.
+    pub fn package(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in parts {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn resolve_relative_targets() {
+        assert_eq!(resolve("word", "media/image1.png"), "word/media/image1.png");
+        assert_eq!(
+            resolve("ppt/slides", "../notesSlides/notesSlide1.xml"),
+            "ppt/notesSlides/notesSlide1.xml"
+        );
+        assert_eq!(resolve("ppt/slides", "/ppt/media/a.png"), "ppt/media/a.png");
+        assert_eq!(resolve("", "word/document.xml"), "word/document.xml");
+    }
+
+    #[test]
+    fn refuse_parts_past_the_size_limit() {
+        let buffer = package(&[("a.xml", &"a".repeat(60)), ("b.xml", &"b".repeat(60))]);
+        let mut package = Package::with_limit(&buffer, 100).unwrap();
+        assert_eq!(package.part("a.xml").unwrap().unwrap().len(), 60);
+        assert!(package.part("b.xml").is_err());
+        assert!(package.part("missing.xml").unwrap().is_none());
+    }
+}
@@ -0,0 +1,344 @@
+use super::{Package, Relationship, attr, child, emphasize, is, parse, rel_attr, table_cell};
+use crate::Result;
+use crate::error::Error;
+use roxmltree::Node;
+use std::collections::HashMap;
+
+const PRESENTATION: &str = "ppt/presentation.xml";
+
+/// Renders a presentation as Markdown with one section per slide, followed
+/// by its speaker notes. Pictures are referenced by their path inside the
+/// package, e.g. `![Logo](ppt/media/image1.png)`.
+pub fn to_markdown(file_buffer: &[u8]) -> Result<String> {
+    let mut package = Package::open(file_buffer)?;
+    let presentation = package
+        .part(PRESENTATION)?
+        .ok_or(Error::Tool("not a PowerPoint presentation".to_string()))?;
+    let presentation = parse(&presentation)?;
+    let rels = package.relationships(PRESENTATION)?;
+    let slide_parts: Vec<&str> = child(presentation.root_element(), "sldIdLst")
+        .into_iter()
+        .flat_map(|list| list.children())
+        .filter(|n| is(*n, "sldId"))
+        .filter_map(|n| rels.get(rel_attr(n, "id")?))
+        .map(|rel| rel.target.as_str())
+        .collect();
+
+    let mut sections = vec![];
+    for (i, part) in slide_parts.iter().enumerate() {
+        sections.push(slide(&mut package, i + 1, part)?);
+    }
+    Ok(sections.join("\n\n"))
+}
+
+fn slide(package: &mut Package, number: usize, part: &str) -> Result<String> {
+    let Some(xml) = package.part(part)? else {
+        return Ok(format!("## Slide {number}"));
+    };
+    let doc = parse(&xml)?;
+    let rels = package.relationships(part)?;
+    let root = doc.root_element();
+
+    let mut content = SlideContent::default();
+    if let Some(tree) = child(root, "cSld").and_then(|c| child(c, "spTree")) {
+        content.shapes(tree, &rels);
+    }
+    let hidden = if attr(root, "show") == Some("0") {
+        " (hidden)"
+    } else {
+        ""
+    };
+    let mut out = match content.title {
+        Some(title) => format!("## Slide {number}{hidden}: {title}"),
+        None => format!("## Slide {number}{hidden}"),
+    };
+    for block in content.blocks {
+        out.push_str("\n\n");
+        out.push_str(&block);
+    }
+
+    let notes_part = rels
+        .values()
+        .find(|rel| rel.rel_type == "notesSlide")
+        .map(|rel| rel.target.clone());
+    if let Some(notes_part) = notes_part
+        && let Some(notes) = speaker_notes(package, &notes_part)?
+    {
+        out.push_str("\n\n### Notes\n\n");
+        out.push_str(&notes);
+    }
+    Ok(out)
+}
+
+/// Text of the body placeholder of a notes slide; the rest is the slide image and number.
+fn speaker_notes(package: &mut Package, part: &str) -> Result<Option<String>> {
+    let Some(xml) = package.part(part)? else {
+        return Ok(None);
+    };
+    let doc = parse(&xml)?;
+    let Some(tree) = child(doc.root_element(), "cSld").and_then(|c| child(c, "spTree")) else {
+        return Ok(None);
+    };
+    let notes: Vec<String> = tree
+        .descendants()
+        .filter(|n| is(*n, "sp") && placeholder_type(*n) == Some("body"))
+        .filter_map(|sp| child(sp, "txBody"))
+        .map(|body| text_body(body, false))
+        .filter(|text| !text.is_empty())
+        .collect();
+    Ok((!notes.is_empty()).then(|| notes.join("\n\n")))
+}
+
+/// The placeholder type of a shape, if it is a placeholder. Untyped
+/// placeholders are body placeholders.
+fn placeholder_type<'a>(shape: Node<'a, '_>) -> Option<&'a str> {
+    let properties = shape
+        .children()
+        .find(|n| n.is_element() && n.tag_name().name().starts_with("nv"))?;
+    let ph = child(child(properties, "nvPr")?, "ph")?;
+    Some(attr(ph, "type").unwrap_or("body"))
+}
+
+#[derive(Default)]
+struct SlideContent {
+    title: Option<String>,
+    blocks: Vec<String>,
+}
+
+impl SlideContent {
+    fn shapes(&mut self, tree: Node, rels: &HashMap<String, Relationship>) {
+        for shape in tree.children().filter(|n| n.is_element()) {
+            match shape.tag_name().name() {
+                "sp" => {
+                    let Some(body) = child(shape, "txBody") else {
+                        continue;
+                    };
+                    match placeholder_type(shape) {
+                        Some("title" | "ctrTitle") if self.title.is_none() => {
+                            let title = text_body(body, false).replace('\n', " ");
+                            if !title.is_empty() {
+                                self.title = Some(title);
+                            }
+                        }
+                        Some("sldNum" | "dt" | "ftr") => {}
+                        ph => {
+                            let text = text_body(body, matches!(ph, Some("body" | "obj")));
+                            if !text.is_empty() {
+                                self.blocks.push(text);
+                            }
+                        }
+                    }
+                }
+                "graphicFrame" => {
+                    if let Some(tbl) = shape.descendants().find(|n| is(*n, "tbl")) {
+                        self.blocks.push(table(tbl));
+                    }
+                }
+                "pic" => {
+                    if let Some(image) = picture(shape, rels) {
+                        self.blocks.push(image);
+                    }
+                }
+                "grpSp" => self.shapes(shape, rels),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn picture(pic: Node, rels: &HashMap<String, Relationship>) -> Option<String> {
+    let target = &rels
+        .get(rel_attr(
+            pic.descendants().find(|n| is(*n, "blip"))?,
+            "embed",
+        )?)?
+        .target;
+    let props = child(pic, "nvPicPr").and_then(|p| child(p, "cNvPr"));
+    let alt = props
+        .and_then(|p| attr(p, "descr").filter(|d| !d.is_empty()))
+        .or_else(|| props.and_then(|p| attr(p, "name")))
+        .unwrap_or("image")
+        .replace(['[', ']', '\n'], " ");
+    Some(format!("![{}]({target})", alt.trim()))
+}
+
+fn table(tbl: Node) -> String {
+    let mut lines = vec![];
+    for (i, tr) in tbl.children().filter(|n| is(*n, "tr")).enumerate() {
+        let cells: Vec<String> = tr
+            .children()
+            .filter(|n| is(*n, "tc"))
+            .map(|tc| {
+                // Cells covered by a merge are repeated as empty placeholders.
+                let merged = attr(tc, "hMerge") == Some("1") || attr(tc, "vMerge") == Some("1");
+                match child(tc, "txBody") {
+                    Some(body) if !merged => table_cell(&text_body(body, false)),
+                    _ => String::new(),
+                }
+            })
+            .collect();
+        lines.push(format!("| {} |", cells.join(" | ")));
+        if i == 0 {
+            lines.push(format!("|{}", " --- |".repeat(cells.len())));
+        }
+    }
+    lines.join("\n")
+}
+
+/// Paragraphs of a text body, as list items where the slide shows bullets.
+/// Body placeholders inherit bullets from the master unless switched off.
+fn text_body(body: Node, bulleted: bool) -> String {
+    let mut lines = vec![];
+    let mut counters: HashMap<usize, usize> = HashMap::new();
+    for p in body.children().filter(|n| is(*n, "p")) {
+        let text = paragraph(p);
+        if text.trim().is_empty() {
+            continue;
+        }
+        let props = child(p, "pPr");
+        let level: usize = props
+            .and_then(|p| attr(p, "lvl"))
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(0);
+        let numbered = props.and_then(|p| child(p, "buAutoNum")).is_some();
+        let bullet = props.and_then(|p| child(p, "buChar")).is_some()
+            || (bulleted && props.and_then(|p| child(p, "buNone")).is_none());
+        let marker = if numbered {
+            counters.retain(|l, _| *l <= level);
+            let counter = counters.entry(level).or_default();
+            *counter += 1;
+            format!("{counter}. ")
+        } else if bullet {
+            counters.clear();
+            "- ".to_string()
+        } else {
+            counters.clear();
+            String::new()
+        };
+        let indent = if marker.is_empty() {
+            String::new()
+        } else {
+            "  ".repeat(level)
+        };
+        lines.push(format!("{indent}{marker}{}", text.trim()));
+    }
+    lines.join("\n")
+}
+
+fn paragraph(p: Node) -> String {
+    let flag = |props: Option<Node>, name: &str| {
+        props
+            .and_then(|p| attr(p, name))
+            .is_some_and(|v| v == "1" || v == "true")
+    };
+    let mut out = String::new();
+    let mut pending = String::new();
+    let mut pending_style = (false, false);
+    for n in p.children().filter(|n| n.is_element()) {
+        let (text, style) = match n.tag_name().name() {
+            "r" | "fld" => {
+                let props = child(n, "rPr");
+                (
+                    child(n, "t").and_then(|t| t.text()).unwrap_or_default(),
+                    (flag(props, "b"), flag(props, "i")),
+                )
+            }
+            "br" => ("\n", pending_style),
+            _ => continue,
+        };
+        if style != pending_style {
+            out.push_str(&emphasize(&pending, pending_style.0, pending_style.1));
+            pending.clear();
+            pending_style = style;
+        }
+        pending.push_str(text);
+    }
+    out.push_str(&emphasize(&pending, pending_style.0, pending_style.1));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_markdown;
+    use crate::office::tests::package;
+
+    const NS: &str = r#"xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main""#;
+    const RELS: &str = r#"xmlns="http://schemas.openxmlformats.org/package/2006/relationships""#;
+    const REL: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+    fn shape(ph: &str, paragraphs: &str) -> String {
+        format!(
+            r#"<p:sp><p:nvSpPr><p:cNvPr id="2" name="s"/><p:cNvSpPr/><p:nvPr>{ph}</p:nvPr></p:nvSpPr><p:txBody>{paragraphs}</p:txBody></p:sp>"#
+        )
+    }
+
+    fn para(text: &str) -> String {
+        format!("<a:p><a:r><a:t>{text}</a:t></a:r></a:p>")
+    }
+
+    #[test]
+    fn pptx_to_markdown() {
+        let slide1 = format!(
+            r#"<p:sld {NS}><p:cSld><p:spTree>{}{}<p:graphicFrame><a:graphic><a:graphicData><a:tbl><a:tr><a:tc><a:txBody>{}</a:txBody></a:tc><a:tc><a:txBody>{}</a:txBody></a:tc></a:tr><a:tr><a:tc><a:txBody>{}</a:txBody></a:tc><a:tc><a:txBody>{}</a:txBody></a:tc></a:tr></a:tbl></a:graphicData></a:graphic></p:graphicFrame><p:pic><p:nvPicPr><p:cNvPr id="5" name="Picture 4" descr="Revenue chart"/></p:nvPicPr><p:blipFill><a:blip r:embed="rId2"/></p:blipFill></p:pic></p:spTree></p:cSld></p:sld>"#,
+            shape(r#"<p:ph type="title"/>"#, &para("Results")),
+            shape(
+                r#"<p:ph idx="1"/>"#,
+                &format!(
+                    r#"{}<a:p><a:pPr lvl="1"/><a:r><a:rPr b="1"/><a:t>Europe</a:t></a:r><a:r><a:t> leads</a:t></a:r></a:p>"#,
+                    para("Growth")
+                )
+            ),
+            para("Region"),
+            para("Sales"),
+            para("EU"),
+            para("10"),
+        );
+        let notes1 = format!(
+            r#"<p:notes {NS}><p:cSld><p:spTree>{}{}</p:spTree></p:cSld></p:notes>"#,
+            shape(r#"<p:ph type="sldImg"/>"#, ""),
+            shape(
+                r#"<p:ph type="body" idx="1"/>"#,
+                &para("Mention the outlook")
+            )
+        );
+        let slide2 = format!(
+            r#"<p:sld {NS} show="0"><p:cSld><p:spTree>{}</p:spTree></p:cSld></p:sld>"#,
+            shape("", &para("Thanks"))
+        );
+        let pptx = package(&[
+            (
+                "ppt/presentation.xml",
+                &format!(
+                    r#"<p:presentation {NS}><p:sldIdLst><p:sldId id="257" r:id="rId8"/><p:sldId id="256" r:id="rId7"/></p:sldIdLst></p:presentation>"#
+                ),
+            ),
+            (
+                "ppt/_rels/presentation.xml.rels",
+                &format!(
+                    r#"<Relationships {RELS}><Relationship Id="rId7" Type="{REL}/slide" Target="slides/slide2.xml"/><Relationship Id="rId8" Type="{REL}/slide" Target="slides/slide1.xml"/></Relationships>"#
+                ),
+            ),
+            ("ppt/slides/slide1.xml", &slide1),
+            (
+                "ppt/slides/_rels/slide1.xml.rels",
+                &format!(
+                    r#"<Relationships {RELS}><Relationship Id="rId1" Type="{REL}/notesSlide" Target="../notesSlides/notesSlide1.xml"/><Relationship Id="rId2" Type="{REL}/image" Target="../media/image1.png"/></Relationships>"#
+                ),
+            ),
+            ("ppt/notesSlides/notesSlide1.xml", &notes1),
+            ("ppt/slides/slide2.xml", &slide2),
+        ]);
+
+        assert_eq!(
+            to_markdown(&pptx).unwrap(),
+            "## Slide 1: Results\n\n\
+             - Growth\n  - **Europe** leads\n\n\
+             | Region | Sales |\n| --- | --- |\n| EU | 10 |\n\n\
+             ![Revenue chart](ppt/media/image1.png)\n\n\
+             ### Notes\n\n\
+             Mention the outlook\n\n\
+             ## Slide 2 (hidden)\n\n\
+             Thanks"
+        );
+    }
+}
@@ -1,16 +1,21 @@
+use crate::Result;
+use crate::error::Error;
 use crate::interaction::Content;
 use crate::interaction::Interaction;
 use crate::interaction::Source;
 use crate::interaction::Target;
-use crate::settings::get_settings;
-use crate::tools;
+use crate::llm::LlmBackend;
+use crate::usage::Usage;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
 use serde_json::json;
-use serde_json::to_string_pretty;
 
-pub struct Openrouter;
+/// OpenRouter, and the OpenAI-style wire format every backend speaks.
+pub struct Openrouter {
+    pub api_key: String,
+    pub provider_order: String,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IncomingMessage {
@@ -34,6 +39,8 @@ pub enum IncomingContent {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatResponse {
     pub choices: Vec<Choice>,
+    #[serde(default)]
+    pub usage: Usage,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -144,72 +151,401 @@ impl Source for Openrouter {
     }
 }
 
-impl Openrouter {
-    pub async fn call(
-        messages: &[Value],
-        model_name: &str,
-        system_prompt: &str,
-        tools: &Vec<tools::Tool>,
-        schema: Option<Value>,
-    ) -> super::Result<ChatResponse> {
-        println!(
-            "Sending messages to OpenRouter: {}",
-            messages
-                .iter()
-                .map(|j| to_string_pretty(j).unwrap_or_default())
-                .collect::<Vec<_>>()
-                .join("\n\n")
-        );
-        let settings = get_settings()?;
-        let client = reqwest::Client::new();
-        let mut final_messages = messages.to_vec();
-        if !system_prompt.is_empty() {
-            final_messages.insert(0, json!({"role":"system","content":system_prompt}));
-        }
-        let mut json_to_send = json!({
-            "model": model_name,
-            "messages": final_messages,
-            "tools": tools,
-            "provider": {
-                "order": settings.provider_order.split(',').collect::<Vec<_>>(),
-            }
-        });
-        if let Some(schema) = schema
-            && let Some(m) = json_to_send.as_object_mut()
-        {
+impl LlmBackend for Openrouter {
+    fn endpoint(&self) -> String {
+        "https://openrouter.ai/api/v1/chat/completions".to_string()
+    }
+
+    fn api_key(&self) -> Option<String> {
+        Some(self.api_key.clone())
+    }
+
+    fn prepare(&self, body: &mut Value) {
+        if let Some(m) = body.as_object_mut() {
             m.insert(
-                "response_format".to_string(),
+                "provider".to_string(),
                 json!({
-                    "type": "json_schema",
-                    "json_schema": {
-                        "name": "output",
-                        "strict": true,
-                        "schema": schema
-                    }
+                    "order": self.provider_order.split(',').collect::<Vec<_>>(),
                 }),
             );
+            // Adds the cost in credits to the usage block
+            m.insert("usage".to_string(), json!({"include": true}));
+        }
+    }
+}
+
+/// A single incremental update parsed out of a streamed completion.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamDelta {
+    Content(String),
+    ToolCall {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    #[serde(default)]
+    error: Option<Value>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct ChunkDelta {
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCallChunk {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    function: Option<FunctionCallChunk>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct FunctionCallChunk {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    arguments: Option<String>,
+}
+
+/// Assembles a server-sent event stream into the final message, handing back
+/// the deltas found in each piece of the body as it arrives.
+#[derive(Debug, Default)]
+pub struct StreamAccumulator {
+    buffer: String,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    usage: Usage,
+    /// Set when the provider gives up mid-stream.
+    error: Option<Error>,
+}
+
+impl StreamAccumulator {
+    pub fn feed(&mut self, data: &str) -> Vec<StreamDelta> {
+        self.buffer.push_str(data);
+        let mut deltas = vec![];
+        while let Some(pos) = self.buffer.find('\n') {
+            let line: String = self.buffer.drain(..=pos).collect();
+            deltas.extend(self.feed_line(line.trim_end()));
+        }
+        deltas
+    }
+
+    fn feed_line(&mut self, line: &str) -> Vec<StreamDelta> {
+        // Lines starting with ':' are keep-alive comments, e.g. ": OPENROUTER PROCESSING"
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            return vec![];
+        };
+        if data.is_empty() || data == "[DONE]" {
+            return vec![];
+        }
+        let chunk = match serde_json::from_str::<ChatChunk>(data) {
+            Ok(chunk) => chunk,
+            Err(_) => return vec![],
+        };
+        // Sent with the last chunk, usually with no choices
+        if let Some(usage) = chunk.usage {
+            self.usage = usage;
         }
-        let res = client
-            .post("https://openrouter.ai/api/v1/chat/completions")
-            .bearer_auth(&settings.api_key)
-            .json(&json_to_send)
-            .send()
-            .await?;
-
-        let text = res.text().await?;
-        println!("Got response from OpenRouter: {text}",);
-        let response: ChatResponse = match serde_json::from_str::<ChatResponse>(&text) {
-            Ok(res) => res,
-            Err(_) => ChatResponse {
-                choices: vec![Choice {
-                    message: IncomingMessage {
-                        role: "assistant".to_string(),
-                        content: IncomingContent::Text(text),
-                        tool_calls: None,
-                    },
-                }],
+        if let Some(error) = chunk.error {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| error.to_string());
+            // OpenRouter puts the HTTP status it would have answered with in `code`.
+            let status = error
+                .get("code")
+                .and_then(|c| c.as_u64().or_else(|| c.as_str()?.parse().ok()))
+                .and_then(|c| u16::try_from(c).ok())
+                .filter(|c| (400..600).contains(c))
+                .unwrap_or(502);
+            self.error = Some(Error::Api { status, message });
+            return vec![];
+        }
+
+        let mut deltas = vec![];
+        for choice in chunk.choices {
+            if let Some(content) = choice.delta.content
+                && !content.is_empty()
+            {
+                deltas.push(StreamDelta::Content(content));
+            }
+            for call in choice.delta.tool_calls.into_iter().flatten() {
+                let function = call.function.unwrap_or_default();
+                deltas.push(StreamDelta::ToolCall {
+                    index: call.index,
+                    id: call.id,
+                    name: function.name,
+                    arguments: function.arguments.unwrap_or_default(),
+                });
+            }
+        }
+        self.apply(deltas)
+    }
+
+    fn apply(&mut self, deltas: Vec<StreamDelta>) -> Vec<StreamDelta> {
+        for delta in &deltas {
+            match delta {
+                StreamDelta::Content(text) => self.content.push_str(text),
+                StreamDelta::ToolCall {
+                    index,
+                    id,
+                    name,
+                    arguments,
+                } => {
+                    while self.tool_calls.len() <= *index {
+                        self.tool_calls.push(ToolCall {
+                            id: String::new(),
+                            r#type: "function".to_string(),
+                            function: FunctionCall {
+                                name: String::new(),
+                                arguments: String::new(),
+                            },
+                        });
+                    }
+                    let call = &mut self.tool_calls[*index];
+                    // Some providers repeat the id and name on every fragment
+                    if let Some(id) = id
+                        && call.id.is_empty()
+                    {
+                        call.id = id.clone();
+                    }
+                    if let Some(name) = name
+                        && call.function.name.is_empty()
+                    {
+                        call.function.name = name.clone();
+                    }
+                    call.function.arguments.push_str(arguments);
+                }
+            }
+        }
+        deltas
+    }
+
+    /// The assembled message along with the usage reported at the end of the
+    /// stream, or the error the provider ended the stream with.
+    pub fn into_response(mut self) -> Result<ChatResponse> {
+        self.flush();
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        let usage = self.usage;
+        Ok(ChatResponse {
+            choices: vec![Choice {
+                message: self.finish(),
+            }],
+            usage,
+        })
+    }
+
+    fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            self.feed_line(rest.trim_end());
+        }
+    }
+
+    pub fn finish(mut self) -> IncomingMessage {
+        self.flush();
+        let tool_calls: Vec<_> = self
+            .tool_calls
+            .into_iter()
+            .filter(|t| !t.function.name.is_empty())
+            .collect();
+        IncomingMessage {
+            role: "assistant".to_string(),
+            content: if self.content.is_empty() {
+                IncomingContent::None
+            } else {
+                IncomingContent::Text(self.content)
+            },
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
             },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FunctionCall, IncomingContent, Openrouter, StreamAccumulator, StreamDelta, ToolCall,
+    };
+    use crate::error::Error;
+    use crate::interaction::{Content, History, Interaction, Target};
+
+    #[test]
+    fn stream_content_deltas() {
+        let mut acc = StreamAccumulator::default();
+        let mut deltas = acc.feed(": OPENROUTER PROCESSING\n\n");
+        deltas.extend(acc.feed(r#"data: {"choices":[{"delta":{"role":"assistant","content":"Hel"#));
+        deltas.extend(
+            acc.feed("lo\"}}]}\n\ndata: {\"choices\":[{\"delta\":{\"content\":\" world\"}}]}\n\n"),
+        );
+        deltas.extend(acc.feed("data: [DONE]\n\n"));
+        assert_eq!(
+            deltas,
+            vec![
+                StreamDelta::Content("Hello".to_string()),
+                StreamDelta::Content(" world".to_string())
+            ]
+        );
+        let message = acc.finish();
+        assert!(matches!(message.content, IncomingContent::Text(ref t) if t == "Hello world"));
+        assert!(message.tool_calls.is_none());
+    }
+
+    #[test]
+    fn stream_tool_call_fragments() {
+        let mut acc = StreamAccumulator::default();
+        acc.feed(concat!(
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"find","arguments":""}}]}}]}"#,
+            "\n",
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"pattern\":"}}]}}]}"#,
+            "\n",
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_2","function":{"name":"ls","arguments":"{"}}]}}]}"#,
+            "\n",
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_2","function":{"name":"ls","arguments":"}"}}]}}]}"#,
+            "\n",
+        ));
+        let deltas = acc.feed(concat!(
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"*.pdf\"}"}}]}}]}"#,
+            "\n"
+        ));
+        assert_eq!(
+            deltas,
+            vec![StreamDelta::ToolCall {
+                index: 0,
+                id: None,
+                name: None,
+                arguments: "\"*.pdf\"}".to_string()
+            }]
+        );
+        let calls = acc.finish().tool_calls.unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.name, "find");
+        assert_eq!(calls[0].function.arguments, r#"{"pattern":"*.pdf"}"#);
+        assert_eq!(calls[1].id, "call_2");
+        assert_eq!(calls[1].function.name, "ls");
+        assert_eq!(calls[1].function.arguments, "{}");
+    }
+
+    #[test]
+    fn stream_error_chunk_fails_the_response() {
+        let mut acc = StreamAccumulator::default();
+        let deltas = acc.feed(concat!(
+            r#"data: {"choices":[{"delta":{"content":"Hel"}}]}"#,
+            "\n",
+            r#"data: {"error":{"code":429,"message":"Rate limited"},"choices":[]}"#,
+            "\n",
+        ));
+        assert_eq!(deltas, vec![StreamDelta::Content("Hel".to_string())]);
+        let err = acc.into_response().unwrap_err();
+        assert!(
+            matches!(&err, Error::Api { status: 429, message } if message == "Rate limited"),
+            "{err:?}"
+        );
+
+        let mut acc = StreamAccumulator::default();
+        acc.feed(r#"data: {"error":{"message":"Provider returned error"}}"#);
+        assert!(matches!(
+            acc.into_response(),
+            Err(Error::Api { status: 502, .. })
+        ));
+    }
+
+    fn call(id: &str) -> ToolCall {
+        ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: FunctionCall {
+                name: "load_file".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }
+    }
+
+    fn file_text(text: &str) -> Vec<Content> {
+        vec![Content::Text {
+            text: text.to_string(),
+        }]
+    }
+
+    #[test]
+    fn render_defers_tool_content_until_after_tool_results() {
+        let history = History {
+            inner: vec![
+                Interaction::user_message(file_text("question")),
+                Interaction::llm_response(vec![], Some(vec![call("a"), call("b")])),
+                Interaction::tool_result("a".to_string(), "ok".to_string(), file_text("A"), vec![]),
+                Interaction::tool_result("b".to_string(), "ok".to_string(), file_text("B"), vec![]),
+                Interaction::llm_response(file_text("answer"), None),
+            ],
+            ..Default::default()
+        };
+        let rendered = Openrouter::render(&history);
+        let summary: Vec<_> = rendered
+            .iter()
+            .map(|m| {
+                let role = m["role"].as_str().unwrap();
+                let detail = m["tool_call_id"]
+                    .as_str()
+                    .or(m["content"][0]["text"].as_str())
+                    .unwrap_or("");
+                format!("{role}:{detail}")
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "user:question",
+                "assistant:",
+                "tool:a",
+                "tool:b",
+                "user:A",
+                "user:B",
+                "assistant:answer"
+            ]
+        );
+    }
+
+    #[test]
+    fn render_flushes_trailing_deferred_content() {
+        let history = History {
+            inner: vec![
+                Interaction::llm_response(vec![], Some(vec![call("a")])),
+                Interaction::tool_result("a".to_string(), "ok".to_string(), file_text("A"), vec![]),
+            ],
+            ..Default::default()
         };
-        Ok(response)
+        let roles: Vec<_> = Openrouter::render(&history)
+            .iter()
+            .map(|m| m["role"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(roles, ["assistant", "tool", "user"]);
     }
 }
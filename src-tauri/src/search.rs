@@ -1,4 +1,7 @@
+use crate::content_index::CONTENT_INDEX;
+use crate::file_handler::{FileType, determine_file_type};
 use crate::settings::get_root;
+use crate::utils::debug_log;
 use camino::Utf8PathBuf;
 use globset::{GlobBuilder, GlobSetBuilder};
 use ignore::Walk;
@@ -45,12 +48,43 @@ impl SearchState {
                     let full_list = Arc::clone(&self.full_list);
                     let win = window.clone();
                     let patt = globs.to_owned();
-                    move |res: Result<event::Event, notify::Error>| match res {
-                        Ok(event) => match event.kind {
-                            EventKind::Create(CreateKind::File) => {
-                                println!("create {:?}", event.paths);
-                                for path in event.paths {
-                                    if let Ok(path) = path.strip_prefix(&root) {
+                    move |res: Result<event::Event, notify::Error>| {
+                        if let Ok(event) = &res {
+                            CONTENT_INDEX.mark_dirty(&event.paths);
+                        }
+                        match res {
+                            Ok(event) => match event.kind {
+                                EventKind::Create(CreateKind::File) => {
+                                    debug_log!("create {:?}", event.paths);
+                                    for path in event.paths {
+                                        if let Ok(path) = path.strip_prefix(&root) {
+                                            add_paths(
+                                                &mut full_list.write().unwrap(),
+                                                &win,
+                                                &patt,
+                                                [path.to_string_lossy().to_string()],
+                                            );
+                                        }
+                                    }
+                                }
+                                EventKind::Remove(_) => {
+                                    debug_log!("remove {:?}", event.paths);
+                                    for path in event.paths {
+                                        if let Ok(path) = path.strip_prefix(&root) {
+                                            remove_paths(
+                                                &mut full_list.write().unwrap(),
+                                                &win,
+                                                &patt,
+                                                [path.to_string_lossy().to_string()],
+                                            );
+                                        }
+                                    }
+                                }
+                                EventKind::Modify(ModifyKind::Name(RenameMode::To))
+                                    if event.paths[0].is_file() =>
+                                {
+                                    debug_log!("rename to {:?}", event.paths);
+                                    if let Ok(path) = event.paths[0].strip_prefix(&root) {
                                         add_paths(
                                             &mut full_list.write().unwrap(),
                                             &win,
@@ -59,11 +93,9 @@ impl SearchState {
                                         );
                                     }
                                 }
-                            }
-                            EventKind::Remove(_) => {
-                                println!("remove {:?}", event.paths);
-                                for path in event.paths {
-                                    if let Ok(path) = path.strip_prefix(&root) {
+                                EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                                    debug_log!("rename from {:?}", event.paths);
+                                    if let Ok(path) = event.paths[0].strip_prefix(&root) {
                                         remove_paths(
                                             &mut full_list.write().unwrap(),
                                             &win,
@@ -72,56 +104,32 @@ impl SearchState {
                                         );
                                     }
                                 }
-                            }
-                            EventKind::Modify(ModifyKind::Name(RenameMode::To))
-                                if event.paths[0].is_file() =>
-                            {
-                                println!("rename to {:?}", event.paths);
-                                if let Ok(path) = event.paths[0].strip_prefix(&root) {
-                                    add_paths(
-                                        &mut full_list.write().unwrap(),
-                                        &win,
-                                        &patt,
-                                        [path.to_string_lossy().to_string()],
-                                    );
-                                }
-                            }
-                            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
-                                println!("rename from {:?}", event.paths);
-                                if let Ok(path) = event.paths[0].strip_prefix(&root) {
-                                    remove_paths(
-                                        &mut full_list.write().unwrap(),
-                                        &win,
-                                        &patt,
-                                        [path.to_string_lossy().to_string()],
-                                    );
-                                }
-                            }
-                            EventKind::Modify(ModifyKind::Name(RenameMode::Both))
-                                if event.paths[1].is_file() =>
-                            {
-                                println!("rename both {:?}", event.paths);
-                                if let Ok(path) = event.paths[0].strip_prefix(&root) {
-                                    remove_paths(
-                                        &mut full_list.write().unwrap(),
-                                        &win,
-                                        &patt,
-                                        [path.to_string_lossy().to_string()],
-                                    );
-                                }
-                                if let Ok(path) = event.paths[1].strip_prefix(&root) {
-                                    add_paths(
-                                        &mut full_list.write().unwrap(),
-                                        &win,
-                                        &patt,
-                                        [path.to_string_lossy().to_string()],
-                                    );
+                                EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+                                    if event.paths[1].is_file() =>
+                                {
+                                    debug_log!("rename both {:?}", event.paths);
+                                    if let Ok(path) = event.paths[0].strip_prefix(&root) {
+                                        remove_paths(
+                                            &mut full_list.write().unwrap(),
+                                            &win,
+                                            &patt,
+                                            [path.to_string_lossy().to_string()],
+                                        );
+                                    }
+                                    if let Ok(path) = event.paths[1].strip_prefix(&root) {
+                                        add_paths(
+                                            &mut full_list.write().unwrap(),
+                                            &win,
+                                            &patt,
+                                            [path.to_string_lossy().to_string()],
+                                        );
+                                    }
                                 }
+                                _ => {}
+                            },
+                            Err(e) => {
+                                eprintln!("Error {e:?}")
                             }
-                            _ => {}
-                        },
-                        Err(e) => {
-                            println!("Error {e:?}")
                         }
                     }
                 })
@@ -163,8 +171,8 @@ impl SearchState {
         if res.len() > SEARCH_RESULT_LIMIT {
             Err(crate::Error::Limit {
                 item: "search".to_string(),
-                requested: res.len(),
-                limit: SEARCH_RESULT_LIMIT,
+                requested: res.len() as f64,
+                limit: SEARCH_RESULT_LIMIT as f64,
             })
         } else {
             Ok(res)
@@ -267,6 +275,38 @@ pub async fn search_files_by_name(globs: &str) -> Result<Vec<String>, crate::Err
     .await?
 }
 
+/// Files under the root that have a text rendition, optionally narrowed by a
+/// `find` style pattern, sorted.
+pub async fn text_files(pattern: Option<&str>) -> Result<Vec<String>, crate::Error> {
+    let mut files = match pattern {
+        Some(pattern) => search_files_by_name(pattern).await?,
+        None => {
+            let root = get_root()?;
+            spawn_blocking(move || {
+                Walk::new(&root)
+                    .flatten()
+                    .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
+                    .filter_map(|e| {
+                        e.path()
+                            .strip_prefix(&root)
+                            .map(|r| r.to_string_lossy().to_string())
+                            .ok()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await?
+        }
+    };
+    files.retain(|f| {
+        !matches!(
+            determine_file_type(Path::new(f)),
+            FileType::Image(_) | FileType::Unsupported
+        )
+    });
+    files.sort();
+    Ok(files)
+}
+
 #[tauri::command]
 pub async fn search_files_by_name_interactive(
     globs: &str,
@@ -0,0 +1,538 @@
+//! Semantic retrieval over the files under the root.
+//!
+//! File texts are split into chunks that keep their page, slide or sheet
+//! location, embedded by a pluggable backend and cached on disk by file
+//! content hash, so unchanged files are never embedded twice. Queries are
+//! answered by cosine similarity against every chunk.
+
+use crate::Result;
+use crate::content_index::{Stamp, tokenize};
+use crate::error::Error;
+use crate::file_handler::{self, determine_file_type, text_sections};
+use crate::llm::{self, backend_from_settings};
+use crate::search::text_files;
+use crate::settings::{EmbeddingBackend, Settings, get_root, get_settings};
+use futures::future::BoxFuture;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::task::spawn_blocking;
+
+/// Bumped whenever chunking changes, so cached vectors are recomputed.
+const CHUNKING_VERSION: u32 = 2;
+const CHUNK_WORDS: usize = 200;
+const MAX_CHUNK_BYTES: usize = 4000;
+const LOCAL_DIMENSIONS: usize = 1024;
+const API_BATCH_SIZE: usize = 64;
+
+/// Turns texts into vectors. Implementations must return one vector per
+/// text, in order.
+pub trait Embedder: Send + Sync {
+    /// Identifies the model, so vectors of different models never mix.
+    fn id(&self) -> String;
+
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>>;
+}
+
+/// Feature hashing of words, word pairs and word trigrams. Deterministic
+/// and offline; it captures shared vocabulary rather than meaning.
+pub struct LocalEmbedder;
+
+impl Embedder for LocalEmbedder {
+    fn id(&self) -> String {
+        format!("local-hash-{LOCAL_DIMENSIONS}")
+    }
+
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+        let vectors = texts.iter().map(|t| hash_embedding(t)).collect();
+        Box::pin(async move { Ok(vectors) })
+    }
+}
+
+/// FNV-1a, stable across platforms and releases unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn hash_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; LOCAL_DIMENSIONS];
+    let mut add = |feature: &str, weight: f32| {
+        let hash = fnv1a(feature.as_bytes());
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % LOCAL_DIMENSIONS as u64) as usize] += sign * weight;
+    };
+    let words = tokenize(text);
+    for word in &words {
+        add(word, 1.0);
+        // Trigrams let "launch" and "launches" share most of their features.
+        let chars: Vec<char> = format!("^{word}$").chars().collect();
+        for trigram in chars.windows(3) {
+            add(&trigram.iter().collect::<String>(), 0.2);
+        }
+    }
+    for pair in words.windows(2) {
+        add(&format!("{} {}", pair[0], pair[1]), 0.5);
+    }
+    normalize(&mut vector);
+    vector
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Any OpenAI compatible `/embeddings` endpoint.
+pub struct ApiEmbedder {
+    pub endpoint: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+impl Embedder for ApiEmbedder {
+    fn id(&self) -> String {
+        format!("{} {}", self.endpoint, self.model)
+    }
+
+    fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, Result<Vec<Vec<f32>>>> {
+        Box::pin(async move {
+            let mut out = Vec::with_capacity(texts.len());
+            for batch in texts.chunks(API_BATCH_SIZE) {
+                let builder = llm::client()
+                    .post(&self.endpoint)
+                    .json(&json!({"model": self.model, "input": batch}));
+                let builder = match &self.api_key {
+                    Some(key) => builder.bearer_auth(key),
+                    None => builder,
+                };
+                let mut response: EmbeddingResponse = llm::send(builder).await?.json().await?;
+                response.data.sort_by_key(|d| d.index);
+                out.extend(response.data.into_iter().map(|d| {
+                    let mut vector = d.embedding;
+                    normalize(&mut vector);
+                    vector
+                }));
+            }
+            if out.len() != texts.len() {
+                return Err(Error::Tool(format!(
+                    "embedding endpoint returned {} vectors for {} texts",
+                    out.len(),
+                    texts.len()
+                )));
+            }
+            Ok(out)
+        })
+    }
+}
+
+pub fn embedder_from_settings(settings: &Settings) -> Arc<dyn Embedder> {
+    match settings.embedding_backend {
+        EmbeddingBackend::Local => Arc::new(LocalEmbedder),
+        EmbeddingBackend::Api => {
+            // Embeddings live next to chat completions on every supported backend.
+            let backend = backend_from_settings(settings);
+            let endpoint = backend.endpoint();
+            let base = endpoint
+                .strip_suffix("/chat/completions")
+                .unwrap_or(&endpoint);
+            Arc::new(ApiEmbedder {
+                endpoint: format!("{base}/embeddings"),
+                api_key: backend.api_key(),
+                model: settings.embedding_model.clone(),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Chunk {
+    location: String,
+    text: String,
+    vector: Vec<f32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SemanticMatch {
+    pub file: String,
+    pub location: String,
+    pub score: f32,
+    pub text: String,
+}
+
+/// Splits a file's text into runs of about `CHUNK_WORDS` words that never
+/// straddle a page, slide or sheet, each with its location. Runs longer than
+/// `MAX_CHUNK_BYTES`, such as a long line, are cut into parts.
+fn chunk_text(path: &Path, text: &str) -> Vec<(String, String)> {
+    let mut out = vec![];
+    for section in text_sections(&determine_file_type(path), text) {
+        let mut start = 0;
+        let mut words = 0;
+        for (i, line) in section.lines.iter().enumerate() {
+            words += line.split_whitespace().count();
+            if words < CHUNK_WORDS && i + 1 < section.lines.len() {
+                continue;
+            }
+            let lines = &section.lines[start..=i];
+            let text = lines.join("\n");
+            if !text.trim().is_empty() {
+                let position = match (start + 1, i + 1) {
+                    (a, b) if a == b => format!("{} {a}", section.unit),
+                    (a, b) => format!("{}s {a}-{b}", section.unit),
+                };
+                let location = match &section.label {
+                    Some(label) => format!("{label}, {position}"),
+                    None => position,
+                };
+                let text = text.trim();
+                if text.len() <= MAX_CHUNK_BYTES {
+                    out.push((location, text.to_string()));
+                } else {
+                    for (n, piece) in file_handler::split_line(text, MAX_CHUNK_BYTES)
+                        .into_iter()
+                        .enumerate()
+                    {
+                        out.push((format!("{location}, part {}", n + 1), piece.to_string()));
+                    }
+                }
+            }
+            start = i + 1;
+            words = 0;
+        }
+    }
+    out
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Where the chunks of a file with content hash `hash` are kept for the
+/// embedder with id `embedder`.
+fn store_path(embedder: &str, hash: &str) -> Result<PathBuf> {
+    let key = sha256_hex(format!("{embedder} v{CHUNKING_VERSION}").as_bytes());
+    let dir = crate::cache_root()?.join("embeddings").join(&key[..16]);
+    Ok(dir.join(format!("{hash}.json")).into_std_path_buf())
+}
+
+/// Chunks of one file, either already embedded or waiting to be.
+enum FileChunks {
+    Embedded(Arc<Vec<Chunk>>),
+    Pending(Vec<(String, String)>),
+}
+
+/// Embedded chunks by (embedder id, content hash).
+type ChunkCache = HashMap<(String, String), Arc<Vec<Chunk>>>;
+
+#[derive(Default)]
+pub struct SemanticState {
+    /// Content hash of each file seen, reused while its size and mtime hold.
+    hashes: Mutex<HashMap<PathBuf, (Stamp, String)>>,
+    /// Embedded chunks of the files seen by the last search.
+    chunks: Mutex<ChunkCache>,
+}
+
+impl SemanticState {
+    fn hash(&self, path: &Path) -> Option<String> {
+        let stamp = Stamp::of(path)?;
+        if let Some((known, hash)) = self.hashes.lock().unwrap().get(path) // unwrap: won't try to recover from poisoned lock
+            && *known == stamp
+        {
+            return Some(hash.clone());
+        }
+        let hash = sha256_hex(&fs::read(path).ok()?);
+        self.hashes
+            .lock()
+            .unwrap() // unwrap: won't try to recover from poisoned lock
+            .insert(path.to_path_buf(), (stamp, hash.clone()));
+        Some(hash)
+    }
+
+    /// Cached chunks of a file, or its chunk texts if it needs embedding.
+    fn file_chunks(&self, embedder: &str, path: &Path, hash: &str) -> FileChunks {
+        let key = (embedder.to_string(), hash.to_string());
+        if let Some(chunks) = self.chunks.lock().unwrap().get(&key) {
+            // unwrap: won't try to recover from poisoned lock
+            return FileChunks::Embedded(chunks.clone());
+        }
+        if let Ok(store) = store_path(embedder, hash)
+            && let Ok(bytes) = fs::read(store)
+            && let Ok(chunks) = serde_json::from_slice(&bytes)
+        {
+            return FileChunks::Embedded(Arc::new(chunks));
+        }
+        let text = file_handler::file_text(path)
+            .inspect_err(|e| eprintln!("Cannot read {path:?} for embedding: {e}"))
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        FileChunks::Pending(chunk_text(path, &text))
+    }
+
+    pub async fn search(
+        &'static self,
+        embedder: &dyn Embedder,
+        query: &str,
+        top_k: usize,
+        pattern: Option<&str>,
+    ) -> Result<Vec<SemanticMatch>> {
+        if query.trim().is_empty() {
+            return Err(Error::Tool("query is empty".to_string()));
+        }
+        let root = get_root()?;
+        let files = text_files(pattern).await?;
+        let id = embedder.id();
+        let files: Vec<(String, String, FileChunks)> = spawn_blocking({
+            let id = id.clone();
+            move || {
+                files
+                    .into_par_iter()
+                    .filter_map(|file| {
+                        let path = root.join(&file).into_std_path_buf();
+                        let hash = self.hash(&path)?;
+                        let chunks = self.file_chunks(&id, &path, &hash);
+                        Some((file, hash, chunks))
+                    })
+                    .collect()
+            }
+        })
+        .await?;
+
+        let mut embedded = HashMap::new();
+        let mut all = vec![];
+        // Embedded and saved a batch at a time, so a failure keeps earlier batches.
+        let mut batch = vec![];
+        let mut batch_len = 0;
+        let mut files = files.into_iter().peekable();
+        while let Some((file, hash, chunks)) = files.next() {
+            match chunks {
+                FileChunks::Embedded(chunks) => {
+                    embedded.insert((id.clone(), hash), chunks.clone());
+                    all.push((file, chunks));
+                }
+                FileChunks::Pending(pending) => {
+                    batch_len += pending.len();
+                    batch.push((file, hash, pending));
+                }
+            }
+            if batch.is_empty() || (batch_len < API_BATCH_SIZE && files.peek().is_some()) {
+                continue;
+            }
+            let texts: Vec<String> = batch
+                .iter()
+                .flat_map(|(_, _, pending)| pending.iter().map(|(_, text)| text.clone()))
+                .collect();
+            let mut vectors = embedder.embed(&texts).await?.into_iter();
+            for (file, hash, pending) in batch.drain(..) {
+                let chunks: Vec<Chunk> = pending
+                    .into_iter()
+                    .zip(vectors.by_ref())
+                    .map(|((location, text), vector)| Chunk {
+                        location,
+                        text,
+                        vector,
+                    })
+                    .collect();
+                if let Err(e) = save_chunks(&id, &hash, &chunks) {
+                    eprintln!("Cannot save embeddings of {file}: {e}");
+                }
+                let chunks = Arc::new(chunks);
+                embedded.insert((id.clone(), hash), chunks.clone());
+                all.push((file, chunks));
+            }
+            batch_len = 0;
+        }
+        *self.chunks.lock().unwrap() = embedded; // unwrap: won't try to recover from poisoned lock
+
+        let query = embedder.embed(&[query.to_string()]).await?;
+        let query = query.first().ok_or(Error::Other)?;
+        let mut hits: Vec<SemanticMatch> = all
+            .iter()
+            .flat_map(|(file, chunks)| {
+                chunks.iter().map(move |chunk| SemanticMatch {
+                    file: file.clone(),
+                    location: chunk.location.clone(),
+                    score: chunk.vector.iter().zip(query).map(|(a, b)| a * b).sum(),
+                    text: chunk.text.clone(),
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.file.cmp(&b.file))
+        });
+        hits.truncate(top_k);
+        Ok(hits)
+    }
+}
+
+fn save_chunks(embedder: &str, hash: &str, chunks: &[Chunk]) -> Result<()> {
+    let path = store_path(embedder, hash)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(chunks)?)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+pub static SEMANTIC: LazyLock<SemanticState> = LazyLock::new(Default::default);
+
+#[tauri::command]
+pub async fn semantic_search(
+    query: String,
+    top_k: usize,
+    pattern: Option<String>,
+) -> Result<Vec<SemanticMatch>> {
+    let embedder = embedder_from_settings(&get_settings()?);
+    SEMANTIC
+        .search(embedder.as_ref(), &query, top_k, pattern.as_deref())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{API_BATCH_SIZE, Embedder, LocalEmbedder, MAX_CHUNK_BYTES, SEMANTIC, chunk_text};
+    use crate::test_support::Harness;
+    use futures::future::BoxFuture;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts the texts it is asked to embed, delegating to `LocalEmbedder`.
+    #[derive(Default)]
+    struct CountingEmbedder(AtomicUsize);
+
+    impl Embedder for CountingEmbedder {
+        fn id(&self) -> String {
+            "counting".to_string()
+        }
+
+        fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, crate::Result<Vec<Vec<f32>>>> {
+            self.0.fetch_add(texts.len(), Ordering::SeqCst);
+            LocalEmbedder.embed(texts)
+        }
+    }
+
+    /// Same id as `CountingEmbedder`, but fails after its first call.
+    #[derive(Default)]
+    struct FailingEmbedder(AtomicUsize);
+
+    impl Embedder for FailingEmbedder {
+        fn id(&self) -> String {
+            "counting".to_string()
+        }
+
+        fn embed<'a>(&'a self, texts: &'a [String]) -> BoxFuture<'a, crate::Result<Vec<Vec<f32>>>> {
+            match self.0.fetch_add(1, Ordering::SeqCst) {
+                0 => LocalEmbedder.embed(texts),
+                _ => Box::pin(async { Err(crate::Error::Tool("quota".to_string())) }),
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_keep_locations() {
+        let text = format!(
+            "## Page 1\n\nshort\n\n## Page 2\n\n{}\nend",
+            "word ".repeat(250)
+        );
+        let chunks = chunk_text(Path::new("a.pdf"), &text);
+        let locations: Vec<_> = chunks.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(
+            locations,
+            ["page 1, line 1", "page 2, line 1", "page 2, line 2"]
+        );
+        assert_eq!(chunks[2].1, "end");
+    }
+
+    #[test]
+    fn long_lines_are_chunked_whole() {
+        let line: String = (0..1000).map(|i| format!("word{i:05} ")).collect();
+        assert_eq!(line.len(), 10_000);
+        let chunks = chunk_text(Path::new("a.txt"), &format!("{line}\ntail"));
+        let locations: Vec<_> = chunks.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(
+            locations,
+            [
+                "line 1, part 1",
+                "line 1, part 2",
+                "line 1, part 3",
+                "line 2"
+            ]
+        );
+        assert!(chunks.iter().all(|(_, t)| t.len() <= MAX_CHUNK_BYTES));
+        let joined: String = chunks[..3].iter().map(|(_, t)| t.as_str()).collect();
+        assert_eq!(joined, line.trim_end());
+    }
+
+    #[tokio::test]
+    async fn search_ranks_chunks_and_embeds_once() {
+        let harness = Harness::new().await;
+        harness.write(
+            "falcon.md",
+            "Project Falcon launches in May.\n\nBudget is tight.",
+        );
+        harness.write("menu.txt", "Soup, salad and bread for lunch.");
+        harness.write("photo.png", "falcon");
+        let embedder = CountingEmbedder::default();
+
+        let hits = SEMANTIC
+            .search(&embedder, "when does falcon launch", 2, None)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].file, "falcon.md");
+        assert_eq!(hits[0].location, "lines 1-3");
+        assert!(hits[0].score > hits[1].score);
+        assert_eq!(embedder.0.load(Ordering::SeqCst), 3);
+
+        // Unchanged files reuse their vectors; only the query is embedded.
+        SEMANTIC.search(&embedder, "lunch", 1, None).await.unwrap();
+        assert_eq!(embedder.0.load(Ordering::SeqCst), 4);
+
+        harness.write("menu.txt", "Soup of the day.");
+        let hits = SEMANTIC
+            .search(&embedder, "soup", 5, Some("menu"))
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].text, "Soup of the day.");
+        assert!(SEMANTIC.search(&embedder, " ", 5, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_batch_keeps_earlier_batches() {
+        let harness = Harness::new().await;
+        for i in 0..=API_BATCH_SIZE {
+            harness.write(&format!("note{i}.txt"), &format!("Note number {i}."));
+        }
+        let failing = FailingEmbedder::default();
+        assert!(SEMANTIC.search(&failing, "note", 1, None).await.is_err());
+
+        let embedder = CountingEmbedder::default();
+        SEMANTIC.search(&embedder, "note", 1, None).await.unwrap();
+        // The remaining file and the query.
+        assert_eq!(embedder.0.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,304 @@
+//! Local HTTP API mirroring the Tauri commands, for scripts and other
+//! front ends. Chat events are streamed as server-sent events.
+
+use crate::error::Error;
+use crate::interaction::Content;
+use crate::settings::{self, Settings, get_settings};
+use crate::sinks::ChannelSink;
+use crate::{AppStateInner, Result, search};
+use axum::extract::{Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use futures::Stream;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::convert::Infallible;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+#[derive(Clone)]
+struct ServerState {
+    app: Arc<AppStateInner>,
+    token: Arc<str>,
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    content: Vec<Content>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    globs: String,
+}
+
+#[derive(Deserialize)]
+struct SelectionRequest {
+    path: String,
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match self {
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Tool(_)
+            | Error::Limit { .. }
+            | Error::GlobSet(_)
+            | Error::Json(_)
+            | Error::Transcript(_) => StatusCode::BAD_REQUEST,
+            Error::Api { .. } => StatusCode::BAD_GATEWAY,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(json!({"error": self.to_string()}))).into_response()
+    }
+}
+
+/// Binds `settings.server_bind` and serves until the listener fails.
+pub async fn start(app: Arc<AppStateInner>, settings: &Settings) -> Result<()> {
+    if settings.server_token.is_empty() {
+        return Err(Error::Tool(
+            "API server requires a token in settings".to_string(),
+        ));
+    }
+    let listener = TcpListener::bind(&settings.server_bind).await?;
+    eprintln!("API server listening on {}", listener.local_addr()?);
+    serve(app, listener, &settings.server_token).await
+}
+
+pub async fn serve(app: Arc<AppStateInner>, listener: TcpListener, token: &str) -> Result<()> {
+    let state = ServerState {
+        app,
+        token: token.into(),
+    };
+    let router = Router::new()
+        .route("/chat", post(chat))
+        .route("/cancel", post(cancel))
+        .route("/history", get(history).delete(clear_history))
+        .route("/search", get(search_files))
+        .route(
+            "/selection",
+            get(selection).post(selection_add).delete(selection_remove),
+        )
+        .route("/selection/all", delete(selection_clear))
+        .route("/settings", get(read_settings).put(write_settings))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn authorize(State(state): State<ServerState>, req: Request, next: Next) -> Response {
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|t| t.as_bytes().ct_eq(state.token.as_bytes()).into());
+    if authorized {
+        next.run(req).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+/// Streams the same events the UI receives. A failed request ends the stream
+/// with an `Error` event. Disconnecting does not cancel; use `/cancel`.
+async fn chat(
+    State(state): State<ServerState>,
+    Json(req): Json<ChatRequest>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let app = Arc::clone(&state.app);
+    tokio::spawn(async move {
+        let sink = Arc::new(ChannelSink::new(tx.clone()));
+        if let Err(e) = app.chat(sink, req.content, None).await {
+            let _ = tx.send(json!({"type": "Error", "message": e.to_string()}));
+        }
+    });
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let event = Event::default()
+            .json_data(event)
+            .unwrap_or_else(|_| Event::default()); // serializing a Value can't fail
+        Some((Ok(event), rx))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+async fn cancel(State(state): State<ServerState>) -> StatusCode {
+    state.app.cancel_outstanding_request();
+    StatusCode::NO_CONTENT
+}
+
+async fn history(State(state): State<ServerState>) -> Result<Json<Vec<Value>>> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    state.app.replay_history(&ChannelSink::new(tx))?;
+    let mut events = vec![];
+    while let Ok(event) = rx.try_recv() {
+        events.push(event);
+    }
+    Ok(Json(events))
+}
+
+async fn clear_history(State(state): State<ServerState>) -> StatusCode {
+    state.app.clear_history();
+    StatusCode::NO_CONTENT
+}
+
+async fn search_files(Query(query): Query<SearchQuery>) -> Result<Json<Vec<String>>> {
+    let mut files = search::search_files_by_name(&query.globs).await?;
+    files.sort();
+    Ok(Json(files))
+}
+
+async fn selection() -> Json<Vec<String>> {
+    let mut selection: Vec<_> = search::SELECTION_STATE
+        .selection
+        .read()
+        .unwrap() // unwrap: won't try to recover from poisoned lock
+        .iter()
+        .cloned()
+        .collect();
+    selection.sort();
+    Json(selection)
+}
+
+async fn selection_add(Json(req): Json<SelectionRequest>) -> Json<bool> {
+    Json(search::selection_add(req.path))
+}
+
+async fn selection_remove(Json(req): Json<SelectionRequest>) -> Json<bool> {
+    Json(search::selection_remove(&req.path))
+}
+
+async fn selection_clear() -> StatusCode {
+    search::selection_clear();
+    StatusCode::NO_CONTENT
+}
+
+/// Secrets are blanked; writing a blank secret back keeps the stored one.
+async fn read_settings() -> Result<Json<Settings>> {
+    Ok(Json(Settings {
+        api_key: "".to_string(),
+        server_token: "".to_string(),
+        ..get_settings()?
+    }))
+}
+
+async fn write_settings(Json(mut settings): Json<Settings>) -> Result<StatusCode> {
+    let current = get_settings()?;
+    if settings.api_key.is_empty() {
+        settings.api_key = current.api_key;
+    }
+    if settings.server_token.is_empty() {
+        settings.server_token = current.server_token;
+    }
+    settings::set_settings(settings)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::AppStateInner;
+    use crate::search::selection_clear;
+    use crate::test_support::Harness;
+    use serde_json::{Value, json};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn requires_token_and_serves_search_and_selection() {
+        let harness = Harness::new().await;
+        harness.write("docs/report.txt", "hello");
+        harness.write("notes.md", "hi");
+        harness.settings(|s| {
+            s.api_key = "sk-test".to_string();
+            s.server_token = "secret".to_string();
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let app = Arc::new(AppStateInner::default());
+        tokio::spawn(async move { serve(app, listener, "secret").await });
+        let client = reqwest::Client::new();
+
+        let res = client
+            .get(format!("{base}/selection"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 401);
+        let res = client
+            .get(format!("{base}/selection"))
+            .bearer_auth("wrong")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), 401);
+
+        let found: Vec<String> = client
+            .get(format!("{base}/search?globs=report"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("report.txt"));
+
+        let added: bool = client
+            .post(format!("{base}/selection"))
+            .bearer_auth("secret")
+            .json(&json!({"path": found[0]}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(added);
+        let selection: Vec<String> = client
+            .get(format!("{base}/selection"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(selection, found);
+
+        let history: Vec<Value> = client
+            .get(format!("{base}/history"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(history.is_empty());
+
+        let settings: Value = client
+            .get(format!("{base}/settings"))
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(settings["apiKey"], "");
+        assert_eq!(settings["serverToken"], "");
+        assert!(!settings["rootDir"].as_str().unwrap().is_empty());
+        selection_clear();
+    }
+}
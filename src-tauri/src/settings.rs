@@ -1,10 +1,42 @@
 use camino::{Utf8Path, Utf8PathBuf};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_value, to_value};
+use std::sync::RwLock;
 
 use crate::{Result, STORE, error::Error, search::selection_clear};
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Openrouter,
+    OpenaiCompatible,
+    Ollama,
+}
+
+/// Where `semantic_search` gets its embeddings from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingBackend {
+    /// Feature hashing computed in process. Offline and free, but lexical.
+    #[default]
+    Local,
+    /// The `/embeddings` endpoint of the configured chat backend.
+    Api,
+}
+
+/// Hard limits on LLM spend. Unset fields are unlimited.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Budget {
+    pub max_calls: Option<u64>,
+    pub max_tokens: Option<u64>,
+    /// In USD, as reported by OpenRouter.
+    pub max_cost: Option<f64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Settings {
     #[serde(rename = "apiKey")]
     pub api_key: String,
@@ -16,6 +48,55 @@ pub struct Settings {
     pub soffice_path: String,
     #[serde(rename = "providerOrder")]
     pub provider_order: String,
+    pub backend: Backend,
+    /// Base URL for the OpenAI compatible and Ollama backends, e.g. `http://localhost:11434/v1`.
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    /// Send PDFs as their extracted text layer, for models without PDF input.
+    #[serde(rename = "pdfAsText")]
+    pub pdf_as_text: bool,
+    /// Serve the local HTTP API on `server_bind`. Takes effect on restart.
+    #[serde(rename = "serverEnabled")]
+    pub server_enabled: bool,
+    #[serde(rename = "serverBind")]
+    pub server_bind: String,
+    /// Bearer token every API request must carry. The server refuses to start without one.
+    #[serde(rename = "serverToken")]
+    pub server_token: String,
+    #[serde(rename = "embeddingBackend")]
+    pub embedding_backend: EmbeddingBackend,
+    /// Model for the API embedding backend, e.g. `openai/text-embedding-3-small`.
+    #[serde(rename = "embeddingModel")]
+    pub embedding_model: String,
+    /// Model `ask_files` queries each file with.
+    #[serde(rename = "mapModelName")]
+    pub map_model_name: String,
+    /// Map model used when the agent asks for the `accurate` tier.
+    #[serde(rename = "mapAccurateModelName")]
+    pub map_accurate_model_name: String,
+    /// OpenRouter provider order for map calls. Empty uses `provider_order`.
+    #[serde(rename = "mapProviderOrder")]
+    pub map_provider_order: String,
+    /// Sampling temperature for map calls. Unset uses the model's default.
+    #[serde(rename = "mapTemperature")]
+    pub map_temperature: Option<f64>,
+    /// System prompt for map calls, with `{file}` and `{query}` substituted.
+    /// Empty uses the built-in prompt.
+    #[serde(rename = "mapPrompt")]
+    pub map_prompt: String,
+    /// Limits for one chat request, including the calls its tools make.
+    #[serde(rename = "requestBudget")]
+    pub request_budget: Budget,
+    /// Limits for everything spent today, across folders.
+    #[serde(rename = "dailyBudget")]
+    pub daily_budget: Budget,
+    /// Agent loop iterations (completions of the main model) per request.
+    #[serde(rename = "maxIterations")]
+    pub max_iterations: Option<u64>,
+    /// Estimated tokens of history sent to the model. Earlier turns are
+    /// compacted beyond it. Unset sends everything.
+    #[serde(rename = "contextBudget")]
+    pub context_budget: Option<usize>,
 }
 
 #[tauri::command]
@@ -29,8 +110,16 @@ pub fn set_settings(settings: Settings) -> Result<()> {
     Ok(())
 }
 
+/// Settings supplied by a host without a Tauri store, e.g. the CLI or unit tests.
+/// Takes precedence over the store when set.
+pub static SETTINGS_OVERRIDE: RwLock<Option<Settings>> = RwLock::new(None);
+
 #[tauri::command]
 pub fn get_settings() -> Result<Settings> {
+    if let Some(settings) = SETTINGS_OVERRIDE.read().unwrap().clone() {
+        // unwrap: won't try to recover from poisoned lock
+        return Ok(settings);
+    }
     let store = STORE
         .get()
         .ok_or(Error::Io(std::io::ErrorKind::NotFound.into()))?;
@@ -56,6 +145,23 @@ impl Default for Settings {
             root_dir: "".to_string(),
             soffice_path: "".to_string(),
             provider_order: "google-vertex".to_string(),
+            backend: Backend::default(),
+            base_url: "".to_string(),
+            pdf_as_text: false,
+            server_enabled: false,
+            server_bind: "127.0.0.1:8765".to_string(),
+            server_token: "".to_string(),
+            embedding_backend: EmbeddingBackend::default(),
+            embedding_model: "openai/text-embedding-3-small".to_string(),
+            map_model_name: "google/gemini-2.5-flash".to_string(),
+            map_accurate_model_name: "google/gemini-2.5-pro".to_string(),
+            map_provider_order: "".to_string(),
+            map_temperature: None,
+            map_prompt: "".to_string(),
+            request_budget: Budget::default(),
+            daily_budget: Budget::default(),
+            max_iterations: Some(50),
+            context_budget: Some(200_000),
         }
     }
 }
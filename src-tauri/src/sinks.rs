@@ -0,0 +1,56 @@
+use crate::Result;
+use crate::ui_events::{EventPayload, EventSink};
+use serde_json::{Value, to_value};
+use std::io::Write;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Writes every event as one JSON object per line, e.g. to stdout or stderr.
+pub struct JsonLinesSink {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesSink {
+    pub fn new(out: Box<dyn Write + Send>) -> Self {
+        Self {
+            out: Mutex::new(out),
+        }
+    }
+}
+
+impl EventSink for JsonLinesSink {
+    fn emit(&self, payload: EventPayload<'_>) -> Result<()> {
+        let mut out = self.out.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+        serde_json::to_writer(&mut *out, &payload)?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Drops every event.
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn emit(&self, _payload: EventPayload<'_>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Forwards every event to a channel, e.g. to stream it to an HTTP client.
+/// Events are dropped once the receiver has gone away.
+pub struct ChannelSink {
+    tx: UnboundedSender<Value>,
+}
+
+impl ChannelSink {
+    pub fn new(tx: UnboundedSender<Value>) -> Self {
+        Self { tx }
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn emit(&self, payload: EventPayload<'_>) -> Result<()> {
+        let _ = self.tx.send(to_value(payload)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,240 @@
+//! Scripted stand-ins for driving the agent loop in unit tests, without a
+//! network connection or a window.
+
+use crate::error::Error;
+use crate::llm::LlmBackend;
+use crate::openrouter::{
+    ChatResponse, Choice, FunctionCall, IncomingContent, IncomingMessage, StreamAccumulator,
+    StreamDelta, ToolCall,
+};
+use crate::settings::{SETTINGS_OVERRIDE, Settings};
+use crate::ui_events::{EventPayload, EventSink};
+use crate::usage::Usage;
+use crate::{DIR_OVERRIDE, Result};
+use camino::Utf8PathBuf;
+use futures::future::BoxFuture;
+use serde_json::{Value, json, to_value};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// Tests touching the global settings or search state must not overlap.
+static HARNESS_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+pub fn text(text: &str) -> IncomingMessage {
+    IncomingMessage {
+        role: "assistant".to_string(),
+        content: IncomingContent::Text(text.to_string()),
+        tool_calls: None,
+    }
+}
+
+pub fn tool_calls(calls: &[(&str, &str, Value)]) -> IncomingMessage {
+    IncomingMessage {
+        role: "assistant".to_string(),
+        content: IncomingContent::None,
+        tool_calls: Some(
+            calls
+                .iter()
+                .map(|(id, name, args)| ToolCall {
+                    id: id.to_string(),
+                    r#type: "function".to_string(),
+                    function: FunctionCall {
+                        name: name.to_string(),
+                        arguments: args.to_string(),
+                    },
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Renders a message the way a streaming server would send it: content word
+/// by word, tool call arguments split in two fragments and usage last.
+fn to_sse(message: &IncomingMessage, usage: &Usage) -> String {
+    let mut out = String::from(": OPENROUTER PROCESSING\n\n");
+    let mut push = |delta: Value| {
+        out.push_str(&format!(
+            "data: {}\n\n",
+            json!({"choices": [{"delta": delta}]})
+        ));
+    };
+    if let IncomingContent::Text(text) = &message.content {
+        for word in text.split_inclusive(' ') {
+            push(json!({"content": word}));
+        }
+    }
+    for (index, call) in message.tool_calls.iter().flatten().enumerate() {
+        let args = &call.function.arguments;
+        let (head, tail) = args.split_at(args.len() / 2);
+        push(json!({"tool_calls": [{
+            "index": index,
+            "id": call.id,
+            "type": "function",
+            "function": {"name": call.function.name, "arguments": head}
+        }]}));
+        push(json!({"tool_calls": [{"index": index, "function": {"arguments": tail}}]}));
+    }
+    if *usage != Usage::default() {
+        out.push_str(&format!(
+            "data: {}\n\n",
+            json!({"choices": [], "usage": usage})
+        ));
+    }
+    out.push_str("data: [DONE]\n\n");
+    out
+}
+
+/// Answers each completion request with the next scripted message and
+/// records the request bodies it received.
+pub struct ScriptedBackend {
+    responses: Mutex<VecDeque<IncomingMessage>>,
+    pub requests: Mutex<Vec<Value>>,
+    usage: Usage,
+}
+
+impl ScriptedBackend {
+    pub fn new(responses: Vec<IncomingMessage>) -> Arc<Self> {
+        Self::with_usage(responses, Usage::default())
+    }
+
+    /// Reports `usage` with every response.
+    pub fn with_usage(responses: Vec<IncomingMessage>, usage: Usage) -> Arc<Self> {
+        Arc::new(Self {
+            responses: Mutex::new(responses.into()),
+            requests: Mutex::new(vec![]),
+            usage,
+        })
+    }
+
+    fn next(&self, body: Value) -> Result<IncomingMessage> {
+        self.requests.lock().unwrap().push(body);
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(Error::Tool("script exhausted".to_string()))
+    }
+}
+
+impl LlmBackend for ScriptedBackend {
+    fn endpoint(&self) -> String {
+        "scripted".to_string()
+    }
+
+    fn api_key(&self) -> Option<String> {
+        None
+    }
+
+    fn complete(&self, body: Value) -> BoxFuture<'_, Result<ChatResponse>> {
+        let message = self.next(body);
+        Box::pin(async move {
+            Ok(ChatResponse {
+                choices: vec![Choice { message: message? }],
+                usage: self.usage,
+            })
+        })
+    }
+
+    fn complete_streaming<'a>(
+        &'a self,
+        body: Value,
+        on_delta: &'a mut (dyn FnMut(StreamDelta) + Send),
+    ) -> BoxFuture<'a, Result<ChatResponse>> {
+        let message = self.next(body);
+        Box::pin(async move {
+            let sse = to_sse(&message?, &self.usage);
+            let mut accumulator = StreamAccumulator::default();
+            // Feed in uneven pieces so events straddle chunk boundaries.
+            for piece in sse.as_bytes().chunks(37) {
+                for delta in accumulator.feed(std::str::from_utf8(piece).unwrap()) {
+                    on_delta(delta);
+                }
+            }
+            accumulator.into_response()
+        })
+    }
+}
+
+/// Keeps every event in memory so tests can assert on the sequence.
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<Value>>,
+}
+
+impl RecordingSink {
+    pub fn events(&self) -> Vec<Value> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn emit(&self, payload: EventPayload<'_>) -> Result<()> {
+        self.events.lock().unwrap().push(to_value(payload)?);
+        Ok(())
+    }
+}
+
+/// A temporary root directory wired into the settings, plus a sink
+/// recording the emitted events.
+pub struct Harness {
+    pub root: TempDir,
+    /// Cache and data directory for the duration of the test.
+    _app_dir: TempDir,
+    pub sink: Arc<RecordingSink>,
+    _guard: tokio::sync::MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    pub async fn new() -> Self {
+        let guard = HARNESS_LOCK.lock().await;
+        let root = TempDir::new().unwrap();
+        *SETTINGS_OVERRIDE.write().unwrap() = Some(Settings {
+            root_dir: root.path().to_string_lossy().to_string(),
+            ..Settings::default()
+        });
+        let app_dir = TempDir::new().unwrap();
+        *DIR_OVERRIDE.write().unwrap() =
+            Some(Utf8PathBuf::from_path_buf(app_dir.path().into()).unwrap());
+        Self {
+            root,
+            _app_dir: app_dir,
+            sink: Arc::new(RecordingSink::default()),
+            _guard: guard,
+        }
+    }
+
+    /// Changes the settings in effect for this test.
+    pub fn settings(&self, change: impl FnOnce(&mut Settings)) {
+        if let Some(settings) = SETTINGS_OVERRIDE.write().unwrap().as_mut() {
+            change(settings);
+        }
+    }
+
+    pub fn write(&self, name: &str, content: &str) {
+        let path = self.root.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap_or(Path::new(""))).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// Event types in order, with consecutive stream deltas collapsed into one.
+    pub fn event_types(&self) -> Vec<String> {
+        let mut out: Vec<String> = vec![];
+        for event in self.sink.events() {
+            let kind = event["type"].as_str().unwrap_or_default().to_string();
+            if kind.ends_with("Delta") && out.last() == Some(&kind) {
+                continue;
+            }
+            out.push(kind);
+        }
+        out
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        *SETTINGS_OVERRIDE.write().unwrap() = None;
+        *DIR_OVERRIDE.write().unwrap() = None;
+    }
+}
@@ -1,24 +1,137 @@
+use crate::ask_cache::{self, AskCache, AskCacheEntry};
+use crate::budget;
 use crate::error::Error;
-use crate::openrouter::{IncomingContent, Openrouter};
+use crate::interaction::Content;
+use crate::llm::{self, LlmBackend};
+use crate::openrouter::IncomingContent;
 use crate::search::{SELECTION_STATE, search_files_by_name};
+use crate::settings::Settings;
 use crate::tools::{Function, Tool};
 use crate::utils::jailed::Jailed;
+use chrono::Utc;
 use futures::stream::{self, StreamExt};
 use schemars::{JsonSchema, schema_for};
 use serde::{Deserialize, Serialize};
-use serde_json::{Value, from_str, json, to_value};
-use std::path::Path;
+use serde_json::{Value, from_str, from_value, json, to_string_pretty, to_value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tokio::task;
 
 use crate::Result;
 
 const MAX_CONCURRENCY: usize = 50;
-const MAP_MODEL: &str = "google/gemini-2.5-flash";
+/// Completions in flight across every `ask_files` call, including parallel tool calls.
+static IN_FLIGHT: Semaphore = Semaphore::const_new(MAX_CONCURRENCY);
+/// Bumped whenever `DEFAULT_MAP_PROMPT` or `AskFileResults` change, so cached answers aren't reused.
+const PROMPT_VERSION: u32 = 1;
+const DEFAULT_MAP_PROMPT: &str =
+    "You are a helpful assistant that answers questions about files. Your answer must be grounded.";
+const DEFAULT_FAN_IN: usize = 10;
+/// Largest file content sent in one map call; bigger files are split.
+const CHUNK_TOKEN_BUDGET: usize = 200_000;
+const MAX_CHUNK_CONCURRENCY: usize = 4;
+const REDUCE_PROMPT: &str = "You merge answers that different files gave to the same query into one consolidated answer. Keep every distinct fact, drop repetition and point out where files disagree. Only cite files and extracts that appear in the answers you are given.";
+
+/// Which map model answers, trading cost against accuracy.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelTier {
+    #[default]
+    Fast,
+    Accurate,
+}
+
+/// How map and reduce calls are made, from settings and the requested tier.
+pub struct MapConfig {
+    backend: Arc<dyn LlmBackend>,
+    model: String,
+    temperature: Option<f64>,
+    /// Provider order the backend was built with, for the cache key.
+    provider_order: String,
+    prompt: String,
+}
+
+impl MapConfig {
+    pub fn from_settings(settings: &Settings, tier: ModelTier) -> Self {
+        let mut map_settings = settings.clone();
+        if !settings.map_provider_order.is_empty() {
+            map_settings.provider_order = settings.map_provider_order.clone();
+        }
+        let model = match tier {
+            ModelTier::Fast => &settings.map_model_name,
+            ModelTier::Accurate => &settings.map_accurate_model_name,
+        };
+        let prompt = match settings.map_prompt.trim() {
+            "" => DEFAULT_MAP_PROMPT,
+            prompt => prompt,
+        };
+        Self {
+            backend: llm::backend_from_settings(&map_settings),
+            model: model.clone(),
+            temperature: settings.map_temperature,
+            provider_order: map_settings.provider_order.clone(),
+            prompt: prompt.to_string(),
+        }
+    }
+
+    fn system_prompt(&self, filename: &str, query: &str) -> String {
+        self.prompt
+            .replace("{file}", filename)
+            .replace("{query}", query)
+    }
+
+    async fn call(&self, messages: &[Value], system_prompt: &str, schema: Value) -> Result<String> {
+        let _permit = IN_FLIGHT.acquire().await.unwrap(); // unwrap: never closed
+        let response = llm::call_with(
+            self.backend.as_ref(),
+            messages,
+            &self.model,
+            system_prompt,
+            &vec![],
+            Some(schema),
+            self.temperature,
+        )
+        .await?;
+        match &response.choices[0].message.content {
+            IncomingContent::Text(text) => Ok(text.clone()),
+            _ => Err(Error::Tool("model returned no text".to_string())),
+        }
+    }
+}
+
+/// How `ask_files` reports back.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AskMode {
+    /// One answer per file.
+    #[default]
+    PerFile,
+    /// A single answer merged from all files, with per-file citations.
+    Reduce,
+}
+
+/// Options shared by the `ask_files` tools, see `ask_parameters`.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AskOptions {
+    pub pages: Option<String>,
+    pub mode: AskMode,
+    /// Answers merged per reduce call.
+    pub fan_in: Option<usize>,
+    /// Query the files again instead of reusing cached answers.
+    pub bypass_cache: bool,
+    /// Map model to answer with, see `ModelTier`.
+    pub model_tier: ModelTier,
+}
 
 #[derive(Deserialize)]
 pub struct AskFilesArgs {
     pub query: String,
     pub filenames: Vec<String>,
+    #[serde(flatten)]
+    pub options: AskOptions,
 }
 
 #[derive(Deserialize)]
@@ -26,13 +139,18 @@ pub struct AskFilesGlobArgs {
     pub query: String,
     pub pattern: String,
     pub max_results: usize,
+    #[serde(flatten)]
+    pub options: AskOptions,
 }
 
 #[derive(Deserialize)]
 pub struct AskFilesSearchedArgs {
     pub query: String,
     pub max_results: usize,
+    #[serde(flatten)]
+    pub options: AskOptions,
 }
+
 #[derive(Deserialize)]
 pub struct UserSelectedArgs {
     pub max_results: usize,
@@ -43,15 +161,97 @@ pub struct AskFileResults {
     answer: String,
     extracts: Vec<String>,
 }
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Clone)]
+pub struct Citation {
+    file: String,
+    extract: String,
+}
+
+/// An answer consolidated from one or more files.
+#[derive(Serialize, Deserialize, JsonSchema, Debug)]
+pub struct MergedAnswer {
+    answer: String,
+    citations: Vec<Citation>,
+}
+
+impl MergedAnswer {
+    fn from_file(file: &str, results: AskFileResults) -> Self {
+        Self {
+            answer: results.answer,
+            citations: results
+                .extracts
+                .into_iter()
+                .map(|extract| Citation {
+                    file: file.to_string(),
+                    extract,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReducedAnswer {
+    #[serde(flatten)]
+    merged: MergedAnswer,
+    /// Files that could not be queried, with the reason.
+    failed: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum AskFilesOutput {
+    PerFile(Vec<Result<Value>>),
+    Reduced(ReducedAnswer),
+}
+
+/// Parameters of the `ask_files` tools: their own `properties` plus the
+/// options they share.
+fn ask_parameters(properties: Value, required: &[&str]) -> Value {
+    let mut parameters = json!({
+        "type": "object",
+        "properties": {
+            "mode": {
+                "type": "string",
+                "enum": ["per_file", "reduce"],
+                "description": "`per_file` (default) returns one answer per file. `reduce` merges them into a single answer citing its files, for questions over many files whose individual answers would not fit in your context."
+            },
+            "fan_in": {
+                "type": "number",
+                "description": "With `reduce`, how many answers are merged at a time. Defaults to 10."
+            },
+            "model_tier": {
+                "type": "string",
+                "enum": ["fast", "accurate"],
+                "description": "`fast` (default) is cheap and good for extraction. Use `accurate` for questions needing careful reading or reasoning, at a higher cost."
+            },
+            "bypass_cache": {
+                "type": "boolean",
+                "description": "Answers to the same query on unchanged files are reused from a cache. Set to true to query the files again."
+            },
+            "pages": {
+                "type": "string",
+                "description": "Optional PDF pages to query in each file, e.g. \"40-55\". Ignored for other file types."
+            }
+        },
+        "required": required
+    });
+    if let (Some(all), Value::Object(own)) = (parameters["properties"].as_object_mut(), properties)
+    {
+        all.extend(own);
+    }
+    parameters
+}
+
 pub fn ask_files_tool() -> Tool {
     Tool {
         r#type: "function".to_string(),
         function: Function {
             name: "ask_files".to_string(),
-            description: "Queries files for direct answers to specific questions (the \"what\"). It's extremely fast and efficient, acting like a targeted search function.\n\n**Trade-off:** This speed comes at the cost of context. It provides isolated facts without the surrounding information, which can obscure the broader strategic picture (the \"why\"). Use this for quick data extraction, but switch to `load_file` when you need to understand relationships within the data.".to_string(),
-            parameters: serde_json::json!({
-                "type": "object",
-                "properties": {
+            description: "Queries files for direct answers to specific questions (the \"what\"). It's extremely fast and efficient, acting like a targeted search function.\n\n**Trade-off:** This speed comes at the cost of context. It provides isolated facts without the surrounding information, which can obscure the broader strategic picture (the \"why\"). Use this for quick data extraction, but switch to `load_file` when you need to understand relationships within the data.\n\nFiles too large for one call are split by page range, sheet or size, and each part's answer and extracts are prefixed with its location, e.g. `[pages 101-200]`.".to_string(),
+            parameters: ask_parameters(
+                json!({
                     "query": {
                         "type": "string",
                         "description": "The query to run against each file."
@@ -63,53 +263,353 @@ pub fn ask_files_tool() -> Tool {
                         },
                         "description": "A list of filenames to run the query against."
                     }
-                },
-                "required": ["query", "filenames"]
-            }),
+                }),
+                &["query", "filenames"],
+            ),
         },
     }
 }
 
-pub async fn ask_files(args: AskFilesArgs) -> Result<Vec<Result<Value>>> {
-    let AskFilesArgs { query, filenames } = args;
+/// A file about to be queried: its cached answer, or what querying it takes.
+enum Prepared {
+    Cached(AskFileResults),
+    Pending {
+        file_path: PathBuf,
+        key: String,
+        /// Map calls the file's parts need.
+        calls: usize,
+    },
+}
+
+/// Looks a file up in the result cache unless `bypass_cache`, and otherwise
+/// counts the map calls querying it takes.
+async fn prepare_file(
+    config: &MapConfig,
+    root_dir: &str,
+    filename: &str,
+    query: &str,
+    pages: Option<String>,
+    bypass_cache: bool,
+) -> Result<Prepared> {
+    let jail = Path::new(root_dir);
+    let file_path = jail.jailed_join(Path::new(filename))?;
+    let hash = task::spawn_blocking({
+        let file_path = file_path.clone();
+        move || ask_cache::file_hash(&file_path)
+    })
+    .await??;
+    let map_key = ask_cache::MapKey {
+        model: &config.model,
+        prompt_version: PROMPT_VERSION,
+        prompt: &config.prompt,
+        temperature: config.temperature,
+        provider_order: &config.provider_order,
+    };
+    let key = ask_cache::key(&hash, query, &map_key, pages.as_deref());
+    if !bypass_cache
+        && let Ok(cache) = AskCache::open()
+        && let Some(cached) = cache.get(&key)
+        && let Ok(results) = from_value(cached)
+    {
+        return Ok(Prepared::Cached(results));
+    }
+    let calls = task::spawn_blocking({
+        let file_path = file_path.clone();
+        move || crate::file_handler::count_chunks(&file_path, pages.as_deref(), CHUNK_TOKEN_BUDGET)
+    })
+    .await??;
+    Ok(Prepared::Pending {
+        file_path,
+        key,
+        calls,
+    })
+}
+
+/// Queries a prepared file and caches its answer if every part succeeded.
+async fn ask_file(
+    config: &MapConfig,
+    filename: &str,
+    query: &str,
+    pages: Option<String>,
+    prepared: Prepared,
+) -> Result<AskFileResults> {
+    let (file_path, key) = match prepared {
+        Prepared::Cached(results) => return Ok(results),
+        Prepared::Pending { file_path, key, .. } => (file_path, key),
+    };
+    let entry = AskCacheEntry {
+        file: filename.to_string(),
+        query: query.to_string(),
+        model: config.model.clone(),
+        prompt_version: PROMPT_VERSION,
+        pages: pages.clone(),
+        created_at: Utc::now(),
+    };
+    let (results, complete) = query_file(config, file_path, filename, query, pages).await?;
+    if complete
+        && let Ok(cache) = AskCache::open()
+        && let Err(e) = cache.put(&key, entry, to_value(&results)?)
+    {
+        eprintln!("Cannot cache answer for {filename}: {e}");
+    }
+    Ok(results)
+}
+
+/// Queries one file, or each part of a file too large for a single call,
+/// prefixing the answers and extracts of parts with their location. Also
+/// tells whether every part could be queried.
+async fn query_file(
+    config: &MapConfig,
+    file_path: PathBuf,
+    filename: &str,
+    query: &str,
+    pages: Option<String>,
+) -> Result<(AskFileResults, bool)> {
+    let mut chunks = task::spawn_blocking(move || {
+        crate::file_handler::chunk_file_for_llm(&file_path, pages.as_deref(), CHUNK_TOKEN_BUDGET)
+    })
+    .await??;
+
+    if let [chunk] = chunks.as_mut_slice()
+        && chunk.location.is_none()
+    {
+        let content = std::mem::take(&mut chunk.content);
+        let results = ask_chunk(config, filename, None, query, content).await?;
+        return Ok((results, true));
+    }
+
+    let results: Vec<(String, Result<AskFileResults>)> = stream::iter(chunks)
+        .map(|chunk| async move {
+            let location = chunk.location.unwrap_or_default();
+            let result = ask_chunk(config, filename, Some(&location), query, chunk.content).await;
+            (location, result)
+        })
+        .buffered(MAX_CHUNK_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut answers = vec![];
+    let mut extracts = vec![];
+    let mut complete = true;
+    for (location, result) in results {
+        match result {
+            Ok(output) => {
+                answers.push(format!("[{location}] {}", output.answer));
+                extracts.extend(
+                    output
+                        .extracts
+                        .into_iter()
+                        .map(|extract| format!("[{location}] {extract}")),
+                );
+            }
+            Err(e) => {
+                answers.push(format!("[{location}] could not be queried: {e}"));
+                complete = false;
+            }
+        }
+    }
+    let results = AskFileResults {
+        answer: answers.join("\n\n"),
+        extracts,
+    };
+    Ok((results, complete))
+}
+
+async fn ask_chunk(
+    config: &MapConfig,
+    filename: &str,
+    location: Option<&str>,
+    query: &str,
+    file_content: Vec<Content>,
+) -> Result<AskFileResults> {
+    let name = match location {
+        Some(location) => format!("{filename} ({location})"),
+        None => filename.to_string(),
+    };
+    let mut messages =
+        vec![json!({"role":"user","content":format!("File: {name}\n\nQuery: {query}")})];
+
+    messages.push(json!({"role":"user","content":file_content}));
+    let schema = to_value(schema_for!(AskFileResults)).unwrap(); // unwrap: all input controlled by code
+
+    let text = config
+        .call(&messages, &config.system_prompt(filename, query), schema)
+        .await?;
+    if let Ok(output) = from_str::<AskFileResults>(&text) {
+        return Ok(output);
+    }
+
+    Err(Error::Tool(format!("Error querying {name}")))
+}
+
+/// Merges one group of answers with a single call, keeping only citations
+/// of files the group actually covers.
+async fn merge_call(
+    config: &MapConfig,
+    query: &str,
+    mut group: Vec<MergedAnswer>,
+) -> Result<MergedAnswer> {
+    if group.len() == 1 {
+        return Ok(group.remove(0));
+    }
+    let files: HashSet<String> = group
+        .iter()
+        .flat_map(|a| a.citations.iter().map(|c| c.file.clone()))
+        .collect();
+    let messages = vec![json!({"role":"user","content":format!(
+        "Query: {query}\n\nAnswers to merge:\n\n{}",
+        to_string_pretty(&group)?
+    )})];
+    let schema = to_value(schema_for!(MergedAnswer)).unwrap(); // unwrap: all input controlled by code
+
+    let text = config.call(&messages, REDUCE_PROMPT, schema).await?;
+    if let Ok(mut merged) = from_str::<MergedAnswer>(&text) {
+        merged.citations.retain(|c| files.contains(&c.file));
+        return Ok(merged);
+    }
+
+    Err(Error::Tool("Error merging answers".to_string()))
+}
+
+/// Merges one group, falling back to the answers one after the other if the
+/// call fails, so the answers already paid for are not lost.
+async fn merge(config: &MapConfig, query: &str, group: Vec<MergedAnswer>) -> MergedAnswer {
+    let fallback = MergedAnswer {
+        answer: group
+            .iter()
+            .map(|a| a.answer.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        citations: group.iter().flat_map(|a| a.citations.clone()).collect(),
+    };
+    merge_call(config, query, group)
+        .await
+        .inspect_err(|e| eprintln!("Cannot merge answers, concatenating them: {e}"))
+        .unwrap_or(fallback)
+}
+
+/// Merge calls `reduce` makes for `answers` answers.
+fn merge_calls(mut answers: usize, fan_in: usize) -> usize {
+    let fan_in = fan_in.max(2);
+    let mut calls = 0;
+    while answers > 1 {
+        // A last group of one passes through without a call.
+        calls += answers / fan_in + usize::from(answers % fan_in > 1);
+        answers = answers.div_ceil(fan_in);
+    }
+    calls
+}
+
+/// Merges answers `fan_in` at a time, level by level, until one is left.
+/// No answers merge to an empty one.
+async fn reduce(
+    config: &MapConfig,
+    query: &str,
+    mut answers: Vec<MergedAnswer>,
+    fan_in: usize,
+) -> MergedAnswer {
+    let fan_in = fan_in.max(2);
+    while answers.len() > 1 {
+        let mut rest = answers.into_iter().peekable();
+        let mut groups = vec![];
+        while rest.peek().is_some() {
+            groups.push(rest.by_ref().take(fan_in).collect::<Vec<_>>());
+        }
+        answers = stream::iter(groups)
+            .map(|group| merge(config, query, group))
+            .buffered(MAX_CONCURRENCY)
+            .collect()
+            .await;
+    }
+    answers.pop().unwrap_or(MergedAnswer {
+        answer: String::new(),
+        citations: vec![],
+    })
+}
+
+pub async fn ask_files(args: AskFilesArgs) -> Result<AskFilesOutput> {
+    let AskFilesArgs {
+        query,
+        filenames,
+        options:
+            AskOptions {
+                pages,
+                mode,
+                fan_in,
+                bypass_cache,
+                model_tier,
+            },
+    } = args;
     let settings = crate::settings::get_settings()?;
+    let config = MapConfig::from_settings(&settings, model_tier);
+    let fan_in = fan_in.unwrap_or(DEFAULT_FAN_IN);
 
-    let responses: Vec<_> = stream::iter(filenames)
+    let prepared: Vec<(String, Result<Prepared>)> = stream::iter(filenames)
         .map(|filename| {
-            let root_dir = settings.root_dir.clone();
-            let query = query.clone();
-            let model_name = MAP_MODEL;
-
+            let config = &config;
+            let root_dir = settings.root_dir.as_str();
+            let query = query.as_str();
+            let pages = pages.clone();
             async move {
-                let jail = Path::new(&root_dir);
-                let file_path = jail.jailed_join(Path::new(&filename))?;
-                let file_content =
-                    task::spawn_blocking(move || crate::file_handler::process_file_for_llm(&file_path))
-                        .await??;
-
-                let mut messages = vec![
-                    json!({"role":"user","content":format!("File: {filename}\n\nQuery: {query}")})
-                ];
-
-                messages.push(json!({"role":"user","content":file_content}));
-                let schema = to_value(schema_for!(AskFileResults)).unwrap(); // unwrap: all input controlled by code
-
-                let response =
-                    Openrouter::call(&messages, model_name, "You are a helpful assistant that answers questions about files. Your answer must be grounded.", &vec![], Some(schema)).await?;
-                if let IncomingContent::Text(text) =  &response.choices[0].message.content
-                && let Ok(output) = from_str::<AskFileResults>(text)
-                {
-                    return Ok(json!({filename:output}));
-                }
+                let result =
+                    prepare_file(config, root_dir, &filename, query, pages, bypass_cache).await;
+                (filename, result)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENCY)
+        .collect()
+        .await;
 
-                Err(Error::Tool(format!("Error querying {filename}")))
+    // A fan-out the budget cannot cover is refused before any call is made.
+    let mut calls: usize = prepared
+        .iter()
+        .filter_map(|(_, prepared)| match prepared {
+            Ok(Prepared::Pending { calls, .. }) => Some(calls),
+            _ => None,
+        })
+        .sum();
+    if mode == AskMode::Reduce {
+        calls += merge_calls(prepared.iter().filter(|(_, p)| p.is_ok()).count(), fan_in);
+    }
+    budget::check(calls as u64)?;
+
+    let responses: Vec<(String, Result<AskFileResults>)> = stream::iter(prepared)
+        .map(|(filename, prepared)| {
+            let config = &config;
+            let query = query.as_str();
+            let pages = pages.clone();
+            async move {
+                let result = match prepared {
+                    Ok(prepared) => ask_file(config, &filename, query, pages, prepared).await,
+                    Err(e) => Err(e),
+                };
+                (filename, result)
             }
         })
         .buffer_unordered(MAX_CONCURRENCY)
         .collect()
         .await;
 
-    Ok(responses)
+    match mode {
+        AskMode::PerFile => Ok(AskFilesOutput::PerFile(
+            responses
+                .into_iter()
+                .map(|(filename, result)| result.map(|output| json!({filename: output})))
+                .collect(),
+        )),
+        AskMode::Reduce => {
+            let mut answers = vec![];
+            let mut failed = vec![];
+            for (filename, result) in responses {
+                match result {
+                    Ok(output) => answers.push(MergedAnswer::from_file(&filename, output)),
+                    Err(e) => failed.push(format!("{filename}: {e}")),
+                }
+            }
+            let merged = reduce(&config, &query, answers, fan_in).await;
+            Ok(AskFilesOutput::Reduced(ReducedAnswer { merged, failed }))
+        }
+    }
 }
 
 pub fn ask_files_glob_tool() -> Tool {
@@ -118,9 +618,8 @@ pub fn ask_files_glob_tool() -> Tool {
         function: Function {
             name: "ask_files_glob".to_string(),
             description: "Same as ask_files, but accepts a glob pattern to match multiple files. Must specify max_results. Note that if user has active selection, you should prioritize `ask_files_selected` and `list_user_selected` unless specifically want to explore outside of user selection.".to_string(),
-            parameters: serde_json::json!({
-                "type": "object",
-                "properties": {
+            parameters: ask_parameters(
+                json!({
                     "query": {
                         "type": "string",
                         "description": "The query to run against each file."
@@ -133,18 +632,19 @@ pub fn ask_files_glob_tool() -> Tool {
                         "type": "number",
                         "description": "Maximum results. If glob matches more than this, the tool will return an error to avoid overwhelming the user. Start with 100 and adjust up if the task really requires understanding more files."
                     }
-                },
-                "required": ["query", "pattern","max_results"]
-            }),
+                }),
+                &["query", "pattern", "max_results"],
+            ),
         },
     }
 }
 
-pub async fn ask_files_glob(args: AskFilesGlobArgs) -> Result<Vec<Result<Value>>> {
+pub async fn ask_files_glob(args: AskFilesGlobArgs) -> Result<AskFilesOutput> {
     let AskFilesGlobArgs {
         query,
         pattern,
         max_results,
+        options,
     } = args;
     let filenames = search_files_by_name(&pattern).await?;
 
@@ -156,7 +656,12 @@ pub async fn ask_files_glob(args: AskFilesGlobArgs) -> Result<Vec<Result<Value>>
         )));
     }
 
-    ask_files(AskFilesArgs { query, filenames }).await
+    ask_files(AskFilesArgs {
+        query,
+        filenames,
+        options,
+    })
+    .await
 }
 
 pub fn ask_files_selected_tool() -> Tool {
@@ -168,9 +673,8 @@ pub fn ask_files_selected_tool() -> Tool {
                 "Same as ask_files, but applies directly to a set of user specified files in the App interface. User has actively selected {} items.",
                 SELECTION_STATE.selection.read().unwrap().len(),
             ),
-            parameters: serde_json::json!({
-                "type": "object",
-                "properties": {
+            parameters: ask_parameters(
+                json!({
                     "query": {
                         "type": "string",
                         "description": "The query to run against each file."
@@ -179,15 +683,19 @@ pub fn ask_files_selected_tool() -> Tool {
                         "type": "number",
                         "description": "Maximum results. If user selected more than this, the tool will return an error to avoid overwhelming the user. Start with 100 and adjust up if the task really requires understanding more files."
                     }
-                },
-                "required": ["query", "max_results"]
-            }),
+                }),
+                &["query", "max_results"],
+            ),
         },
     }
 }
 
-pub async fn ask_files_selected(args: AskFilesSearchedArgs) -> Result<Vec<Result<Value>>> {
-    let AskFilesSearchedArgs { query, max_results } = args;
+pub async fn ask_files_selected(args: AskFilesSearchedArgs) -> Result<AskFilesOutput> {
+    let AskFilesSearchedArgs {
+        query,
+        max_results,
+        options,
+    } = args;
     let filenames: Vec<String>;
     {
         let selection = SELECTION_STATE.selection.read().unwrap();
@@ -202,7 +710,12 @@ pub async fn ask_files_selected(args: AskFilesSearchedArgs) -> Result<Vec<Result
         )));
     }
 
-    ask_files(AskFilesArgs { query, filenames }).await
+    ask_files(AskFilesArgs {
+        query,
+        filenames,
+        options,
+    })
+    .await
 }
 
 pub fn list_user_selected_tool() -> Tool {
@@ -246,3 +759,186 @@ pub async fn list_user_selected(args: UserSelectedArgs) -> Result<Vec<String>> {
 
     Ok(filenames)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AskFileResults, AskFilesArgs, AskFilesGlobArgs, AskFilesOutput, AskMode, AskOptions,
+        Citation, MapConfig, MergedAnswer, ModelTier, ask_files, merge_calls, reduce,
+    };
+    use crate::budget;
+    use crate::error::Error;
+    use crate::settings::Settings;
+    use crate::settings::get_settings;
+    use crate::test_support::{Harness, ScriptedBackend, text};
+    use serde_json::json;
+
+    fn file_answer(file: &str) -> MergedAnswer {
+        MergedAnswer::from_file(
+            file,
+            AskFileResults {
+                answer: format!("{file} says yes"),
+                extracts: vec![format!("quote from {file}")],
+            },
+        )
+    }
+
+    fn merged(answer: &str, files: &[&str]) -> String {
+        json!({
+            "answer": answer,
+            "citations": files
+                .iter()
+                .map(|f| json!({"file": f, "extract": format!("quote from {f}")}))
+                .collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn reduce_merges_level_by_level() {
+        let backend = ScriptedBackend::new(vec![
+            text(&merged("a+b", &["a.txt", "b.txt", "made-up.txt"])),
+            text(&merged("c+d", &["c.txt", "d.txt"])),
+            text(&merged("a+b+c+d", &["a.txt", "d.txt"])),
+            text(&merged("all", &["a.txt", "d.txt", "e.txt"])),
+        ]);
+        let config = MapConfig {
+            backend: backend.clone(),
+            model: "test/map".to_string(),
+            temperature: Some(0.2),
+            provider_order: String::new(),
+            prompt: String::new(),
+        };
+        let answers = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"]
+            .into_iter()
+            .map(file_answer)
+            .collect();
+
+        let result = reduce(&config, "yes?", answers, 2).await;
+        assert_eq!(result.answer, "all");
+        assert_eq!(result.citations.len(), 3);
+
+        // 5 answers -> 3 (e passes through) -> 2 -> 1.
+        {
+            let requests = backend.requests.lock().unwrap();
+            assert_eq!(requests.len(), 4);
+            assert_eq!(requests[0]["model"], "test/map");
+            assert_eq!(requests[0]["temperature"], 0.2);
+            let second_level = requests[2]["messages"][1]["content"].as_str().unwrap();
+            assert!(second_level.contains("a+b") && second_level.contains("c+d"));
+            assert!(!second_level.contains("made-up.txt"));
+            let last = requests[3]["messages"][1]["content"].as_str().unwrap();
+            assert!(last.contains("e.txt says yes"));
+        }
+
+        let one = reduce(&config, "yes?", vec![file_answer("x.txt")], 2).await;
+        assert_eq!(
+            one.citations,
+            [Citation {
+                file: "x.txt".to_string(),
+                extract: "quote from x.txt".to_string()
+            }]
+        );
+        let none = reduce(&config, "yes?", vec![], 2).await;
+        assert!(none.answer.is_empty() && none.citations.is_empty());
+
+        // The script is exhausted, so the merge call fails and the answers are kept.
+        let answers = vec![file_answer("x.txt"), file_answer("y.txt")];
+        let fallback = reduce(&config, "yes?", answers, 2).await;
+        assert_eq!(fallback.answer, "x.txt says yes\n\ny.txt says yes");
+        assert_eq!(fallback.citations.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reduce_reports_files_that_all_failed() {
+        let _harness = Harness::new().await;
+        let output = ask_files(AskFilesArgs {
+            query: "yes?".to_string(),
+            filenames: vec!["missing.txt".to_string()],
+            options: AskOptions {
+                mode: AskMode::Reduce,
+                ..AskOptions::default()
+            },
+        })
+        .await
+        .unwrap();
+        let AskFilesOutput::Reduced(reduced) = output else {
+            panic!("expected a reduced answer");
+        };
+        assert!(reduced.merged.answer.is_empty());
+        assert_eq!(reduced.failed.len(), 1);
+        assert!(reduced.failed[0].starts_with("missing.txt: "));
+    }
+
+    #[test]
+    fn map_config_follows_settings_and_tier() {
+        let settings = Settings {
+            map_prompt: "Answer about {file}: {query}".to_string(),
+            ..Settings::default()
+        };
+        let fast = MapConfig::from_settings(&settings, ModelTier::Fast);
+        assert_eq!(fast.model, "google/gemini-2.5-flash");
+        assert_eq!(
+            fast.system_prompt("a.pdf", "why?"),
+            "Answer about a.pdf: why?"
+        );
+        let accurate = MapConfig::from_settings(&Settings::default(), ModelTier::Accurate);
+        assert_eq!(accurate.model, "google/gemini-2.5-pro");
+        assert!(accurate.prompt.contains("grounded"));
+    }
+
+    #[test]
+    fn options_are_read_from_the_tool_arguments() {
+        let args: AskFilesGlobArgs = serde_json::from_value(json!({
+            "query": "q",
+            "pattern": "*.pdf",
+            "max_results": 5,
+            "mode": "reduce",
+            "fan_in": 4,
+            "model_tier": "accurate",
+            "pages": "1-2"
+        }))
+        .unwrap();
+        assert_eq!(args.max_results, 5);
+        assert_eq!(args.options.mode, AskMode::Reduce);
+        assert_eq!(args.options.fan_in, Some(4));
+        assert_eq!(args.options.model_tier, ModelTier::Accurate);
+        assert_eq!(args.options.pages.as_deref(), Some("1-2"));
+        assert!(!args.options.bypass_cache);
+    }
+
+    #[test]
+    fn merge_calls_follow_reduce_levels() {
+        assert_eq!(merge_calls(0, 10), 0);
+        assert_eq!(merge_calls(1, 10), 0);
+        assert_eq!(merge_calls(5, 2), 4);
+        assert_eq!(merge_calls(10, 10), 1);
+        assert_eq!(merge_calls(11, 10), 2);
+        assert_eq!(merge_calls(12, 10), 3);
+    }
+
+    #[tokio::test]
+    async fn budget_covers_file_parts_and_merges() {
+        let harness = Harness::new().await;
+        // 20,000 lines of 26 estimated tokens make three parts.
+        harness.write("big.txt", &format!("{}\n", "x".repeat(99)).repeat(20_000));
+        harness.write("small.txt", "hello");
+        harness.settings(|s| s.request_budget.max_calls = Some(4));
+        let args = |mode| AskFilesArgs {
+            query: "yes?".to_string(),
+            filenames: vec!["big.txt".to_string(), "small.txt".to_string()],
+            options: AskOptions {
+                mode,
+                ..AskOptions::default()
+            },
+        };
+        let settings = get_settings().unwrap();
+
+        let result = budget::limited(&settings, ask_files(args(AskMode::Reduce))).await;
+        let Err(Error::Limit { requested, .. }) = result else {
+            panic!("expected the budget to refuse the fan-out");
+        };
+        // Three parts, the small file and one merge.
+        assert_eq!(requested, 5.0);
+    }
+}
@@ -1,7 +1,9 @@
 use crate::Result;
 use crate::error::Error;
 use crate::interaction::Content;
-use crate::openrouter::{IncomingContent, Openrouter};
+use crate::llm;
+use crate::openrouter::IncomingContent;
+use crate::settings::Backend;
 use crate::tools::{Function, Tool};
 use schemars::{JsonSchema, schema_for};
 use serde::{Deserialize, Serialize};
@@ -25,7 +27,7 @@ pub struct CheckOnlineResult {
 pub async fn check_online(args: CheckOnlineArgs) -> Result<CheckOnlineResult> {
     let settings = crate::settings::get_settings()?;
 
-    if settings.api_key.is_empty() {
+    if settings.backend == Backend::Openrouter && settings.api_key.is_empty() {
         return Err(Error::Tool(
             "Error: OpenRouter API key is not set.".to_string(),
         ));
@@ -46,7 +48,7 @@ pub async fn check_online(args: CheckOnlineArgs) -> Result<CheckOnlineResult> {
     ]
     });
     let schema = to_value(schema_for!(CheckOnlineResult)).unwrap(); // unwrap: all input controlled by code
-    let response = Openrouter::call(&[message], SEARCH_MODEL, "", &vec![], Some(schema)).await?;
+    let response = llm::call(&[message], SEARCH_MODEL, "", &vec![], Some(schema)).await?;
     if let IncomingContent::Text(text) = &response.choices[0].message.content {
         let result: CheckOnlineResult = from_str(text)?;
         return Ok(result);
@@ -0,0 +1,193 @@
+use crate::Result;
+use crate::error::Error;
+use crate::file_handler::{self, determine_file_type, text_sections};
+use crate::search::text_files;
+use crate::settings::get_root;
+
+use super::{Function, Tool};
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::task::spawn_blocking;
+
+const DEFAULT_CONTEXT: usize = 1;
+const MAX_CONTEXT: usize = 10;
+const MAX_LINE_CHARS: usize = 300;
+
+pub fn get_tool() -> Tool {
+    Tool {
+        r#type: "function".to_string(),
+        function: Function {
+            name: "grep".to_string(),
+            description: "Searches file contents with a regular expression, like grep. Covers text files and the text of PDF, Word, PowerPoint and Excel files, and reports where each match is (line, PDF page, slide or sheet row) with surrounding lines. Much cheaper than `ask_files` for locating a literal number, name or clause. Returns an error with the total count if there are more matches than 'max_results'.".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "regex": {
+                        "type": "string",
+                        "description": "Regular expression (Rust regex syntax) matched against each line."
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Optional file name pattern limiting the files searched, same logic as the `find` tool pattern. Searches all files if omitted."
+                    },
+                    "case_insensitive": {
+                        "type": "boolean",
+                        "description": "Ignore case. Defaults to false."
+                    },
+                    "context": {
+                        "type": "number",
+                        "description": "Lines of context before and after each match. Defaults to 1, at most 10."
+                    },
+                    "max_results": {
+                        "type": "number",
+                        "description": "Maximum matches. If there are more, the tool will return an error to avoid overwhelming the user. Start with 50 and narrow the regex or pattern if required."
+                    }
+                },
+                "required": ["regex", "max_results"]
+            }),
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GrepArgs {
+    pub regex: String,
+    #[serde(default)]
+    pub pattern: Option<String>,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[serde(default)]
+    pub context: Option<usize>,
+    pub max_results: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GrepMatch {
+    pub file: String,
+    pub location: String,
+    pub line: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+fn clip(line: &str) -> String {
+    let line = line.trim_end();
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((i, _)) => format!("{}…", &line[..i]),
+        None => line.to_string(),
+    }
+}
+
+fn grep_file(file: &str, path: &Path, regex: &Regex, context: usize) -> Vec<GrepMatch> {
+    let file_type = determine_file_type(path);
+    let Ok(Some(text)) = file_handler::file_text(path) else {
+        return vec![];
+    };
+    let mut out = vec![];
+    for section in text_sections(&file_type, &text) {
+        for (i, line) in section.lines.iter().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            let position = format!("{} {}", section.unit, i + 1);
+            out.push(GrepMatch {
+                file: file.to_string(),
+                location: match &section.label {
+                    Some(label) => format!("{label}, {position}"),
+                    None => position,
+                },
+                line: clip(line),
+                before: section.lines[i.saturating_sub(context)..i]
+                    .iter()
+                    .map(|l| clip(l))
+                    .collect(),
+                after: section.lines[i + 1..(i + 1 + context).min(section.lines.len())]
+                    .iter()
+                    .map(|l| clip(l))
+                    .collect(),
+            });
+        }
+    }
+    out
+}
+
+pub async fn grep(args: GrepArgs) -> Result<Vec<GrepMatch>> {
+    let regex = RegexBuilder::new(&args.regex)
+        .case_insensitive(args.case_insensitive)
+        .build()
+        .map_err(|e| Error::Tool(format!("invalid regex: {e}")))?;
+    let context = args.context.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT);
+    let root = get_root()?;
+    let files = text_files(args.pattern.as_deref()).await?;
+
+    let result = spawn_blocking(move || {
+        files
+            .par_iter()
+            .flat_map_iter(|file| grep_file(file, root.join(file).as_std_path(), &regex, context))
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    if result.len() > args.max_results {
+        return Err(Error::Tool(format!(
+            "Error: Found more matches ({}) than limit ({}). Consider up the limit, narrow the regex or limit the files with a pattern.",
+            result.len(),
+            args.max_results
+        )));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GrepArgs, grep};
+    use crate::file_handler::tests::sample_pdf;
+    use crate::test_support::Harness;
+
+    fn args(regex: &str) -> GrepArgs {
+        GrepArgs {
+            regex: regex.to_string(),
+            pattern: None,
+            case_insensitive: true,
+            context: Some(1),
+            max_results: 10,
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_lines_and_pages() {
+        let harness = Harness::new().await;
+        harness.write("notes.txt", "first\nsecond falcon\nthird\n");
+        std::fs::write(
+            harness.root.path().join("report.pdf"),
+            sample_pdf(&["alpha", "falcon beta"]),
+        )
+        .unwrap();
+
+        let mut matches = grep(args("falcon")).await.unwrap();
+        matches.sort_by(|a, b| a.file.cmp(&b.file));
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.file.as_str(), m.location.as_str(), m.line.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("notes.txt", "line 2", "second falcon"),
+                ("report.pdf", "page 2, line 1", "falcon beta"),
+            ]
+        );
+        assert_eq!(matches[0].before, ["first"]);
+        assert_eq!(matches[0].after, ["third"]);
+        assert!(matches[1].before.is_empty());
+
+        let err = grep(GrepArgs {
+            max_results: 1,
+            ..args("a")
+        })
+        .await;
+        assert!(err.is_err());
+    }
+}
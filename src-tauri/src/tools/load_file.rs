@@ -11,6 +11,8 @@ use tokio::task;
 #[derive(Deserialize)]
 pub struct LoadFileArgs {
     pub filename: String,
+    #[serde(default)]
+    pub pages: Option<String>,
 }
 
 pub async fn load_file(args: LoadFileArgs) -> Result<ToolPayload> {
@@ -18,8 +20,11 @@ pub async fn load_file(args: LoadFileArgs) -> Result<ToolPayload> {
 
     let safe_path = root_dir.jailed_join(Path::new(&args.filename))?;
 
-    let mut file_content =
-        task::spawn_blocking(move || file_handler::process_file_for_llm(&safe_path)).await??;
+    let pages = args.pages.clone();
+    let mut file_content = task::spawn_blocking(move || {
+        file_handler::process_file_for_llm(&safe_path, pages.as_deref())
+    })
+    .await??;
 
     // Prepend an instructional message for the LLM.
     let instructional_text = Content::Text {
@@ -47,6 +52,10 @@ pub fn get_tool() -> Tool {
                     "filename": {
                         "type": "string",
                         "description": "The path to the file to load."
+                    },
+                    "pages": {
+                        "type": "string",
+                        "description": "Optional PDF pages to load, e.g. \"40-55\" or \"1,3,10-12\". Use this for long reports instead of loading the whole file. Ignored for other file types."
                     }
                 },
                 "required": ["filename"]
@@ -2,11 +2,14 @@ mod ask_files;
 mod check_online;
 mod extract;
 mod find;
+mod grep;
 mod load_file;
 mod ls;
 mod make_file;
 mod notes;
 mod roll_dice;
+mod search_content;
+mod semantic_search;
 
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, from_str, to_string, to_value};
@@ -118,6 +121,9 @@ pub fn get_tools() -> Vec<Tool> {
         roll_dice::get_tool(),
         ls::get_tool(),
         find::get_tool(),
+        search_content::get_tool(),
+        grep::get_tool(),
+        semantic_search::get_tool(),
         notes::read_notes_tool(),
         notes::append_notes_tool(),
         ask_files::ask_files_tool(),
@@ -136,6 +142,9 @@ pub async fn tool_dispatcher(name: &str, arguments: &str) -> ToolPayload {
         "ls" => tool_execute(ls::ls, arguments).await,
         "roll_dice" => tool_execute(roll_dice::execute, arguments).await,
         "find" => tool_execute(find::find, arguments).await,
+        "search_content" => tool_execute(search_content::search_content, arguments).await,
+        "grep" => tool_execute(grep::grep, arguments).await,
+        "semantic_search" => tool_execute(semantic_search::semantic_search, arguments).await,
         "read_notes" => tool_execute(notes::read_notes, arguments).await,
         "append_notes" => tool_execute(notes::append_notes, arguments).await,
         "ask_files" => tool_execute(ask_files::ask_files, arguments).await,
@@ -0,0 +1,39 @@
+use crate::Result;
+use crate::content_index::{ContentMatch, search_content as search_index};
+
+use super::{Function, Tool};
+use serde::{Deserialize, Serialize};
+
+pub fn get_tool() -> Tool {
+    Tool {
+        r#type: "function".to_string(),
+        function: Function {
+            name: "search_content".to_string(),
+            description: "Full-text search over the contents of files (text, PDF, Word, PowerPoint and Excel), e.g. to find which files mention \"Project Falcon\". Returns files containing every word of the query, best matches first, each with a snippet where query words are in bold. Use `find` to search file names instead, and `ask_files` or `load_file` to read the files found.".to_string(),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Words to look for. Case-insensitive; all words must appear in a file for it to match."
+                    },
+                    "max_results": {
+                        "type": "number",
+                        "description": "Maximum number of ranked results to return. Start with 20."
+                    }
+                },
+                "required": ["query", "max_results"]
+            }),
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SearchContentArgs {
+    pub query: String,
+    pub max_results: usize,
+}
+
+pub async fn search_content(args: SearchContentArgs) -> Result<Vec<ContentMatch>> {
+    search_index(args.query, args.max_results).await
+}
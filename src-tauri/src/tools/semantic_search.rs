@@ -0,0 +1,55 @@
+use crate::Result;
+use crate::semantic::{SemanticMatch, semantic_search as search_chunks};
+use crate::settings::{EmbeddingBackend, get_settings};
+
+use super::{Function, Tool};
+use serde::{Deserialize, Serialize};
+
+pub fn get_tool() -> Tool {
+    // The local embeddings only see shared words, so don't promise more.
+    let matching = match get_settings().map(|s| s.embedding_backend) {
+        Ok(EmbeddingBackend::Api) => "even when they don't share its exact words",
+        _ => {
+            "ranked by the words and word fragments they share with it, so use the vocabulary the files likely use"
+        }
+    };
+    Tool {
+        r#type: "function".to_string(),
+        function: Function {
+            name: "semantic_search".to_string(),
+            description: format!(
+                "Finds the passages most related to a question or topic across the contents of files (text, PDF, Word, PowerPoint and Excel), {matching}. Returns the top passages with their file, location (page, slide, sheet rows or lines) and text, best first, for you to read and cite. Much cheaper than `ask_files` over many files; use `grep` for exact strings."
+            ),
+            parameters: serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "A question or description of what you are looking for."
+                    },
+                    "top_k": {
+                        "type": "number",
+                        "description": "Number of passages to return. Start with 10."
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Optional file name pattern limiting the files searched, same logic as the `find` tool pattern. Searches all files if omitted."
+                    }
+                },
+                "required": ["query", "top_k"]
+            }),
+        },
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SemanticSearchArgs {
+    pub query: String,
+    pub top_k: usize,
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+pub async fn semantic_search(args: SemanticSearchArgs) -> Result<Vec<SemanticMatch>> {
+    search_chunks(args.query, args.top_k, args.pattern).await
+}
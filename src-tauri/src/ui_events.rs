@@ -1,6 +1,8 @@
 use crate::{
     Result,
     interaction::{Content, History, Interaction, Source, Target},
+    openrouter::StreamDelta,
+    usage::Usage,
 };
 use serde::Serialize;
 use tauri::Emitter;
@@ -31,6 +33,28 @@ pub enum EventPayload<'a> {
         tool_call_id: &'a str,
         tool_result: &'a str,
     },
+    MessageDelta {
+        id: usize,
+        delta: &'a str,
+    },
+    ToolCallDelta {
+        id: usize,
+        index: usize,
+        tool_call_id: Option<&'a str>,
+        tool_name: Option<&'a str>,
+        args_delta: &'a str,
+    },
+    /// What interaction `id` cost, with the running total of the conversation.
+    Usage {
+        id: usize,
+        usage: Usage,
+        conversation: Usage,
+    },
+    /// Interaction `id` has alternative branches starting with `siblings`, itself included.
+    Branches {
+        id: usize,
+        siblings: &'a [usize],
+    },
 }
 
 impl<'a> Target<'a> for UIEvents {
@@ -41,6 +65,7 @@ impl<'a> Target<'a> for UIEvents {
                 interaction_id: id,
                 content,
                 tool_calls,
+                ..
             } => {
                 let mut out = vec![];
                 if let Some(tool_calls) = tool_calls {
@@ -100,34 +125,85 @@ impl Source for UIEvents {
     }
 }
 
-impl UIEvents {
-    pub fn new(window: tauri::Window) -> Self {
-        Self { window }
+/// Receives the chat updates produced by the agent loop.
+///
+/// `UIEvents` forwards them to the Tauri window; other front ends (stdout,
+/// channels, HTTP streams, test recorders) only need to implement `emit`.
+pub trait EventSink: Send + Sync {
+    fn emit(&self, payload: EventPayload<'_>) -> Result<()>;
+
+    fn emit_done(&self) -> Result<()> {
+        self.emit(EventPayload::End)
     }
 
-    pub fn emit_done(&self) -> Result<()> {
-        self.window
-            .emit("chat_completion_update", EventPayload::End)?;
-        Ok(())
+    fn emit_start(&self) -> Result<()> {
+        self.emit(EventPayload::Start)
     }
 
-    pub fn emit_start(&self) -> Result<()> {
-        self.window
-            .emit("chat_completion_update", EventPayload::Start)?;
-        Ok(())
+    fn emit_delta(&self, id: usize, delta: &StreamDelta) -> Result<()> {
+        let payload = match delta {
+            StreamDelta::Content(text) => EventPayload::MessageDelta { id, delta: text },
+            StreamDelta::ToolCall {
+                index,
+                id: tool_call_id,
+                name,
+                arguments,
+            } => EventPayload::ToolCallDelta {
+                id,
+                index: *index,
+                tool_call_id: tool_call_id.as_deref(),
+                tool_name: name.as_deref(),
+                args_delta: arguments,
+            },
+        };
+        self.emit(payload)
     }
 
-    pub fn emit_interaction(&self, interaction: &Interaction) -> Result<()> {
-        for payload in Self::convert(interaction) {
-            let _ = self.window.emit("chat_completion_update", payload);
+    fn emit_interaction(&self, interaction: &Interaction) -> Result<()> {
+        for payload in UIEvents::convert(interaction) {
+            let _ = self.emit(payload);
         }
         Ok(())
     }
 
-    pub fn replay_history(&self, history: &History) -> Result<()> {
-        for payload in Self::render(history) {
-            let _ = self.window.emit("chat_completion_update", payload);
+    fn replay_history(&self, history: &History) -> Result<()> {
+        for payload in UIEvents::render(history) {
+            let _ = self.emit(payload);
         }
+        let mut conversation = Usage::default();
+        for interaction in &history.inner {
+            if let Some(usage) = interaction.usage() {
+                conversation += usage;
+                let _ = self.emit(EventPayload::Usage {
+                    id: interaction.id(),
+                    usage,
+                    conversation,
+                });
+            }
+        }
+        self.emit_branches(history)
+    }
+
+    fn emit_branches(&self, history: &History) -> Result<()> {
+        for (id, siblings) in history.forks() {
+            let _ = self.emit(EventPayload::Branches {
+                id,
+                siblings: &siblings,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl UIEvents {
+    pub fn new(window: tauri::Window) -> Self {
+        Self { window }
+    }
+}
+
+impl EventSink for UIEvents {
+    fn emit(&self, payload: EventPayload<'_>) -> Result<()> {
+        self.window.emit("chat_completion_update", payload)?;
         Ok(())
     }
 }
@@ -0,0 +1,204 @@
+//! Token and cost accounting.
+//!
+//! Every completion reports what it used. Calls made while a tool runs are
+//! collected with `scoped` so they can be charged to that tool call, and
+//! everything the agent loop spends is added to a monthly ledger per root
+//! and to a daily total across roots.
+
+use crate::Result;
+use crate::conversations::root_hash;
+use crate::settings::get_root;
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::Local;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::AddAssign;
+use std::sync::Mutex;
+
+/// The `usage` block of a completion, or the sum of several.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    /// Number of completions counted; not part of the wire format.
+    #[serde(default)]
+    pub calls: u64,
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+    /// In USD, as reported by OpenRouter. Other backends leave it at zero.
+    #[serde(default)]
+    pub cost: f64,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost += other.cost;
+    }
+}
+
+tokio::task_local! {
+    static SCOPE: Cell<Usage>;
+}
+
+/// Adds `usage` to the enclosing `scoped` call, if any.
+pub fn charge(usage: Usage) {
+    let _ = SCOPE.try_with(|total| {
+        let mut sum = total.get();
+        sum += usage;
+        total.set(sum);
+    });
+}
+
+/// Runs `fut`, returning its output with the usage of every completion made
+/// inside it. The usage is also charged to any enclosing scope.
+pub async fn scoped<F: Future>(fut: F) -> (F::Output, Usage) {
+    let (out, usage) = SCOPE
+        .scope(Cell::new(Usage::default()), async {
+            let out = fut.await;
+            (out, SCOPE.with(Cell::get))
+        })
+        .await;
+    charge(usage);
+    (out, usage)
+}
+
+/// Spend of one root directory, by month ("2025-07").
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq)]
+pub struct Ledger {
+    pub root_dir: String,
+    pub months: BTreeMap<String, Usage>,
+}
+
+/// Spend across all roots, by day ("2025-07-14").
+type Daily = BTreeMap<String, Usage>;
+
+/// Serializes read-modify-write cycles of ledger files.
+static LEDGER_LOCK: Mutex<()> = Mutex::new(());
+
+fn usage_dir() -> Result<Utf8PathBuf> {
+    let dir = crate::get_data_dir()?.join("usage");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn ledger_path(root_dir: &Utf8Path) -> Result<Utf8PathBuf> {
+    Ok(usage_dir()?.join(format!("{}.json", root_hash(root_dir))))
+}
+
+fn daily_path() -> Result<Utf8PathBuf> {
+    Ok(usage_dir()?.join("daily.json"))
+}
+
+fn read_json<T: DeserializeOwned>(path: &Utf8Path) -> Option<T> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+fn write_json<T: Serialize>(path: &Utf8Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(value)?)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+fn read_ledger(path: &Utf8Path, root_dir: &Utf8Path) -> Ledger {
+    read_json(path).unwrap_or_else(|| Ledger {
+        root_dir: root_dir.to_string(),
+        ..Default::default()
+    })
+}
+
+fn add_to_ledger(path: &Utf8Path, root_dir: &Utf8Path, month: String, usage: Usage) -> Result<()> {
+    let _guard = LEDGER_LOCK.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+    let mut ledger = read_ledger(path, root_dir);
+    *ledger.months.entry(month).or_default() += usage;
+    write_json(path, &ledger)
+}
+
+fn add_to_daily(path: &Utf8Path, day: String, usage: Usage) -> Result<()> {
+    let _guard = LEDGER_LOCK.lock().unwrap(); // unwrap: won't try to recover from poisoned lock
+    let mut daily: Daily = read_json(path).unwrap_or_default();
+    *daily.entry(day).or_default() += usage;
+    write_json(path, &daily)
+}
+
+fn today() -> String {
+    Local::now().format("%Y-%m-%d").to_string()
+}
+
+/// Adds `usage` to this month's spend of the current root and to today's total.
+pub fn record(usage: Usage) -> Result<()> {
+    add_to_daily(&daily_path()?, today(), usage)?;
+    let root_dir = get_root()?;
+    let month = Local::now().format("%Y-%m").to_string();
+    add_to_ledger(&ledger_path(&root_dir)?, &root_dir, month, usage)
+}
+
+/// What has been recorded today, across roots. Zero if nothing was recorded
+/// yet, an error if the daily totals cannot be read.
+pub fn spent_today() -> Result<Usage> {
+    let bytes = match fs::read(daily_path()?) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Usage::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut daily: Daily = serde_json::from_slice(&bytes)?;
+    Ok(daily.remove(&today()).unwrap_or_default())
+}
+
+#[tauri::command]
+pub fn usage_report() -> Result<Ledger> {
+    let root_dir = get_root()?;
+    Ok(read_ledger(&ledger_path(&root_dir)?, &root_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Usage, add_to_ledger, charge, read_ledger, scoped};
+    use camino::Utf8PathBuf;
+    use tempfile::TempDir;
+
+    fn usage(tokens: u64, cost: f64) -> Usage {
+        Usage {
+            calls: 1,
+            prompt_tokens: tokens,
+            completion_tokens: 0,
+            total_tokens: tokens,
+            cost,
+        }
+    }
+
+    #[tokio::test]
+    async fn scopes_nest_and_ledger_adds_up() {
+        charge(usage(1000, 1.0)); // outside any scope, dropped
+        let ((_, inner), outer) = scoped(async {
+            charge(usage(10, 0.5));
+            scoped(async { charge(usage(5, 0.25)) }).await
+        })
+        .await;
+        assert_eq!(inner, usage(5, 0.25));
+        assert_eq!(outer.calls, 2);
+        assert_eq!(outer.total_tokens, 15);
+        assert_eq!(outer.cost, 0.75);
+
+        let dir = TempDir::new().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().join("ledger.json")).unwrap();
+        let root = Utf8PathBuf::from("/docs");
+        add_to_ledger(&path, &root, "2025-06".to_string(), inner).unwrap();
+        add_to_ledger(&path, &root, "2025-07".to_string(), inner).unwrap();
+        add_to_ledger(&path, &root, "2025-07".to_string(), outer).unwrap();
+        let ledger = read_ledger(&path, &root);
+        assert_eq!(ledger.root_dir, "/docs");
+        assert_eq!(ledger.months["2025-06"], inner);
+        assert_eq!(ledger.months["2025-07"].calls, 3);
+        assert_eq!(ledger.months["2025-07"].total_tokens, 20);
+    }
+}
@@ -1 +1,21 @@
 pub mod jailed;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether `debug_log!` prints. On in the app; the command line only turns
+/// it on with `--verbose`, so stderr stays quiet.
+pub static VERBOSE: AtomicBool = AtomicBool::new(true);
+
+pub fn verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// `eprintln!` for request, response and conversion traces.
+macro_rules! debug_log {
+    ($($arg:tt)*) => {
+        if $crate::utils::verbose() {
+            eprintln!($($arg)*)
+        }
+    };
+}
+pub(crate) use debug_log;
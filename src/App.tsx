@@ -1,5 +1,5 @@
 import { useState, useEffect, useRef } from "react";
-import { open } from "@tauri-apps/plugin-dialog";
+import { open, save } from "@tauri-apps/plugin-dialog";
 import "./App.css";
 import {
   chat,
@@ -13,17 +13,30 @@ import {
   delete_tool_interaction,
   search_files_by_name_interactive,
   onSearchResultUpdate,
+  listConversations,
+  currentConversation,
+  openConversation,
+  renameConversation,
+  deleteConversation,
+  editMessage,
+  exportConversation,
+  ExportFormat,
+  importTranscript,
+  regenerate,
+  switchBranch,
 } from "./commands";
 import { fileToAttachment } from "./helpers";
 import {
   IChatCompletionMessage,
+  IConversationSummary,
   ISearchResultUpdate,
   ISettings,
+  IUsage,
   MessageContent,
 } from "./types";
 import { SettingsModal } from "./components/SettingsModal";
 import { getVersion } from "@tauri-apps/api/app";
-import { Bounce, ToastContainer } from "react-toastify";
+import { Bounce, ToastContainer, toast } from "react-toastify";
 import { TopBar } from "./components/TopBar";
 import { FilePicker } from "./components/FilePicker";
 import { ChatStream } from "./components/ChatStream";
@@ -42,6 +55,8 @@ function App() {
   const [input, setInput] = useState("");
   const [attachments, setAttachments] = useState<Attachment[]>([]);
   const [appVersion, setAppVersion] = useState("");
+  const [usage, setUsage] = useState<IUsage | null>(null);
+  const [branches, setBranches] = useState<Record<number, number[]>>({});
   const [isFlapOpen, setIsFlapOpen] = useState(false);
   const rootDirInputRef = useRef<HTMLInputElement>(null);
   const messageInputRef = useRef<HTMLTextAreaElement>(null);
@@ -50,6 +65,10 @@ function App() {
   const [isTyping, setIsTyping] = useState(false);
   const [fileList, setFileList] = useState<string[]>([]);
   const [searchTerm, setSearchTerm] = useState("");
+  const [conversations, setConversations] = useState<IConversationSummary[]>(
+    []
+  );
+  const [conversationId, setConversationId] = useState<string | null>(null);
   const debouncedSearchTerm = useDebounce(searchTerm, 300);
   const chatContainerRef = useAutoScroll(messages);
   const {
@@ -68,10 +87,100 @@ function App() {
     rootDir: "",
     sofficePath: "",
     providerOrder: "",
+    backend: "openrouter",
+    baseUrl: "",
+    pdfAsText: false,
+    serverEnabled: false,
+    serverBind: "127.0.0.1:8765",
+    serverToken: "",
+    embeddingBackend: "local",
+    embeddingModel: "openai/text-embedding-3-small",
+    mapModelName: "google/gemini-2.5-flash",
+    mapAccurateModelName: "google/gemini-2.5-pro",
+    mapProviderOrder: "",
+    mapTemperature: null,
+    mapPrompt: "",
+    requestBudget: { maxCalls: null, maxTokens: null, maxCost: null },
+    dailyBudget: { maxCalls: null, maxTokens: null, maxCost: null },
+    maxIterations: 50,
+    contextBudget: 200000,
   });
 
+  const refreshConversations = () => {
+    listConversations()
+      .then(setConversations)
+      .catch(() => setConversations([]));
+    currentConversation().then(setConversationId);
+  };
+
+  const handleOpenConversation = (id: string) => {
+    openConversation(id).then(() => {
+      setMessages([]);
+      setUsage(null);
+      setBranches({});
+      replayHistory();
+      refreshConversations();
+    });
+  };
+
+  const handleRenameConversation = (id: string) => {
+    const current = conversations.find((c) => c.id === id);
+    const title = window.prompt("Conversation title", current?.title ?? "");
+    if (title) {
+      renameConversation(id, title).then(refreshConversations);
+    }
+  };
+
+  const handleExportConversation = async () => {
+    const path = await save({
+      filters: [
+        { name: "Markdown", extensions: ["md"] },
+        { name: "HTML", extensions: ["html"] },
+        { name: "Transcript", extensions: ["json"] },
+      ],
+    });
+    if (!path) return;
+    const extension = path.split(".").pop()?.toLowerCase();
+    const format: ExportFormat =
+      extension === "html" ? "html" : extension === "json" ? "json" : "markdown";
+    exportConversation(format, path)
+      .then(() => toast.success(`Exported to ${path}`))
+      .catch((e) => toast.error(String(e)));
+  };
+
+  const handleImportTranscript = async () => {
+    const path = await open({
+      multiple: false,
+      filters: [{ name: "Transcript", extensions: ["json"] }],
+    });
+    if (typeof path !== "string") return;
+    importTranscript(path)
+      .then(() => {
+        setMessages([]);
+        setUsage(null);
+        setBranches({});
+        replayHistory();
+        refreshConversations();
+      })
+      .catch((e) => toast.error(String(e)));
+  };
+
+  const handleDeleteConversation = (id: string) => {
+    deleteConversation(id).then(() => {
+      setMessages([]);
+      setUsage(null);
+      setBranches({});
+      refreshConversations();
+    });
+  };
+
   const handleClearAll = () => {
-    clearHistory().then(() => setMessages([]));
+    clearHistory().then(() => {
+      setMessages([]);
+      setUsage(null);
+      setBranches({});
+      refreshConversations();
+    });
     setSearchTerm("");
     setSelectedFiles([]);
     setIsFlapOpen(false);
@@ -84,9 +193,71 @@ function App() {
         break;
       case "End":
         setIsTyping(false);
+        refreshConversations();
+        break;
+      case "MessageDelta":
+        setMessages((prev) => {
+          const foundIndex = prev.findIndex(
+            (m) => m.id === update.id && m.isStreaming && !m.isNotification
+          );
+          if (foundIndex === -1) {
+            return [
+              ...prev,
+              {
+                id: update.id,
+                role: "assistant",
+                content: [{ type: "text", text: update.delta }],
+                isStreaming: true,
+              },
+            ];
+          }
+          const newMessages = [...prev];
+          const draft = newMessages[foundIndex];
+          const text =
+            draft.content[0]?.type === "text" ? draft.content[0].text : "";
+          newMessages[foundIndex] = {
+            ...draft,
+            content: [{ type: "text", text: text + update.delta }],
+          };
+          return newMessages;
+        });
+        break;
+      case "ToolCallDelta":
+        setMessages((prev) => {
+          const foundIndex = prev.findIndex(
+            (m) =>
+              m.id === update.id &&
+              m.isStreaming &&
+              m.streamIndex === update.index
+          );
+          if (foundIndex === -1) {
+            return [
+              ...prev,
+              {
+                id: update.id,
+                role: "assistant",
+                content: [],
+                isNotification: true,
+                isStreaming: true,
+                streamIndex: update.index,
+                toolName: update.tool_name ?? "",
+                toolArgs: update.args_delta,
+              },
+            ];
+          }
+          const newMessages = [...prev];
+          const draft = newMessages[foundIndex];
+          newMessages[foundIndex] = {
+            ...draft,
+            toolName: (draft.toolName ?? "") + (update.tool_name ?? ""),
+            toolArgs: (draft.toolArgs ?? "") + update.args_delta,
+          };
+          return newMessages;
+        });
         break;
       case "Message":
         setMessages((prev) => {
+          prev = prev.filter((m) => !(m.id === update.id && m.isStreaming));
           if (prev.some((m) => m.id === update.id)) return prev;
           return [
             ...prev,
@@ -100,6 +271,7 @@ function App() {
         break;
       case "ToolCall":
         setMessages((prev) => {
+          prev = prev.filter((m) => !(m.id === update.id && m.isStreaming));
           if (
             prev.some(
               (m) =>
@@ -137,6 +309,12 @@ function App() {
           return newMessages;
         });
         break;
+      case "Usage":
+        setUsage(update.conversation);
+        break;
+      case "Branches":
+        setBranches((prev) => ({ ...prev, [update.id]: update.siblings }));
+        break;
     }
   };
 
@@ -149,6 +327,8 @@ function App() {
     const unlisten = onChatCompletionUpdate(handleChatUpdate);
     const unlistenSearch = onSearchResultUpdate(handleSearchUpdate);
     setMessages([]);
+    setUsage(null);
+    setBranches({});
     replayHistory();
     return () => {
       unlisten.then((f) => f());
@@ -217,6 +397,12 @@ function App() {
     }
   }, [settings.rootDir, debouncedSearchTerm]);
 
+  useEffect(() => {
+    if (settings.rootDir) {
+      refreshConversations();
+    }
+  }, [settings.rootDir]);
+
   useEffect(() => {
     if (messageInputRef.current) {
       messageInputRef.current.style.height = "auto";
@@ -259,7 +445,10 @@ function App() {
     });
     setInput("");
     setAttachments([]);
-    chat(messageContent);
+    chat(messageContent).catch((e) => {
+      setIsTyping(false);
+      toast.error(String(e));
+    });
   };
 
   const handleKeyDown = (event: React.KeyboardEvent<HTMLTextAreaElement>) => {
@@ -316,9 +505,54 @@ function App() {
     delete_tool_interaction(llm_interaction_id, tool_call_id);
   };
 
+  const handleEdit = (id: number) => {
+    const message = messages.find((m) => m.id === id);
+    if (!message) return;
+    const text = message.content
+      .filter((c) => c.type === "text")
+      .map((c) => c.text)
+      .join("\n");
+    const edited = window.prompt("Edit message", text);
+    if (edited === null || edited === text) return;
+    const content: MessageContent = [
+      ...(edited.trim() ? [{ type: "text" as const, text: edited }] : []),
+      ...message.content.filter((c) => c.type !== "text"),
+    ];
+    if (content.length === 0) return;
+    setMessages((prev) => prev.filter((m) => m.id < id));
+    editMessage(id, content).catch((e) => {
+      setIsTyping(false);
+      toast.error(String(e));
+    });
+  };
+
+  const handleRegenerate = () => {
+    const modelName = window.prompt("Regenerate with model", settings.modelName);
+    if (modelName === null) return;
+    const lastUser = Math.max(
+      ...messages.filter((m) => m.role === "user").map((m) => m.id)
+    );
+    setMessages((prev) => prev.filter((m) => m.id <= lastUser));
+    regenerate(modelName.trim()).catch((e) => {
+      setIsTyping(false);
+      toast.error(String(e));
+    });
+  };
+
+  const handleSwitchBranch = (id: number) => {
+    switchBranch(id).then(() => {
+      setMessages([]);
+      setUsage(null);
+      setBranches({});
+      replayHistory();
+    });
+  };
+
   const handleCancel = () => {
     cancelOutstandingRequest().then(() => {
       setMessages([]);
+      setUsage(null);
+      setBranches({});
       replayHistory();
     });
   };
@@ -334,12 +568,20 @@ function App() {
     <div className="container">
       <TopBar
         appVersion={appVersion}
+        usage={usage}
         settings={settings}
         handleSettingsChange={handleSettingsChange}
         onClearHistory={handleClearAll}
         onOpenSettings={() => setOpenSettingsModal(true)}
         onSelectFolder={handleSelectFolder}
         rootDirInputRef={rootDirInputRef}
+        conversations={conversations}
+        conversationId={conversationId}
+        onOpenConversation={handleOpenConversation}
+        onRenameConversation={handleRenameConversation}
+        onExportConversation={handleExportConversation}
+        onImportTranscript={handleImportTranscript}
+        onDeleteConversation={handleDeleteConversation}
         onToggleFlap={() => setIsFlapOpen((prev) => !prev)}
       />
       <main id="main-content">
@@ -367,6 +609,10 @@ function App() {
           onCopy={handleCopy}
           onDelete={handleDelete}
           onDeleteTool={handleDeleteTool}
+          onEdit={handleEdit}
+          branches={branches}
+          onSwitchBranch={handleSwitchBranch}
+          onRegenerate={handleRegenerate}
           chatContainerRef={chatContainerRef}
           attachments={attachments}
           setAttachments={setAttachments}
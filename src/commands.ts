@@ -1,8 +1,13 @@
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import {
+  IAskCacheEntry,
   IChatCompletionUpdate,
+  IContentMatch,
+  IConversationSummary,
+  ILedger,
   ISearchResultUpdate,
+  ISemanticMatch,
   ISettings,
   MessageContent,
 } from "./types";
@@ -40,16 +45,94 @@ export const delete_tool_interaction = async (
   });
 };
 
+export const listConversations = async (): Promise<IConversationSummary[]> => {
+  return await invoke("list_conversations");
+};
+
+export const currentConversation = async (): Promise<string | null> => {
+  return await invoke("current_conversation");
+};
+
+export const openConversation = async (id: string): Promise<void> => {
+  await invoke("open_conversation", { id });
+};
+
+export const renameConversation = async (
+  id: string,
+  title: string
+): Promise<void> => {
+  await invoke("rename_conversation", { id, title });
+};
+
+export const deleteConversation = async (id: string): Promise<void> => {
+  await invoke("delete_conversation", { id });
+};
+
 export const chat = async (content: MessageContent): Promise<void> => {
   await invoke("chat", { content: content });
 };
 
+export const editMessage = async (
+  id: number,
+  content: MessageContent
+): Promise<void> => {
+  await invoke("edit_message", { id, content });
+};
+
+export const regenerate = async (modelName?: string): Promise<void> => {
+  await invoke("regenerate", { modelName: modelName || null });
+};
+
+export type ExportFormat = "markdown" | "html" | "json";
+
+export const exportConversation = async (
+  format: ExportFormat,
+  path: string
+): Promise<void> => {
+  await invoke("export_conversation", { format, path });
+};
+
+export const importTranscript = async (path: string): Promise<string> => {
+  return await invoke("import_transcript", { path });
+};
+
+export const switchBranch = async (id: number): Promise<void> => {
+  await invoke("switch_branch", { id });
+};
+
 export const search_files_by_name_interactive = async (
   globs: string
 ): Promise<string[]> => {
   return await invoke("search_files_by_name_interactive", { globs: globs });
 };
 
+export const searchContent = async (
+  query: string,
+  maxResults: number
+): Promise<IContentMatch[]> => {
+  return await invoke("search_content", { query, maxResults });
+};
+
+export const semanticSearch = async (
+  query: string,
+  topK: number,
+  pattern?: string
+): Promise<ISemanticMatch[]> => {
+  return await invoke("semantic_search", { query, topK, pattern });
+};
+
+export const askCacheList = async (): Promise<IAskCacheEntry[]> => {
+  return await invoke("ask_cache_list");
+};
+
+export const askCacheClear = async (): Promise<number> => {
+  return await invoke("ask_cache_clear");
+};
+
+export const usageReport = async (): Promise<ILedger> => {
+  return await invoke("usage_report");
+};
+
 export const onChatCompletionUpdate = async (
   callback: (update: IChatCompletionUpdate) => void
 ) => {
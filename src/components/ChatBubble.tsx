@@ -10,6 +10,10 @@ import {
   FaAngleUp,
   FaDownload,
   FaTrash,
+  FaEdit,
+  FaChevronLeft,
+  FaChevronRight,
+  FaRedo,
 } from "react-icons/fa";
 import { Bounce, toast } from "react-toastify";
 import "./components.css";
@@ -132,16 +136,45 @@ export const ChatBubble = ({
   toolArgs,
   toolResult,
   id,
+  onEdit,
+  siblings,
+  onSwitchBranch,
+  onRegenerate,
 }: IChatCompletionMessage & {
   onCopy?: () => void;
   onDelete?: () => void;
   onDeleteTool?: (llm_interaction_id: number, tool_call_id: string) => void;
+  onEdit?: () => void;
+  siblings?: number[];
+  onSwitchBranch?: (id: number) => void;
+  onRegenerate?: () => void;
 }) => {
   const [isExpanded, setIsExpanded] = useState(false);
 
   // A bubble is a tool bubble if it has a tool name, or if it already has args/results from history
   const isTool = !!(toolName || toolArgs || toolResult);
 
+  const branchIndex = siblings ? siblings.indexOf(id) : -1;
+  const branchSwitcher = siblings && branchIndex !== -1 && (
+    <div className="branch-switcher">
+      <button
+        disabled={branchIndex === 0}
+        onClick={() => onSwitchBranch?.(siblings[branchIndex - 1])}
+        title="Previous version"
+      >
+        <FaChevronLeft />
+      </button>
+      {branchIndex + 1}/{siblings.length}
+      <button
+        disabled={branchIndex === siblings.length - 1}
+        onClick={() => onSwitchBranch?.(siblings[branchIndex + 1])}
+        title="Next version"
+      >
+        <FaChevronRight />
+      </button>
+    </div>
+  );
+
   const mainContent = toolName
     ? `Calling ${toolName}${toolResult ? " done." : ""}`
     : renderContent(content);
@@ -150,6 +183,10 @@ export const ChatBubble = ({
     <div className={`chat-bubble-container ${role}`}>
       {role === "user" && !isNotification && (
         <div className="message-actions">
+          {branchSwitcher}
+          <button onClick={onEdit} title="Edit and resend">
+            <FaEdit />
+          </button>
           <button onClick={onCopy} title="Copy">
             <FaCopy />
           </button>
@@ -191,6 +228,11 @@ export const ChatBubble = ({
       </div>
       {role === "assistant" && (
         <div className="message-actions">
+          {onRegenerate && (
+            <button onClick={onRegenerate} title="Regenerate">
+              <FaRedo />
+            </button>
+          )}
           <button onClick={onCopy} title="Copy">
             <FaCopy />
           </button>
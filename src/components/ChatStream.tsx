@@ -14,6 +14,10 @@ type ChatStreamProps = {
   onCopy: (content: IChatCompletionMessage["content"]) => void;
   onDelete: (id: number) => void;
   onDeleteTool: (llm_interaction_id: number, tool_call_id: string) => void;
+  onEdit: (id: number) => void;
+  branches: Record<number, number[]>;
+  onSwitchBranch: (id: number) => void;
+  onRegenerate: () => void;
   chatContainerRef: React.RefObject<HTMLDivElement>;
   attachments: Attachment[];
   setAttachments: React.Dispatch<React.SetStateAction<Attachment[]>>;
@@ -34,6 +38,10 @@ export const ChatStream = ({
   onCopy,
   onDelete,
   onDeleteTool,
+  onEdit,
+  branches,
+  onSwitchBranch,
+  onRegenerate,
   chatContainerRef,
   attachments,
   setAttachments,
@@ -51,7 +59,7 @@ export const ChatStream = ({
     <div id="chat-container" ref={chatContainerRef}>
       {messages
         .sort((a, b) => a.id - b.id)
-        .map((m) => (
+        .map((m, i, all) => (
           <ChatBubble
             key={m.tool_call_id || m.id}
             {...m}
@@ -60,6 +68,12 @@ export const ChatStream = ({
             onDeleteTool={(llm_interaction_id, tool_call_id) =>
               onDeleteTool(llm_interaction_id, tool_call_id)
             }
+            onEdit={() => onEdit(m.id)}
+            siblings={m.tool_call_id ? undefined : branches[m.id]}
+            onSwitchBranch={onSwitchBranch}
+            onRegenerate={
+              !isTyping && i === all.length - 1 ? onRegenerate : undefined
+            }
           />
         ))}
       {isTyping && (
@@ -1,5 +1,7 @@
+import { useEffect, useState } from "react";
 import "./components.css";
-import { ISettings } from "../types";
+import { askCacheClear, askCacheList, usageReport } from "../commands";
+import { IBudget, ILedger, ISettings } from "../types";
 
 export const SettingsModal = ({
   settings,
@@ -10,6 +12,62 @@ export const SettingsModal = ({
   onClose: Function;
   onSave: (settings: Partial<ISettings>) => void;
 }) => {
+  const [cachedAnswers, setCachedAnswers] = useState<number | null>(null);
+  const [ledger, setLedger] = useState<ILedger | null>(null);
+
+  useEffect(() => {
+    askCacheList()
+      .then((entries) => setCachedAnswers(entries.length))
+      .catch(() => setCachedAnswers(null));
+    usageReport()
+      .then(setLedger)
+      .catch(() => setLedger(null));
+  }, []);
+
+  const optionalNumber = (value: string) =>
+    value === "" ? null : Number(value);
+
+  const budgetInputs = (key: "requestBudget" | "dailyBudget") => {
+    const budget = settings[key];
+    const update = (change: Partial<IBudget>) =>
+      onSave({ [key]: { ...budget, ...change } });
+    return (
+      <div style={{ display: "flex", gap: "5px" }}>
+        <input
+          type="number"
+          min={0}
+          placeholder="Calls"
+          title="Max LLM calls"
+          value={budget.maxCalls ?? ""}
+          onChange={(e) => update({ maxCalls: optionalNumber(e.target.value) })}
+        />
+        <input
+          type="number"
+          min={0}
+          placeholder="Tokens"
+          title="Max tokens"
+          value={budget.maxTokens ?? ""}
+          onChange={(e) =>
+            update({ maxTokens: optionalNumber(e.target.value) })
+          }
+        />
+        <input
+          type="number"
+          min={0}
+          step={0.01}
+          placeholder="USD"
+          title="Max cost in USD"
+          value={budget.maxCost ?? ""}
+          onChange={(e) => update({ maxCost: optionalNumber(e.target.value) })}
+        />
+      </div>
+    );
+  };
+
+  const handleClearCache = async () => {
+    await askCacheClear();
+    setCachedAnswers(0);
+  };
 
   const handleModalClick = (e: React.MouseEvent) => {
     e.stopPropagation();
@@ -27,7 +85,33 @@ export const SettingsModal = ({
         </div>
         <h2>Settings</h2>
         <div style={{ display: "flex", flexDirection: "column" }}>
-          <label htmlFor="apiKey">OpenRouter API Key:</label>
+          <label htmlFor="backend">Backend:</label>
+          <select
+            value={settings.backend}
+            onChange={(e) =>
+              onSave({ backend: e.target.value as ISettings["backend"] })
+            }
+          >
+            <option value="openrouter">OpenRouter</option>
+            <option value="openai_compatible">OpenAI compatible</option>
+            <option value="ollama">Ollama / llama.cpp</option>
+          </select>
+          {settings.backend !== "openrouter" && (
+            <>
+              <label htmlFor="baseUrl">Base URL:</label>
+              <input
+                type="text"
+                placeholder={
+                  settings.backend === "ollama"
+                    ? "http://localhost:11434/v1"
+                    : "e.g., https://llm.example.com/v1"
+                }
+                value={settings.baseUrl}
+                onChange={(e) => onSave({ baseUrl: e.target.value })}
+              />
+            </>
+          )}
+          <label htmlFor="apiKey">API Key:</label>
           <input
             type="password"
             value={settings.apiKey}
@@ -55,8 +139,8 @@ export const SettingsModal = ({
               marginBottom: "10px",
             }}
           >
-            Optional: Set this to enable DOCX/PPTX support. Leave empty if
-            LibreOffice is not installed.
+            Optional: Used as a fallback for DOCX/PPTX files the built-in
+            reader can't open. Leave empty if LibreOffice is not installed.
           </small>
           <label htmlFor="providerOrder">Provider Order:</label>
           <input
@@ -65,6 +149,189 @@ export const SettingsModal = ({
             value={settings.providerOrder}
             onChange={(e) => onSave({ providerOrder: e.target.value })}
           />
+          <label htmlFor="mapModelName">File Query Model (ask_files):</label>
+          <input
+            type="text"
+            value={settings.mapModelName}
+            onChange={(e) => onSave({ mapModelName: e.target.value })}
+          />
+          <label htmlFor="mapAccurateModelName">
+            File Query Model, accurate tier:
+          </label>
+          <input
+            type="text"
+            value={settings.mapAccurateModelName}
+            onChange={(e) => onSave({ mapAccurateModelName: e.target.value })}
+          />
+          <label htmlFor="mapProviderOrder">File Query Provider Order:</label>
+          <input
+            type="text"
+            placeholder="Same as Provider Order"
+            value={settings.mapProviderOrder}
+            onChange={(e) => onSave({ mapProviderOrder: e.target.value })}
+          />
+          <label htmlFor="mapTemperature">File Query Temperature:</label>
+          <input
+            type="number"
+            min={0}
+            max={2}
+            step={0.1}
+            placeholder="Model default"
+            value={settings.mapTemperature ?? ""}
+            onChange={(e) =>
+              onSave({
+                mapTemperature:
+                  e.target.value === "" ? null : Number(e.target.value),
+              })
+            }
+          />
+          <label htmlFor="mapPrompt">File Query Prompt:</label>
+          <textarea
+            style={{ marginBottom: 0 }}
+            rows={3}
+            placeholder="You are a helpful assistant that answers questions about files. Your answer must be grounded."
+            value={settings.mapPrompt}
+            onChange={(e) => onSave({ mapPrompt: e.target.value })}
+          />
+          <small
+            style={{
+              color: "#666",
+              display: "block",
+              marginTop: "5px",
+              marginBottom: "10px",
+            }}
+          >
+            Optional: {"{file}"} and {"{query}"} are replaced by the file name
+            and the question. Leave empty for the built-in prompt.
+          </small>
+          <label>Budget per Request:</label>
+          {budgetInputs("requestBudget")}
+          <label>Budget per Day:</label>
+          {budgetInputs("dailyBudget")}
+          <label htmlFor="maxIterations">Max Agent Steps per Request:</label>
+          <input
+            type="number"
+            min={1}
+            placeholder="Unlimited"
+            value={settings.maxIterations ?? ""}
+            onChange={(e) =>
+              onSave({ maxIterations: optionalNumber(e.target.value) })
+            }
+          />
+          <small
+            style={{
+              color: "#666",
+              display: "block",
+              marginTop: "5px",
+              marginBottom: "10px",
+            }}
+          >
+            Empty fields are unlimited. Requests stop with an error once a
+            limit is reached.
+          </small>
+          <label htmlFor="contextBudget">Context Budget (tokens):</label>
+          <input
+            type="number"
+            min={1000}
+            step={1000}
+            placeholder="Send the whole conversation"
+            value={settings.contextBudget ?? ""}
+            onChange={(e) =>
+              onSave({ contextBudget: optionalNumber(e.target.value) })
+            }
+          />
+          <label htmlFor="pdfAsText">
+            <input
+              type="checkbox"
+              checked={settings.pdfAsText}
+              onChange={(e) => onSave({ pdfAsText: e.target.checked })}
+            />
+            Send PDFs as text (for models without PDF support)
+          </label>
+          <label htmlFor="embeddingBackend">Embeddings:</label>
+          <select
+            value={settings.embeddingBackend}
+            onChange={(e) =>
+              onSave({
+                embeddingBackend: e.target
+                  .value as ISettings["embeddingBackend"],
+              })
+            }
+          >
+            <option value="local">Local (offline, keyword based)</option>
+            <option value="api">Backend embeddings endpoint</option>
+          </select>
+          {settings.embeddingBackend === "api" && (
+            <>
+              <label htmlFor="embeddingModel">Embedding Model:</label>
+              <input
+                type="text"
+                value={settings.embeddingModel}
+                onChange={(e) => onSave({ embeddingModel: e.target.value })}
+              />
+            </>
+          )}
+          <label htmlFor="askCache">
+            Cached file answers: {cachedAnswers ?? "unavailable"}
+          </label>
+          <button
+            disabled={!cachedAnswers}
+            onClick={handleClearCache}
+            style={{ marginBottom: "10px" }}
+          >
+            Clear answer cache
+          </button>
+          {ledger && Object.keys(ledger.months).length > 0 && (
+            <>
+              <label>Spend in this folder:</label>
+              <ul style={{ marginTop: 0 }}>
+                {Object.entries(ledger.months)
+                  .reverse()
+                  .map(([month, u]) => (
+                    <li key={month}>
+                      {month}: ${u.cost.toFixed(2)} ({u.calls} calls,{" "}
+                      {u.total_tokens} tokens)
+                    </li>
+                  ))}
+              </ul>
+            </>
+          )}
+          <label htmlFor="serverEnabled">
+            <input
+              type="checkbox"
+              checked={settings.serverEnabled}
+              onChange={(e) => onSave({ serverEnabled: e.target.checked })}
+            />
+            Local API server
+          </label>
+          {settings.serverEnabled && (
+            <>
+              <label htmlFor="serverBind">API Bind Address:</label>
+              <input
+                type="text"
+                placeholder="127.0.0.1:8765"
+                value={settings.serverBind}
+                onChange={(e) => onSave({ serverBind: e.target.value })}
+              />
+              <label htmlFor="serverToken">API Token:</label>
+              <input
+                type="password"
+                style={{ marginBottom: 0 }}
+                value={settings.serverToken}
+                onChange={(e) => onSave({ serverToken: e.target.value })}
+              />
+              <small
+                style={{
+                  color: "#666",
+                  display: "block",
+                  marginTop: "5px",
+                  marginBottom: "10px",
+                }}
+              >
+                Required. Server changes take effect after a restart.
+              </small>
+            </>
+          )}
         </div>
       </div>
     </div>
@@ -1,8 +1,18 @@
-import { FaCog, FaTrash, FaFolderOpen, FaBars } from "react-icons/fa";
-import { ISettings } from "../types";
+import {
+  FaCog,
+  FaTrash,
+  FaFolderOpen,
+  FaBars,
+  FaEdit,
+  FaTimes,
+  FaFileExport,
+  FaFileImport,
+} from "react-icons/fa";
+import { IConversationSummary, ISettings, IUsage } from "../types";
 
 type TopBarProps = {
   appVersion: string;
+  usage: IUsage | null;
   settings: ISettings;
   handleSettingsChange: (newSettings: Partial<ISettings>) => void;
   onClearHistory: () => void;
@@ -10,10 +20,18 @@ type TopBarProps = {
   onToggleFlap: () => void;
   onSelectFolder: () => void;
   rootDirInputRef: React.RefObject<HTMLInputElement>;
+  conversations: IConversationSummary[];
+  conversationId: string | null;
+  onOpenConversation: (id: string) => void;
+  onRenameConversation: (id: string) => void;
+  onExportConversation: () => void;
+  onImportTranscript: () => void;
+  onDeleteConversation: (id: string) => void;
 };
 
 export const TopBar = ({
   appVersion,
+  usage,
   settings,
   handleSettingsChange,
   onClearHistory,
@@ -21,6 +39,13 @@ export const TopBar = ({
   onToggleFlap,
   onSelectFolder,
   rootDirInputRef,
+  conversations,
+  conversationId,
+  onOpenConversation,
+  onRenameConversation,
+  onExportConversation,
+  onImportTranscript,
+  onDeleteConversation,
 }: TopBarProps) => (
   <header>
     <button id="header-button" title="Toggle File Explorer" onClick={onToggleFlap}>
@@ -43,7 +68,61 @@ export const TopBar = ({
         <FaFolderOpen />
       </button>
     </div>
+    <div id="conversation-container">
+      <select
+        id="conversation-select"
+        value={conversationId ?? ""}
+        onChange={(e) => e.target.value && onOpenConversation(e.target.value)}
+      >
+        <option value="">New conversation</option>
+        {conversations.map((c) => (
+          <option key={c.id} value={c.id}>
+            {c.title}
+          </option>
+        ))}
+      </select>
+      <button
+        id="header-button"
+        title="Import transcript"
+        onClick={onImportTranscript}
+      >
+        <FaFileImport />
+      </button>
+      {conversationId && (
+        <>
+          <button
+            id="header-button"
+            title="Rename conversation"
+            onClick={() => onRenameConversation(conversationId)}
+          >
+            <FaEdit />
+          </button>
+          <button
+            id="header-button"
+            title="Export conversation"
+            onClick={onExportConversation}
+          >
+            <FaFileExport />
+          </button>
+          <button
+            id="header-button"
+            title="Delete conversation"
+            onClick={() => onDeleteConversation(conversationId)}
+          >
+            <FaTimes />
+          </button>
+        </>
+      )}
+    </div>
     <div style={{ paddingLeft: "10px" }}>
+      {usage && (
+        <span
+          title={`${usage.calls} calls, ${usage.prompt_tokens} prompt and ${usage.completion_tokens} completion tokens`}
+          style={{ color: "white", marginRight: "10px" }}
+        >
+          ${usage.cost.toFixed(4)}
+        </span>
+      )}
       <button id="header-button" title="Clear History" onClick={onClearHistory}>
         <FaTrash />
       </button>
@@ -4,6 +4,29 @@ export interface ISettings {
   rootDir: string;
   sofficePath: string;
   providerOrder: string;
+  backend: "openrouter" | "openai_compatible" | "ollama";
+  baseUrl: string;
+  pdfAsText: boolean;
+  serverEnabled: boolean;
+  serverBind: string;
+  serverToken: string;
+  embeddingBackend: "local" | "api";
+  embeddingModel: string;
+  mapModelName: string;
+  mapAccurateModelName: string;
+  mapProviderOrder: string;
+  mapTemperature: number | null;
+  mapPrompt: string;
+  requestBudget: IBudget;
+  dailyBudget: IBudget;
+  maxIterations: number | null;
+  contextBudget: number | null;
+}
+
+export interface IBudget {
+  maxCalls: number | null;
+  maxTokens: number | null;
+  maxCost: number | null;
 }
 
 export type TextContent = {
@@ -48,6 +71,8 @@ export interface IChatCompletionMessage {
   toolName?: string;
   toolArgs?: string;
   toolResult?: string;
+  isStreaming?: boolean;
+  streamIndex?: number;
 }
 
 export interface IChatCompletionOptions {
@@ -71,7 +96,61 @@ export type IChatCompletionUpdate =
       id: number;
       tool_call_id: string;
       tool_result: string;
-    };
+    }
+  | { type: "MessageDelta"; id: number; delta: string }
+  | {
+      type: "ToolCallDelta";
+      id: number;
+      index: number;
+      tool_call_id: string | null;
+      tool_name: string | null;
+      args_delta: string;
+    }
+  | { type: "Usage"; id: number; usage: IUsage; conversation: IUsage }
+  | { type: "Branches"; id: number; siblings: number[] };
+
+export interface IUsage {
+  calls: number;
+  prompt_tokens: number;
+  completion_tokens: number;
+  total_tokens: number;
+  cost: number;
+}
+
+export interface ILedger {
+  root_dir: string;
+  months: Record<string, IUsage>;
+}
+
+export interface IConversationSummary {
+  id: string;
+  title: string;
+  created_at: string;
+  updated_at: string;
+  message_count: number;
+}
+
+export interface IContentMatch {
+  file: string;
+  score: number;
+  snippet: string;
+}
+
+export interface IAskCacheEntry {
+  file: string;
+  query: string;
+  model: string;
+  prompt_version: number;
+  pages: string | null;
+  created_at: string;
+}
+
+export interface ISemanticMatch {
+  file: string;
+  location: string;
+  score: number;
+  text: string;
+}
 
 export type ISearchResultUpdate =
   | { "Add": string }